@@ -1,17 +1,31 @@
 use crate::{
+    editor::Editor,
     window::Window,
-    buffer::{Buffer, EditMode},
-    utils::{BufCharIdx, BufCol, Movement, Selection},
+    buffer::{Buffer, Cursor, EditMode},
+    comment, indent, pairs,
+    fold::{self, FoldCommand},
+    message::Error,
+    picker::PickerKind,
+    prompt::PromptKind,
+    search::{Direction, Search},
+    utils::{BlockSplit, BufCharIdx, DisplayCol, BufRange, Movement, Selection},
 };
+use regex::Regex;
 
+#[derive(Debug)]
 pub struct Command {
     pub buffer_action: BufferAction,
+    pub window_action: WindowAction,
     pub render_action: RenderAction,
 }
 
 impl Command {
     pub fn new(buffer_action: BufferAction, render_action: RenderAction) -> Self {
-        Self { buffer_action, render_action }
+        Self { buffer_action, window_action: WindowAction::Nothing, render_action }
+    }
+
+    pub fn window(window_action: WindowAction) -> Self {
+        Self { buffer_action: BufferAction::Nothing, window_action, render_action: RenderAction::DrawAll }
     }
 }
 
@@ -27,19 +41,89 @@ pub trait Undoable : Action {
 }
 
 #[allow(unused)]
+#[derive(Clone, Debug)]
 pub enum BufferAction {
     Undo,
     Redo,
-    MoveTo(BufCharIdx, BufCol),
+    /// Moves to a character, snapping to a display column when moving between lines
+    MoveTo(BufCharIdx, DisplayCol),
     Move(Movement),
     Delete(Selection),
     InsertAt(BufCharIdx, String),
     Insert(String),
+    /// Inserts a character typed in insert mode
+    Type(char),
+    /// Deletes the character before the cursor in insert mode
+    Backspace,
+    /// Inserts a tab, or spaces if `softtabstop` is set
+    Tab,
+    /// Breaks the line at the cursor, keeping the indentation
+    NewLine,
+    /// Adds an indented line below the cursor line, or above it if `above` is set
+    OpenLine { above: bool },
+    /// Indents the cursor line according to the syntax tree
+    IndentLine,
+    /// Indents the non-blank lines of the selection according to the syntax tree
+    Reindent(Selection),
+    /// Comments or uncomments the lines of the selection, or the selection itself with a block comment
+    ToggleComment { selection: Selection, block: bool },
     Yank(Selection),
     SetMode(EditMode),
+    /// Selects the word under the cursor, or adds a cursor selecting the next match of the selection
+    SelectNextMatch,
+    /// Replaces the visual block with a cursor or selection on every line
+    SplitBlock(BlockSplit),
+    /// Splits every selection into the parts that are separated by matches of the regex
+    SplitSelections(Regex),
+    CollapseCursors,
+    /// Stores the position of the cursor under a name
+    SetMark(char),
+    /// Moves to a mark, or to the first character of its line if `line` is set
+    JumpToMark { name: char, line: bool },
+    /// Moves to an older position in the change list, or a newer one if `older` isn't set
+    JumpToChange { older: bool },
+    /// Opens, closes, creates or deletes folds at the cursor
+    Fold(FoldCommand),
+    /// Applies multiple actions in order as a single step
+    Batch(Vec<BufferAction>),
     Nothing,
 }
 
+impl BufferAction {
+    /// Whether the action should be applied to each cursor separately
+    pub fn is_per_cursor(&self) -> bool {
+        match self {
+            BufferAction::Delete(Selection::Bounds(_, _)) => false,
+            BufferAction::Move(_) | BufferAction::Delete(_) | BufferAction::Insert(_) => true,
+            BufferAction::Type(_) | BufferAction::NewLine | BufferAction::OpenLine { .. } => true,
+            BufferAction::IndentLine | BufferAction::Backspace | BufferAction::Tab => true,
+            // Every line of a visual block is wrapped in a comment of its own
+            BufferAction::ToggleComment { selection: Selection::Selected, block: true } => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the action is a jump, which adds the position it moves away from to the jump list
+    pub fn is_jump(&self) -> bool {
+        matches!(
+            self,
+            BufferAction::Move(Movement::Top | Movement::Bottom | Movement::MatchingBracket) | BufferAction::JumpToMark { .. }
+        )
+    }
+
+    /// Whether the action changes the text of the buffer
+    pub fn is_edit(&self) -> bool {
+        match self {
+            BufferAction::Delete(_) | BufferAction::Insert(_) | BufferAction::InsertAt(_, _) => true,
+            BufferAction::Type(_) | BufferAction::NewLine | BufferAction::OpenLine { .. } => true,
+            BufferAction::IndentLine | BufferAction::Reindent(_) | BufferAction::Backspace | BufferAction::Tab => true,
+            BufferAction::ToggleComment { .. } => true,
+            BufferAction::Batch(actions) => actions.iter().any(BufferAction::is_edit),
+            _ => false,
+        }
+    }
+}
+
 impl Action for BufferAction {
     type Target = Buffer;
     type Error = &'static str;
@@ -49,8 +133,9 @@ impl Action for BufferAction {
             BufferAction::Undo => {
                 match buf.undo.pop() {
                     Some(action) => {
-                        buf.redo.push(action.inverse(buf));
-                        action.apply(buf)
+                        let inverse = buf.record(action)?;
+                        buf.redo.push(inverse);
+                        Ok(())
                     }
                     None => Err("Nothing to undo"),
                 }
@@ -58,8 +143,9 @@ impl Action for BufferAction {
             BufferAction::Redo => {
                 match buf.redo.pop() {
                     Some(action) => {
-                        buf.undo.push(action.inverse(buf));
-                        action.apply(buf)
+                        let inverse = buf.record(action)?;
+                        buf.undo.push(inverse);
+                        Ok(())
                     }
                     None => Err("Nothing to redo"),
                 }
@@ -70,6 +156,11 @@ impl Action for BufferAction {
                 Ok(())
             }
             BufferAction::Move(movement) => {
+                match movement {
+                    Movement::MatchingBracket => buf.update_syntax(),
+                    Movement::NextFold | Movement::PrevFold => fold::update(buf),
+                    _ => (),
+                }
                 buf.idx = movement.dest(buf);
                 if movement.is_horizontal() {
                     buf.save_col();
@@ -82,21 +173,145 @@ impl Action for BufferAction {
             }
             BufferAction::InsertAt(idx, text) => {
                 buf.insert(idx, &text);
-                buf.idx = buf.idx + text.chars().count().into();
                 Ok(())
             }
             BufferAction::Insert(text) => {
                 buf.insert(buf.idx, &text);
-                buf.idx = buf.idx + text.chars().count().into();
                 Ok(())
             }
+            BufferAction::Type(c) => {
+                buf.update_syntax();
+                pairs::type_char(buf, c).unwrap_or_else(|| indent::type_char(buf, c)).apply(buf)
+            }
+            BufferAction::Backspace => pairs::backspace(buf).apply(buf),
+            BufferAction::Tab => indent::tab(buf).apply(buf),
+            BufferAction::NewLine => indent::new_line(buf).apply(buf),
+            BufferAction::OpenLine { above } => indent::open_line(buf, above).apply(buf),
+            BufferAction::IndentLine => {
+                buf.update_syntax();
+                indent::indent_line(buf).apply(buf)
+            }
+            BufferAction::Reindent(selection) => {
+                buf.update_syntax();
+                indent::reindent(buf, selection).apply(buf)
+            }
+            BufferAction::ToggleComment { selection, block } => comment::toggle(buf, selection, block)?.apply(buf),
             BufferAction::Yank(selection) => {
-                cli_clipboard::set_contents(buf.slice(selection.bounds(buf)).to_string())
-                    .expect("Error setting system clipboard");
-                Ok(())
+                let range = selection.bounds(buf);
+                buf.set_mark('[', range.start);
+                buf.set_mark(']', usize::max(*range.start, range.end.saturating_sub(1)).into());
+                cli_clipboard::set_contents(buf.slice(range).to_string()).map_err(|_| "Could not set the system clipboard")
             }
             BufferAction::SetMode(mode) => {
-                buf.mode = mode;
+                buf.set_mode(mode);
+                Ok(())
+            }
+            BufferAction::SetMark(name) => match name {
+                'a'..='z' | '[' | ']' | '<' | '>' => {
+                    buf.set_mark(name, buf.idx);
+                    Ok(())
+                }
+                _ => Err("Invalid mark name"),
+            },
+            BufferAction::JumpToMark { name, line } => {
+                buf.idx = buf.mark(name).ok_or("Mark not set")?;
+                if line {
+                    buf.idx = Movement::FirstChar.dest(buf);
+                }
+                buf.save_col();
+                Ok(())
+            }
+            BufferAction::JumpToChange { older } => {
+                buf.idx = buf.next_change(older)?;
+                buf.save_col();
+                Ok(())
+            }
+            BufferAction::SelectNextMatch => {
+                let range = match buf.selection() {
+                    Some(range) => range,
+                    None => {
+                        let word = buf.word_at(buf.idx).ok_or("No word under cursor")?;
+                        buf.set_mode(EditMode::Visual);
+                        buf.anchor = Some(word.start);
+                        buf.idx = word.end - 1.into();
+                        buf.save_col();
+                        return Ok(());
+                    }
+                };
+                let text = buf.slice(range).to_string();
+                let pattern = match buf.word_at(range.start) {
+                    Some(word) if word == range => format!(r"\b{}\b", regex::escape(&text)),
+                    _ => regex::escape(&text),
+                };
+                let regex = Regex::new(&pattern).map_err(|_| "Unable to search for selection")?;
+                let found = buf
+                    .find_next(&regex, range.end)
+                    .or_else(|| buf.find_next(&regex, 0.into()))
+                    .ok_or("Pattern not found")?;
+                if buf.all_cursors().any(|c| buf.cursor_selection(&c) == Some(found)) {
+                    return Err("No more matches");
+                }
+                let idx = found.end - 1.into();
+                buf.add_cursor(Cursor { idx, saved_col: buf.display_col(idx), anchor: Some(found.start) });
+                Ok(())
+            }
+            BufferAction::SplitBlock(split) => {
+                let anchor = buf.char_to_pos(buf.anchor.ok_or("No block selected")?);
+                let cursor = buf.cursor();
+                let left = usize::min(*anchor.x, *cursor.x);
+                let right = usize::max(*anchor.x, *cursor.x) + 1;
+                let rows = usize::min(*anchor.y, *cursor.y)..=usize::max(*anchor.y, *cursor.y);
+                let cursors = rows
+                    .filter_map(|row| {
+                        let start = buf.row_to_char(row.into());
+                        let len = buf.line_str(row.into()).chars().count();
+                        // Lines that end before the block are skipped, except when appending
+                        if len < left || (len == left && !matches!(split, BlockSplit::Append)) {
+                            return None;
+                        }
+                        let cursor = |col: usize, anchor: Option<usize>| Cursor {
+                            idx: start + col.into(),
+                            saved_col: buf.display_col(start + col.into()),
+                            anchor: anchor.map(|a| start + a.into()),
+                        };
+                        Some(match split {
+                            BlockSplit::Insert => cursor(left, None),
+                            BlockSplit::Append => cursor(usize::min(right, len), None),
+                            BlockSplit::Select => cursor(usize::min(right, len) - 1, Some(left)),
+                        })
+                    })
+                    .collect();
+                buf.set_cursors(cursors);
+                Ok(())
+            }
+            BufferAction::SplitSelections(regex) => {
+                let mut cursors = Vec::new();
+                for range in buf.all_cursors().filter_map(|c| buf.cursor_selection(&c)) {
+                    let mut start = range.start;
+                    let end = BufRange::new(range.end, range.end);
+                    for m in buf.find_all(&regex, range).into_iter().chain(std::iter::once(end)) {
+                        if *m.start > *start {
+                            let idx = m.start - 1.into();
+                            cursors.push(Cursor { idx, saved_col: buf.display_col(idx), anchor: Some(start) });
+                        }
+                        start = m.end;
+                    }
+                }
+                if cursors.is_empty() {
+                    return Err("Nothing selected to split");
+                }
+                buf.set_cursors(cursors);
+                Ok(())
+            }
+            BufferAction::CollapseCursors => {
+                buf.collapse_cursors();
+                Ok(())
+            }
+            BufferAction::Fold(command) => fold::apply(buf, command),
+            BufferAction::Batch(actions) => {
+                for action in actions {
+                    action.apply(buf)?;
+                }
                 Ok(())
             }
             BufferAction::Nothing => Ok(())
@@ -111,7 +326,7 @@ impl Undoable for BufferAction {
             BufferAction::Redo => BufferAction::Nothing,
             BufferAction::MoveTo(_, _) | BufferAction::Move(_) => BufferAction::MoveTo(buf.idx, buf.saved_col),
             BufferAction::Delete(selection) => {
-                let bounds = selection.bounds(&buf);
+                let bounds = selection.bounds(buf);
                 BufferAction::InsertAt(bounds.start, buf.slice(bounds).to_string())
             }
             BufferAction::InsertAt(idx, text) => {
@@ -123,11 +338,31 @@ impl Undoable for BufferAction {
             )),
             BufferAction::Yank(_) => BufferAction::Nothing,
             BufferAction::SetMode(_) => BufferAction::SetMode(buf.mode),
+            BufferAction::SelectNextMatch
+            | BufferAction::SplitBlock(_)
+            | BufferAction::SplitSelections(_)
+            | BufferAction::CollapseCursors
+            | BufferAction::Fold(_)
+            | BufferAction::SetMark(_)
+            | BufferAction::JumpToMark { .. }
+            | BufferAction::JumpToChange { .. } => BufferAction::Nothing,
+            // Inverses of batches and typing are built while applying them, see `Buffer::record`
+            BufferAction::Batch(_)
+            | BufferAction::Type(_)
+            | BufferAction::Backspace
+            | BufferAction::Tab
+            | BufferAction::NewLine
+            | BufferAction::OpenLine { .. }
+            | BufferAction::IndentLine
+            | BufferAction::Reindent(_)
+            | BufferAction::ToggleComment { .. } => BufferAction::Nothing,
             BufferAction::Nothing => BufferAction::Nothing,
         }
     }
 }
 
+#[allow(unused)]
+#[derive(Debug)]
 pub enum RenderAction {
     DrawAll,
     DrawFromCursor,
@@ -151,3 +386,154 @@ impl Action for RenderAction {
         }
     }
 }
+
+/// An action that concerns more than a single window
+#[derive(Debug)]
+pub enum EditorAction {
+    /// Searches all files in the project, the string is the pattern as typed
+    Grep(Regex, String),
+    OpenQuickfix,
+    CloseQuickfix,
+    /// Jumps to the entry of the quickfix list at the index, or the current entry
+    QuickfixGoto(Option<usize>),
+    /// Jumps to the next entry of the quickfix list, or the previous one if `reverse` is set
+    QuickfixNext { reverse: bool },
+    /// Marks the cursor position in the file, an uppercase mark exists in only one file at a time
+    SetGlobalMark(char),
+    /// Opens the file of an uppercase mark and moves to it, or to the first character of its line if `line` is set
+    JumpToGlobalMark { name: char, line: bool },
+    /// Shows the marks of the current buffer and the uppercase marks of all buffers
+    ListMarks,
+    /// Shows the messages reported since the editor started
+    ListMessages,
+    /// Shows the log file in a read-only buffer in the last selected file window
+    OpenLog,
+    /// Opens a popup that narrows down a list by fuzzy matching, or the list of pickers if none is given
+    OpenPicker(Option<PickerKind>),
+    /// Shows what replacing the matches in the lines of the quickfix list would change
+    PreviewReplace { regex: Regex, replacement: String, global: bool },
+    /// Applies the replacements in the text of the preview buffer
+    ApplyPreview(String),
+    /// Moves to an older position in the jump list, or a newer one if `older` isn't set
+    Jump { older: bool },
+    WriteAll,
+    CloseWindow,
+}
+
+impl Action for EditorAction {
+    type Target = Editor;
+    type Error = crossterm::ErrorKind;
+
+    fn apply(self, editor: &mut Editor) -> Result<(), Self::Error> {
+        match self {
+            EditorAction::Grep(regex, pattern) => {
+                editor.grep(regex, pattern);
+                Ok(())
+            }
+            EditorAction::OpenQuickfix => {
+                editor.open_quickfix();
+                Ok(())
+            }
+            EditorAction::CloseQuickfix => {
+                if let Some(i) = editor.quickfix_window() {
+                    editor.close_window(i);
+                }
+                Ok(())
+            }
+            EditorAction::QuickfixGoto(idx) => {
+                let idx = idx.unwrap_or_else(|| editor.quickfix_idx());
+                editor.jump_to_entry(idx)
+            }
+            EditorAction::QuickfixNext { reverse } => editor.jump_to_next_entry(reverse),
+            EditorAction::PreviewReplace { regex, replacement, global } => {
+                editor.preview_replace(&regex, &replacement, global);
+                Ok(())
+            }
+            EditorAction::ApplyPreview(text) => {
+                editor.apply_preview(&text);
+                Ok(())
+            }
+            EditorAction::SetGlobalMark(name) => {
+                editor.set_global_mark(name);
+                Ok(())
+            }
+            EditorAction::JumpToGlobalMark { name, line } => {
+                editor.jump_to_global_mark(name, line);
+                Ok(())
+            }
+            EditorAction::ListMarks => {
+                editor.list_marks();
+                Ok(())
+            }
+            EditorAction::ListMessages => {
+                editor.list_messages();
+                Ok(())
+            }
+            EditorAction::OpenLog => {
+                editor.open_log();
+                Ok(())
+            }
+            EditorAction::OpenPicker(kind) => {
+                editor.open_picker(kind);
+                Ok(())
+            }
+            EditorAction::Jump { older } => {
+                editor.jump(older);
+                Ok(())
+            }
+            EditorAction::WriteAll => {
+                editor.write_all();
+                Ok(())
+            }
+            EditorAction::CloseWindow => {
+                editor.close_window(editor.selected_window());
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WindowAction {
+    OpenPrompt(PromptKind),
+    /// Repeats the last search, in the opposite direction if `reverse` is set
+    SearchNext { reverse: bool },
+    /// Searches for the word under the cursor
+    SearchWord(Direction),
+    /// Passes an action on to the editor
+    Editor(EditorAction),
+    Nothing,
+}
+
+impl Action for WindowAction {
+    type Target = Window;
+    type Error = crossterm::ErrorKind;
+
+    fn apply(self, window: &mut Self::Target) -> Result<(), Self::Error> {
+        match self {
+            WindowAction::OpenPrompt(kind) => {
+                window.open_prompt(kind);
+                Ok(())
+            }
+            WindowAction::SearchNext { reverse } => {
+                window.search_next(reverse);
+                Ok(())
+            }
+            WindowAction::SearchWord(direction) => {
+                match window.buf.word_at(window.buf.idx) {
+                    Some(word) => {
+                        let word = window.buf.slice(word).to_string();
+                        window.start_search(Search::word(&word, direction));
+                    }
+                    None => window.report(Error::Action("No word under cursor")),
+                }
+                Ok(())
+            }
+            WindowAction::Editor(action) => {
+                window.editor_action = Some(action);
+                Ok(())
+            }
+            WindowAction::Nothing => Ok(()),
+        }
+    }
+}
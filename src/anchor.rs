@@ -0,0 +1,277 @@
+use crate::utils::{BufCharIdx, BufRange};
+use std::collections::HashMap;
+
+/// Which side of text inserted exactly at an anchor the anchor ends up on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gravity {
+    /// Stays before the inserted text
+    Left,
+    /// Moves after the inserted text, staying on the character it was on
+    Right,
+}
+
+/// Identifies an anchor registered in `Anchors`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AnchorId(usize);
+
+/// A position or range in a buffer that moves along with the text around it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Anchor {
+    pub range: BufRange,
+    pub start_gravity: Gravity,
+    pub end_gravity: Gravity,
+    /// Whether the anchor is deleted once the text it covers is removed. An empty anchor is deleted once the
+    /// character after it is removed.
+    pub delete_on_collapse: bool,
+}
+
+impl Anchor {
+    pub fn point(idx: BufCharIdx, gravity: Gravity) -> Self {
+        Self::range(BufRange::new(idx, idx), gravity, gravity)
+    }
+
+    /// A range with `Gravity::Left` at the start and `Gravity::Right` at the end grows with text inserted at its
+    /// edges, the reverse keeps such text outside of it
+    pub fn range(range: BufRange, start_gravity: Gravity, end_gravity: Gravity) -> Self {
+        Self { range, start_gravity, end_gravity, delete_on_collapse: false }
+    }
+
+    pub fn delete_on_collapse(self) -> Self {
+        Self { delete_on_collapse: true, ..self }
+    }
+
+    /// Moves the anchor for `len` characters inserted at `at`
+    fn inserted(&mut self, at: BufCharIdx, len: usize) {
+        let start = shift_insert(self.range.start, at, len, self.start_gravity);
+        let end = shift_insert(self.range.end, at, len, self.end_gravity);
+        // An empty range whose edges pull apart moves after the inserted text
+        self.range = BufRange { start, end: BufCharIdx(usize::max(*start, *end)) };
+    }
+
+    /// Moves the anchor for the text in `removed` being removed, returns whether it collapsed
+    fn removed(&mut self, removed: BufRange) -> bool {
+        let collapsed = if self.range.start == self.range.end {
+            *removed.start <= *self.range.start && *self.range.start < *removed.end
+        } else {
+            *removed.start <= *self.range.start && *self.range.end <= *removed.end
+        };
+        self.range = BufRange {
+            start: shift_remove(self.range.start, removed),
+            end: shift_remove(self.range.end, removed),
+        };
+        collapsed
+    }
+}
+
+/// Returns where `idx` ends up after `len` characters are inserted at `at`
+pub fn shift_insert(idx: BufCharIdx, at: BufCharIdx, len: usize, gravity: Gravity) -> BufCharIdx {
+    if *idx > *at || (idx == at && gravity == Gravity::Right) {
+        idx + len.into()
+    } else {
+        idx
+    }
+}
+
+/// Returns where `idx` ends up after the text in `removed` is removed
+pub fn shift_remove(idx: BufCharIdx, removed: BufRange) -> BufCharIdx {
+    if *idx >= *removed.end {
+        idx - (removed.end - removed.start)
+    } else if *idx > *removed.start {
+        removed.start
+    } else {
+        idx
+    }
+}
+
+/// The anchors of a buffer, which `Buffer::insert` and `Buffer::remove` keep up to date with its text
+#[derive(Default)]
+pub struct Anchors {
+    anchors: HashMap<AnchorId, Anchor>,
+    next_id: usize,
+}
+
+impl Anchors {
+    pub fn add(&mut self, anchor: Anchor) -> AnchorId {
+        let id = AnchorId(self.next_id);
+        self.next_id += 1;
+        self.anchors.insert(id, anchor);
+        id
+    }
+
+    /// Returns the anchor, or `None` if it was deleted
+    pub fn get(&self, id: AnchorId) -> Option<&Anchor> {
+        self.anchors.get(&id)
+    }
+
+    /// Returns the start of the anchor
+    pub fn idx(&self, id: AnchorId) -> Option<BufCharIdx> {
+        self.range(id).map(|range| range.start)
+    }
+
+    pub fn range(&self, id: AnchorId) -> Option<BufRange> {
+        self.get(id).map(|anchor| anchor.range)
+    }
+
+    /// Moves an anchor that hasn't been deleted to a new range
+    pub fn set(&mut self, id: AnchorId, range: BufRange) {
+        if let Some(anchor) = self.anchors.get_mut(&id) {
+            anchor.range = range;
+        }
+    }
+
+    pub fn delete(&mut self, id: AnchorId) {
+        self.anchors.remove(&id);
+    }
+
+    /// Moves the anchors for `len` characters inserted at `at`
+    pub fn inserted(&mut self, at: BufCharIdx, len: usize) {
+        for anchor in self.anchors.values_mut() {
+            anchor.inserted(at, len);
+        }
+    }
+
+    /// Moves the anchors for the text in `removed` being removed, deleting the ones that collapse
+    pub fn removed(&mut self, removed: BufRange) {
+        self.anchors.retain(|_, anchor| !(anchor.removed(removed) && anchor.delete_on_collapse));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+
+    fn range(start: usize, end: usize) -> BufRange {
+        BufRange::new(start.into(), end.into())
+    }
+
+    fn anchors(anchor: Anchor) -> (Anchors, AnchorId) {
+        let mut anchors = Anchors::default();
+        let id = anchors.add(anchor);
+        (anchors, id)
+    }
+
+    #[test]
+    fn insert_before_range() {
+        let (mut anchors, id) = anchors(Anchor::range(range(5, 10), Gravity::Left, Gravity::Right));
+        anchors.inserted(2.into(), 3);
+        assert_eq!(anchors.range(id), Some(range(8, 13)));
+    }
+
+    #[test]
+    fn insert_inside_range() {
+        let (mut anchors, id) = anchors(Anchor::range(range(5, 10), Gravity::Right, Gravity::Left));
+        anchors.inserted(7.into(), 3);
+        assert_eq!(anchors.range(id), Some(range(5, 13)));
+    }
+
+    #[test]
+    fn insert_after_range() {
+        let (mut anchors, id) = anchors(Anchor::range(range(5, 10), Gravity::Left, Gravity::Right));
+        anchors.inserted(11.into(), 3);
+        assert_eq!(anchors.range(id), Some(range(5, 10)));
+    }
+
+    #[test]
+    fn insert_at_edges_follows_gravity() {
+        let (mut inclusive, id) = anchors(Anchor::range(range(5, 10), Gravity::Left, Gravity::Right));
+        inclusive.inserted(5.into(), 2);
+        inclusive.inserted(12.into(), 2);
+        assert_eq!(inclusive.range(id), Some(range(5, 14)));
+
+        let (mut exclusive, id) = anchors(Anchor::range(range(5, 10), Gravity::Right, Gravity::Left));
+        exclusive.inserted(5.into(), 2);
+        exclusive.inserted(12.into(), 2);
+        assert_eq!(exclusive.range(id), Some(range(7, 12)));
+    }
+
+    #[test]
+    fn insert_at_point() {
+        let (mut anchors, left) = anchors(Anchor::point(4.into(), Gravity::Left));
+        let right = anchors.add(Anchor::point(4.into(), Gravity::Right));
+        anchors.inserted(4.into(), 3);
+        assert_eq!(anchors.idx(left), Some(4.into()));
+        assert_eq!(anchors.idx(right), Some(7.into()));
+    }
+
+    #[test]
+    fn insert_into_empty_exclusive_range() {
+        let (mut anchors, id) = anchors(Anchor::range(range(4, 4), Gravity::Right, Gravity::Left));
+        anchors.inserted(4.into(), 3);
+        assert_eq!(anchors.range(id), Some(range(7, 7)));
+    }
+
+    #[test]
+    fn remove_before_range() {
+        let (mut anchors, id) = anchors(Anchor::range(range(5, 10), Gravity::Left, Gravity::Right));
+        anchors.removed(range(1, 4));
+        assert_eq!(anchors.range(id), Some(range(2, 7)));
+    }
+
+    #[test]
+    fn remove_inside_range() {
+        let (mut anchors, id) = anchors(Anchor::range(range(5, 10), Gravity::Left, Gravity::Right).delete_on_collapse());
+        anchors.removed(range(6, 9));
+        assert_eq!(anchors.range(id), Some(range(5, 7)));
+    }
+
+    #[test]
+    fn remove_after_range() {
+        let (mut anchors, id) = anchors(Anchor::range(range(5, 10), Gravity::Left, Gravity::Right));
+        anchors.removed(range(10, 15));
+        assert_eq!(anchors.range(id), Some(range(5, 10)));
+    }
+
+    #[test]
+    fn remove_overlapping_range() {
+        let (mut anchors, id) = anchors(Anchor::range(range(5, 10), Gravity::Left, Gravity::Right).delete_on_collapse());
+        anchors.removed(range(3, 7));
+        assert_eq!(anchors.range(id), Some(range(3, 6)));
+        anchors.removed(range(5, 8));
+        assert_eq!(anchors.range(id), Some(range(3, 5)));
+    }
+
+    #[test]
+    fn remove_whole_range() {
+        let (mut anchors, kept) = anchors(Anchor::range(range(5, 10), Gravity::Left, Gravity::Right));
+        let deleted = anchors.add(Anchor::range(range(5, 10), Gravity::Left, Gravity::Right).delete_on_collapse());
+        anchors.removed(range(4, 12));
+        assert_eq!(anchors.range(kept), Some(range(4, 4)));
+        assert_eq!(anchors.range(deleted), None);
+    }
+
+    #[test]
+    fn remove_around_point() {
+        let (mut anchors, before) = anchors(Anchor::point(4.into(), Gravity::Right).delete_on_collapse());
+        let on = anchors.add(Anchor::point(6.into(), Gravity::Right).delete_on_collapse());
+        let after = anchors.add(Anchor::point(8.into(), Gravity::Right).delete_on_collapse());
+        anchors.removed(range(5, 8));
+        assert_eq!(anchors.idx(before), Some(4.into()));
+        assert_eq!(anchors.idx(on), None);
+        assert_eq!(anchors.idx(after), Some(5.into()));
+    }
+
+    #[test]
+    fn buffer_edits_move_anchors() {
+        let mut buf = Buffer::scratch("test", "one\ntwo\nthree\n");
+        let two = buf.anchors.add(Anchor::range(range(4, 8), Gravity::Left, Gravity::Left).delete_on_collapse());
+        let three = buf.anchors.add(Anchor::point(8.into(), Gravity::Right));
+        buf.insert(0.into(), "zero\n");
+        assert_eq!(buf.anchors.range(two), Some(range(9, 13)));
+        assert_eq!(buf.anchors.idx(three), Some(13.into()));
+        buf.remove(range(9, 13));
+        assert_eq!(buf.anchors.range(two), None);
+        assert_eq!(buf.anchors.idx(three), Some(9.into()));
+        assert_eq!(buf.text.to_string(), "zero\none\nthree\n");
+    }
+
+    #[test]
+    fn marks_follow_edits() {
+        let mut buf = Buffer::scratch("test", "hello world");
+        buf.set_mark('a', 6.into());
+        buf.insert(0.into(), ">> ");
+        assert_eq!(buf.mark('a'), Some(9.into()));
+        buf.remove(range(0, 9));
+        assert_eq!(buf.mark('a'), Some(0.into()));
+    }
+}
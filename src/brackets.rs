@@ -0,0 +1,49 @@
+use crate::{buffer::Buffer, indent::BRACKETS, utils::BufCharIdx};
+
+/// Returns the bracket under the cursor, or the one before it, and the bracket it matches
+pub fn matching_pair(buf: &Buffer) -> Option<(BufCharIdx, BufCharIdx)> {
+    let under = matching(buf, buf.idx).map(|other| (buf.idx, other));
+    under.or_else(|| {
+        let before = buf.idx.checked_sub(1)?.into();
+        matching(buf, before).map(|other| (before, other))
+    })
+}
+
+/// Returns the bracket matching the one at `idx`. The text is only scanned up to the match, and brackets inside
+/// a string or comment only match the ones inside the same string or comment.
+pub fn matching(buf: &Buffer, idx: BufCharIdx) -> Option<BufCharIdx> {
+    if *idx >= buf.text.len_chars() {
+        return None;
+    }
+    let c = buf.text.char(*idx);
+    let (open, close, forward) = BRACKETS.iter().find_map(|&(open, close)| match c {
+        _ if c == open => Some((open, close, true)),
+        _ if c == close => Some((open, close, false)),
+        _ => None,
+    })?;
+    let literal = |idx: usize| {
+        let byte = buf.text.char_to_byte(idx);
+        buf.syntax.as_ref().and_then(|syntax| syntax.literal_at(byte))
+    };
+    let inside = literal(*idx);
+    let mut depth = 0usize;
+    let mut check = |i: usize, ch: char| {
+        if (ch != open && ch != close) || literal(i) != inside {
+            return false;
+        }
+        if (ch == open) == forward {
+            depth += 1;
+            return false;
+        }
+        if depth == 0 {
+            return true;
+        }
+        depth -= 1;
+        false
+    };
+    if forward {
+        buf.text.chars_at(*idx + 1).zip(*idx + 1..).find(|&(ch, i)| check(i, ch)).map(|(_, i)| i.into())
+    } else {
+        buf.text.chars_at(*idx).reversed().zip((0..*idx).rev()).find(|&(ch, i)| check(i, ch)).map(|(_, i)| i.into())
+    }
+}
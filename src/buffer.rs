@@ -1,56 +1,128 @@
 use crate::{
     action::{BufferAction, Action, Undoable},
-    utils::{BufByteIdx, BufCharIdx, BufCol, BufPos, BufRow, BufRange},
+    anchor::{self, Anchor, AnchorId, Anchors, Gravity},
+    config::Filetype,
+    highlight::language,
+    comment, grapheme, indent, pairs,
+    fold::Fold,
+    syntax::{self, Syntax},
+    utils::{BufByteIdx, BufCharIdx, BufCol, BufPos, BufRow, BufRange, DisplayCol, DisplayPos},
 };
+use log::debug;
+use regex::Regex;
 use ropey::{Rope, RopeSlice};
+use tree_sitter::{InputEdit, Point};
+use unicode_segmentation::UnicodeSegmentation;
 use std::{
+    borrow::Cow,
+    collections::HashMap,
     fs::File,
-    io::{BufReader, BufWriter},
+    io::{self, BufReader, BufWriter},
     ops::Range,
     path::PathBuf,
+    time::Instant,
 };
 
-#[derive(Clone, Copy)]
+/// The number of changes that are remembered by the change list
+const MAX_CHANGES: usize = 100;
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
 pub enum EditMode {
+    #[default]
     Normal,
     Insert,
+    Visual,
+    VisualBlock,
 }
 
-impl Default for EditMode {
-    fn default() -> Self {
-        EditMode::Normal
+impl EditMode {
+    pub fn is_visual(&self) -> bool {
+        matches!(self, EditMode::Visual | EditMode::VisualBlock)
     }
 }
 
+/// The state of a single cursor, used to store the cursors that are not currently being operated on
+#[derive(Clone, Copy, Default)]
+pub struct Cursor {
+    pub idx: BufCharIdx,
+    pub saved_col: DisplayCol,
+    pub anchor: Option<BufCharIdx>,
+}
+
 #[derive(Default)]
 pub struct Buffer {
     /// Rope represtation of the contents of this buffer
     pub text: Rope,
     /// Current index of the cursor within the rope
     pub idx: BufCharIdx,
-    /// The column index the cursor will snap to when moving between lines
-    pub saved_col: BufCol,
+    /// The display column the cursor will snap to when moving between lines
+    pub saved_col: DisplayCol,
+    /// The width lines wrap at in the window that shows the buffer, if they wrap
+    pub wrap_width: Option<usize>,
+    /// The other end of the selection of the cursor, if anything is selected
+    pub anchor: Option<BufCharIdx>,
+    /// Additional cursors, every action that applies to the cursor is applied to these as well
+    pub cursors: Vec<Cursor>,
+    /// Positions and ranges that move along with the text around them
+    pub anchors: Anchors,
+    /// Named positions, see `Buffer::mark`
+    pub marks: HashMap<char, AnchorId>,
+    /// The ranges of lines that can be hidden behind a single line, see `fold::update`
+    pub folds: Vec<Fold>,
+    /// The lines marked by `:global`, a mark is deleted along with its line
+    pub line_marks: Vec<AnchorId>,
+    /// The positions of the last changes, oldest first, which `g;` and `g,` move through
+    changes: Vec<AnchorId>,
+    /// The change that was moved to last, or the length of `changes` after a new change
+    change_idx: usize,
+    /// The starts of the lines that were indented by opening them, since the buffer last left insert mode
+    auto_indented: Vec<AnchorId>,
+    /// The length of the undo history when the buffer entered insert mode
+    insert_undo_len: usize,
     /// The mode the buffer is currently in
     pub mode: EditMode,
     /// Whether the buffer has been edited since saving
     pub edited: bool,
+    /// Whether edits are refused, such as in the log
+    pub readonly: bool,
+    /// Incremented on every change to the text
+    pub version: usize,
     /// The path of the file being edited
     pub path: PathBuf,
+    /// The settings for the type of the file
+    pub filetype: Filetype,
+    /// The syntax tree of the text, if the language of the file is known
+    pub syntax: Option<Syntax>,
     pub undo: Vec<BufferAction>,
     pub redo: Vec<BufferAction>,
 }
 
 impl Buffer {
+    /// Creates an empty buffer for a file that doesn't exist yet, writing the buffer creates it
     pub fn new(path: PathBuf) -> Self {
-        let text = Rope::from_reader(BufReader::new(File::open(&path).unwrap())).unwrap();
         Self {
-            text,
-            edited: false,
+            syntax: language::detect(&path).map(Syntax::new),
             path,
             ..Default::default()
         }
     }
 
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let start = Instant::now();
+        let text = Rope::from_reader(BufReader::new(File::open(&path)?))?;
+        debug!("Read {} lines of {} in {:?}", text.len_lines(), path.display(), start.elapsed());
+        Ok(Self { text, ..Self::new(path) })
+    }
+
+    /// Creates a buffer that isn't backed by a file, such as the quickfix list
+    pub fn scratch(name: &str, text: &str) -> Self {
+        Self {
+            text: Rope::from_str(text),
+            path: PathBuf::from(name),
+            ..Default::default()
+        }
+    }
+
     /// Returns which row the cursor is on
     pub fn row(&self) -> BufRow {
         self.char_to_row(self.idx)
@@ -61,23 +133,353 @@ impl Buffer {
         self.char_to_col(self.idx)
     }
 
+    /// Returns the last row that holds text, ignoring the empty row after a trailing line break
+    pub fn last_row(&self) -> BufRow {
+        let last = self.text.len_lines().saturating_sub(1);
+        if last > 0 && self.text.line(last).len_chars() == 0 {
+            (last - 1).into()
+        } else {
+            last.into()
+        }
+    }
+
     /// Returns the column of the last character in a given row
     pub fn max_col(&self, row: BufRow) -> BufCol {
         self.text.line(*row).len_chars().saturating_sub(1).into()
     }
 
+    /// Inserts `string` at index `i`, moving all cursors after it along with the text
     pub fn insert(&mut self, i: BufCharIdx, string: &str) {
+        let len = string.chars().count();
+        let (start_byte, start_position) = self.byte_point(i);
         self.text.insert(*i, string);
+        if let Some(syntax) = &mut self.syntax {
+            syntax.edit(&InputEdit {
+                start_byte,
+                old_end_byte: start_byte,
+                new_end_byte: start_byte + string.len(),
+                start_position,
+                old_end_position: start_position,
+                new_end_position: syntax::point(&self.text, start_byte + string.len()),
+            });
+        }
+        self.changed();
+        self.anchors.inserted(i, len);
+        self.rebase(|idx| anchor::shift_insert(idx, i, len, Gravity::Right));
+        self.set_change_marks(i, usize::max(*i + len, *i + 1) - 1);
     }
 
+    /// Removes the text in `range`, moving all cursors after it along with the text
     pub fn remove(&mut self, range: BufRange) {
-        self.idx = range.start;
+        self.anchors.removed(range);
+        self.rebase(|idx| anchor::shift_remove(idx, range));
+        let ((start_byte, start_position), (old_end_byte, old_end_position)) = (self.byte_point(range.start), self.byte_point(range.end));
+        if let Some(syntax) = &mut self.syntax {
+            syntax.edit(&InputEdit {
+                start_byte,
+                old_end_byte,
+                new_end_byte: start_byte,
+                start_position,
+                old_end_position,
+                new_end_position: start_position,
+            });
+        }
+        let start = range.start;
         let range: Range<usize> = range.into();
         self.text.remove(range);
+        self.changed();
+        self.set_change_marks(start, *start);
+    }
+
+    /// Returns the byte index of a character and its position in the syntax tree
+    fn byte_point(&self, idx: BufCharIdx) -> (usize, Point) {
+        let byte = self.text.char_to_byte(*idx);
+        (byte, syntax::point(&self.text, byte))
+    }
+
+    /// Parses the text again if it was edited since the syntax tree was last updated
+    pub fn update_syntax(&mut self) {
+        if let Some(syntax) = &mut self.syntax {
+            syntax.update(&self.text);
+        }
+    }
+
+    /// Marks the text that was changed last, and adds it to the change list
+    fn set_change_marks(&mut self, start: BufCharIdx, end: impl Into<BufCharIdx>) {
+        self.set_mark('.', start);
+        self.set_mark('[', start);
+        self.set_mark(']', end.into());
+        self.change_idx = self.changes.len();
+        // A change on the same line as the last one replaces it
+        if let Some(&last) = self.changes.last() {
+            if self.anchors.idx(last).map(|idx| *self.char_to_row(idx)) == Some(*self.char_to_row(start)) {
+                return self.anchors.set(last, BufRange::new(start, start));
+            }
+        }
+        if self.changes.len() >= MAX_CHANGES {
+            let oldest = self.changes.remove(0);
+            self.anchors.delete(oldest);
+        }
+        self.changes.push(self.anchors.add(Anchor::point(start, Gravity::Right)));
+        self.change_idx = self.changes.len();
+    }
+
+    /// Returns the position of the change before the one moved to last, or after it if `older` isn't set
+    pub fn next_change(&mut self, older: bool) -> Result<BufCharIdx, &'static str> {
+        if self.changes.is_empty() {
+            return Err("Change list is empty");
+        }
+        let idx = match older {
+            true => self.change_idx.checked_sub(1).ok_or("At start of change list")?,
+            false => Some(self.change_idx + 1).filter(|idx| *idx < self.changes.len()).ok_or("At end of change list")?,
+        };
+        self.change_idx = idx;
+        self.anchors.idx(self.changes[idx]).ok_or("Change was deleted")
+    }
+
+    /// Sets a mark, which stays on the character it is placed on as text is inserted before it
+    pub fn set_mark(&mut self, name: char, idx: BufCharIdx) {
+        let range = BufRange::new(idx, idx);
+        match self.marks.get(&name) {
+            Some(&id) => self.anchors.set(id, range),
+            None => {
+                let id = self.anchors.add(Anchor::point(idx, Gravity::Right));
+                self.marks.insert(name, id);
+            }
+        }
+    }
+
+    pub fn delete_mark(&mut self, name: char) {
+        if let Some(id) = self.marks.remove(&name) {
+            self.anchors.delete(id);
+        }
+    }
+
+    /// Marks every line in `rows` for `:global`
+    pub fn mark_lines(&mut self, rows: impl Iterator<Item = BufRow>) {
+        for id in self.line_marks.drain(..) {
+            self.anchors.delete(id);
+        }
+        for row in rows {
+            let start = self.row_to_char(row);
+            let end = self.text.line_to_char(usize::min(*row + 1, self.text.len_lines())).into();
+            let anchor = Anchor::range(BufRange::new(start, end), Gravity::Left, Gravity::Left);
+            let id = self.anchors.add(anchor.delete_on_collapse());
+            self.line_marks.push(id);
+        }
+    }
+
+    /// Removes the first line marked by `:global` that still exists and returns its start
+    pub fn next_line_mark(&mut self) -> Option<BufCharIdx> {
+        while !self.line_marks.is_empty() {
+            let id = self.line_marks.remove(0);
+            let idx = self.anchors.idx(id);
+            self.anchors.delete(id);
+            if idx.is_some() {
+                return idx;
+            }
+        }
+        None
+    }
+
+    fn changed(&mut self) {
+        self.edited = true;
+        self.version += 1;
+    }
+
+    /// Moves all stored positions to where `f` maps them
+    fn rebase(&mut self, f: impl Fn(BufCharIdx) -> BufCharIdx) {
+        self.idx = f(self.idx);
+        self.anchor = self.anchor.map(&f);
+        for cursor in self.cursors.iter_mut() {
+            cursor.idx = f(cursor.idx);
+            cursor.anchor = cursor.anchor.map(&f);
+        }
+    }
+
+    /// Returns the position of a mark. Besides the marks set with `m`, `.` is the last change, `^` where insert mode
+    /// was left, `[` and `]` the first and last character of the last change or yank, and `<` and `>` of the last
+    /// visual selection.
+    pub fn mark(&self, name: char) -> Option<BufCharIdx> {
+        let idx = self.anchors.idx(*self.marks.get(&name)?)?;
+        Some(usize::min(*idx, self.text.len_chars()).into())
+    }
+
+    /// Returns every mark with its name, ordered by name
+    pub fn all_marks(&self) -> Vec<(char, BufCharIdx)> {
+        let mut marks: Vec<_> = self.marks.keys().filter_map(|&name| Some((name, self.mark(name)?))).collect();
+        marks.sort_by_key(|(name, _)| *name);
+        marks
+    }
+
+    /// Returns the state of the cursor that is currently being operated on
+    pub fn primary(&self) -> Cursor {
+        Cursor {
+            idx: self.idx,
+            saved_col: self.saved_col,
+            anchor: self.anchor,
+        }
+    }
+
+    fn set_primary(&mut self, cursor: Cursor) {
+        self.idx = cursor.idx;
+        self.saved_col = cursor.saved_col;
+        self.anchor = cursor.anchor;
+    }
+
+    /// Swaps the primary cursor with the additional cursor at index `i`
+    fn swap_cursor(&mut self, i: usize) {
+        let primary = self.primary();
+        let cursor = std::mem::replace(&mut self.cursors[i], primary);
+        self.set_primary(cursor);
+    }
+
+    /// Adds a new cursor and makes it the primary one
+    pub fn add_cursor(&mut self, cursor: Cursor) {
+        self.cursors.push(self.primary());
+        self.set_primary(cursor);
     }
 
-    pub fn cursor(&self) -> BufPos {
-        BufPos::new(self.col(), self.row())
+    /// Replaces all cursors, the first one becomes the primary cursor
+    pub fn set_cursors(&mut self, mut cursors: Vec<Cursor>) {
+        if cursors.is_empty() {
+            return;
+        }
+        self.set_primary(cursors.remove(0));
+        self.cursors = cursors;
+    }
+
+    /// Removes all cursors except for the primary one
+    pub fn collapse_cursors(&mut self) {
+        self.cursors.clear();
+    }
+
+    /// Returns the primary cursor followed by all additional cursors
+    pub fn all_cursors(&self) -> impl Iterator<Item = Cursor> + '_ {
+        std::iter::once(self.primary()).chain(self.cursors.iter().copied())
+    }
+
+    /// Returns the range of text selected by the cursor, including the character under the cursor
+    pub fn selection(&self) -> Option<BufRange> {
+        self.cursor_selection(&self.primary())
+    }
+
+    pub fn cursor_selection(&self, cursor: &Cursor) -> Option<BufRange> {
+        cursor.anchor.map(|anchor| {
+            let range = BufRange::new(anchor, cursor.idx);
+            BufRange::new(range.start, usize::min(*range.end + 1, self.text.len_chars()).into())
+        })
+    }
+
+    /// Returns the range of the word under the cursor, if the cursor is on a word
+    pub fn word_at(&self, idx: BufCharIdx) -> Option<BufRange> {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        if *idx >= self.text.len_chars() || !is_word(self.text.char(*idx)) {
+            return None;
+        }
+        let start = *idx - self.text.chars_at(*idx).reversed().take_while(|c| is_word(*c)).count();
+        let end = *idx + self.text.chars_at(*idx).take_while(|c| is_word(*c)).count();
+        Some(BufRange::new(start.into(), end.into()))
+    }
+
+    /// Returns the contents of a row without the line break, borrowing from the rope when possible
+    pub fn line_str(&self, row: BufRow) -> Cow<'_, str> {
+        let line = self.text.line(*row);
+        let mut chunks = line.chunks();
+        let line = match (chunks.next(), chunks.next()) {
+            (None, _) => Cow::Borrowed(""),
+            (Some(chunk), None) => Cow::Borrowed(chunk),
+            _ => Cow::Owned(line.to_string()),
+        };
+        match line {
+            Cow::Borrowed(s) => Cow::Borrowed(s.trim_end_matches(['\n', '\r'])),
+            Cow::Owned(s) => Cow::Owned(s.trim_end_matches(['\n', '\r']).to_string()),
+        }
+    }
+
+    /// Returns the matches of `regex` in the given row that start at or after byte `from` of the row
+    fn row_matches(&self, regex: &Regex, row: BufRow, from: usize) -> Vec<BufRange> {
+        let line = self.line_str(row);
+        let line_start = self.row_to_char(row);
+        let to_char = |byte: usize| line_start + line[..byte].chars().count().into();
+        let mut matches = Vec::new();
+        let mut at = from;
+        while at <= line.len() {
+            let m = match regex.find_at(&line, at) {
+                Some(m) => m,
+                None => break,
+            };
+            matches.push(BufRange::new(to_char(m.start()), to_char(m.end())));
+            // Step over empty matches so they are not found again
+            at = if m.end() > m.start() {
+                m.end()
+            } else {
+                m.end() + line[m.end()..].chars().next().map_or(1, char::len_utf8)
+            };
+        }
+        matches
+    }
+
+    /// Returns the first match of `regex` starting at or after `from`. Matches do not span multiple lines.
+    pub fn find_next(&self, regex: &Regex, from: BufCharIdx) -> Option<BufRange> {
+        let first_row = self.char_to_row(from);
+        let from_byte = self.text.char_to_byte(*from) - *self.row_to_byte(first_row);
+        (*first_row..self.text.len_lines()).find_map(|row| {
+            let from = if row == *first_row { from_byte } else { 0 };
+            self.row_matches(regex, row.into(), from).into_iter().next()
+        })
+    }
+
+    /// Returns the last match of `regex` starting before `before`
+    pub fn find_prev(&self, regex: &Regex, before: BufCharIdx) -> Option<BufRange> {
+        let last_row = self.char_to_row(before);
+        (0..=*last_row).rev().find_map(|row| {
+            self.row_matches(regex, row.into(), 0)
+                .into_iter()
+                .rev()
+                .find(|m| *m.start < *before)
+        })
+    }
+
+    /// Returns all matches of `regex` that lie within `range`
+    pub fn find_all(&self, regex: &Regex, range: BufRange) -> Vec<BufRange> {
+        let first_row = self.char_to_row(range.start);
+        let last_row = self.char_to_row(range.end);
+        (*first_row..=*last_row)
+            .flat_map(|row| self.row_matches(regex, row.into(), 0))
+            .filter(|m| *m.start >= *range.start && *m.end <= *range.end)
+            .collect()
+    }
+
+    /// Returns the position of the cursor on screen
+    pub fn cursor(&self) -> DisplayPos {
+        self.char_to_display_pos(self.idx)
+    }
+
+    /// Returns the display column a character starts at, with tabs reaching up to the next tab stop and wide
+    /// characters taking up two columns
+    pub fn display_col(&self, character: BufCharIdx) -> DisplayCol {
+        let line = self.line_str(self.char_to_row(character));
+        let byte = line.char_indices().nth(*self.char_to_col(character)).map_or(line.len(), |(i, _)| i);
+        grapheme::text_width(&line[..byte], 0, self.filetype.tabstop).into()
+    }
+
+    /// Returns the column of the grapheme that covers a display column of a row, or of the last character if the
+    /// row ends before it
+    pub fn display_to_col(&self, row: BufRow, target: DisplayCol) -> BufCol {
+        let (mut display, mut col) = (0, 0);
+        for grapheme in self.line_str(row).graphemes(true) {
+            display += grapheme::width(grapheme, display, self.filetype.tabstop);
+            if display > *target {
+                return col.into();
+            }
+            col += grapheme.chars().count();
+        }
+        self.max_col(row)
+    }
+
+    pub fn char_to_display_pos(&self, character: BufCharIdx) -> DisplayPos {
+        DisplayPos::new(self.display_col(character), self.char_to_row(character))
     }
 
     pub fn char_to_col(&self, character: BufCharIdx) -> BufCol {
@@ -109,27 +511,191 @@ impl Buffer {
         self.text.slice(range)
     }
 
+    /// Switches modes, entering or leaving visual mode starts or ends the selection of every cursor
+    pub fn set_mode(&mut self, mode: EditMode) {
+        if let (Some(anchor), false) = (self.anchor, mode.is_visual()) {
+            let range = BufRange::new(anchor, self.idx);
+            self.set_mark('<', range.start);
+            self.set_mark('>', range.end);
+        }
+        if self.mode != EditMode::Insert && mode == EditMode::Insert {
+            self.insert_undo_len = self.undo.len();
+        }
+        if self.mode == EditMode::Insert && mode != EditMode::Insert {
+            self.set_mark('^', self.idx);
+            self.trim_blank_lines();
+        }
+        if mode.is_visual() {
+            self.anchor.get_or_insert(self.idx);
+            for cursor in self.cursors.iter_mut() {
+                cursor.anchor.get_or_insert(cursor.idx);
+            }
+        } else {
+            self.anchor = None;
+            for cursor in self.cursors.iter_mut() {
+                cursor.anchor = None;
+            }
+        }
+        self.mode = mode;
+    }
+
+    /// Empties the cursor lines that were indented by opening them and hold only whitespace, as nothing was typed
+    /// after the indentation. The removal is undone together with the changes made in insert mode.
+    fn trim_blank_lines(&mut self) {
+        let indented: Vec<usize> = std::mem::take(&mut self.auto_indented)
+            .into_iter()
+            .filter_map(|id| {
+                let idx = self.anchors.idx(id);
+                self.anchors.delete(id);
+                idx.map(|idx| *self.char_to_row(idx))
+            })
+            .collect();
+        let mut rows: Vec<usize> = self.all_cursors().map(|cursor| *self.char_to_row(cursor.idx)).filter(|row| indented.contains(row)).collect();
+        rows.sort_unstable();
+        rows.dedup();
+        let mut inverses = Vec::new();
+        for row in rows.into_iter().rev() {
+            let line = self.line_str(row.into());
+            if line.is_empty() || !line.trim().is_empty() {
+                continue;
+            }
+            let start = self.row_to_char(row.into());
+            inverses.push(BufferAction::InsertAt(start, line.to_string()));
+            self.remove(BufRange::new(start, start + line.chars().count().into()));
+        }
+        if inverses.is_empty() {
+            return;
+        }
+        inverses.reverse();
+        self.push_undo(BufferAction::Batch(inverses));
+        self.join_undo(self.insert_undo_len);
+    }
+
+    /// Remembers that the line of the cursor was indented by opening it
+    fn mark_auto_indented(&mut self) {
+        let start = self.row_to_char(self.row());
+        let id = self.anchors.add(Anchor::point(start, Gravity::Left));
+        self.auto_indented.push(id);
+    }
+
     pub fn save_col(&mut self) {
-        self.saved_col = self.col();
+        self.saved_col = self.display_col(self.idx);
     }
 
-    #[allow(unused)]
     /// Saves the current state of the buffer to the file
-    pub fn write(&mut self) {
-        self.text
-            .write_to(BufWriter::new(
-                File::create(&self.path)
-                    .expect("Unable to create new/read existing file at buffer path"),
-            ))
-            .unwrap();
+    pub fn write(&mut self) -> io::Result<()> {
+        let start = Instant::now();
+        self.text.write_to(BufWriter::new(File::create(&self.path)?))?;
         self.edited = false;
+        debug!("Wrote {} lines of {} in {:?}", self.text.len_lines(), self.path.display(), start.elapsed());
+        Ok(())
+    }
+
+    /// Fails for an action that edits a read-only buffer
+    fn check_writable(&self, action: &BufferAction) -> Result<(), &'static str> {
+        match self.readonly && action.is_edit() {
+            true => Err("The buffer is read-only"),
+            false => Ok(()),
+        }
+    }
+
+    /// Applies an action and returns the action that undoes it. Every edit passes through here, so that read-only
+    /// buffers can't be changed.
+    pub fn record(&mut self, action: BufferAction) -> Result<BufferAction, &'static str> {
+        self.check_writable(&action)?;
+        match action {
+            BufferAction::Batch(actions) => {
+                let mut inverses = Vec::with_capacity(actions.len());
+                for action in actions {
+                    inverses.push(self.record(action)?);
+                }
+                inverses.reverse();
+                Ok(BufferAction::Batch(inverses))
+            }
+            // Typing is recorded as the edits it is made of
+            BufferAction::NewLine => {
+                let inverse = self.record(indent::new_line(self));
+                self.mark_auto_indented();
+                inverse
+            }
+            BufferAction::OpenLine { above } => {
+                let inverse = self.record(indent::open_line(self, above));
+                self.mark_auto_indented();
+                inverse
+            }
+            BufferAction::Type(c) => {
+                self.update_syntax();
+                self.record(pairs::type_char(self, c).unwrap_or_else(|| indent::type_char(self, c)))
+            }
+            BufferAction::Backspace => self.record(pairs::backspace(self)),
+            BufferAction::Tab => self.record(indent::tab(self)),
+            BufferAction::IndentLine => {
+                self.update_syntax();
+                self.record(indent::indent_line(self))
+            }
+            BufferAction::Reindent(selection) => {
+                self.update_syntax();
+                self.record(indent::reindent(self, selection))
+            }
+            BufferAction::ToggleComment { selection, block } => self.record(comment::toggle(self, selection, block)?),
+            action => {
+                let inverse = action.inverse(self);
+                action.apply(self)?;
+                Ok(inverse)
+            }
+        }
+    }
+
+    /// Stores an action in the undo history, it is applied when undoing
+    pub fn push_undo(&mut self, inverse: BufferAction) {
+        self.redo.clear();
+        self.undo.push(inverse);
+    }
+
+    /// Merges the undo steps stored since the undo history had length `len` into a single step
+    pub fn join_undo(&mut self, len: usize) {
+        if self.undo.len() > len + 1 {
+            let mut inverses = self.undo.split_off(len);
+            inverses.reverse();
+            self.undo.push(BufferAction::Batch(inverses));
+        }
+    }
+
+    /// Applies an action to every cursor, and stores it in the undo history if it edits the text
+    pub fn apply(&mut self, action: BufferAction) -> Result<(), &'static str> {
+        let is_edit = action.is_edit();
+        // Checked up front as well, so that a batch doesn't move the cursor before failing
+        self.check_writable(&action)?;
+        let inverse = self.apply_to_cursors(action)?;
+        if is_edit {
+            self.push_undo(inverse);
+        }
+        Ok(())
     }
 
-    pub fn apply(&mut self, action: BufferAction) -> Result<(), &'static str>{
-        if let BufferAction::Delete(_) | BufferAction::Insert(_) | BufferAction::InsertAt(_, _) = action {
-            self.redo.clear();
-            self.undo.push(action.inverse(self));
+    /// Applies an action once, or to each cursor if it concerns cursors, and returns the action that undoes it
+    fn apply_to_cursors(&mut self, action: BufferAction) -> Result<BufferAction, &'static str> {
+        match action {
+            BufferAction::Batch(actions) => {
+                let mut inverses = Vec::with_capacity(actions.len());
+                for action in actions {
+                    inverses.push(self.apply_to_cursors(action)?);
+                }
+                inverses.reverse();
+                Ok(BufferAction::Batch(inverses))
+            }
+            action if action.is_per_cursor() => {
+                let mut inverses = vec![self.record(action.clone())?];
+                for i in 0..self.cursors.len() {
+                    self.swap_cursor(i);
+                    let inverse = self.record(action.clone());
+                    self.swap_cursor(i);
+                    inverses.push(inverse?);
+                }
+                inverses.reverse();
+                Ok(BufferAction::Batch(inverses))
+            }
+            action => self.record(action),
         }
-        action.apply(self)
     }
 }
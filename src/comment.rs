@@ -0,0 +1,86 @@
+use crate::{
+    action::BufferAction,
+    buffer::Buffer,
+    indent::indentation,
+    utils::{BufCharIdx, Selection},
+};
+
+/// Returns the token that starts a line comment at a byte, from the settings of the file type or else the language
+fn line_token(buf: &Buffer, byte: usize) -> Option<String> {
+    let language = buf.syntax.as_ref().map(|syntax| syntax.language_at(byte));
+    buf.filetype.line_comment.clone().or_else(|| language?.line_comment().map(String::from))
+}
+
+/// Returns the tokens that start and end a block comment at a byte
+fn block_tokens(buf: &Buffer, byte: usize) -> Option<(String, String)> {
+    let language = buf.syntax.as_ref().map(|syntax| syntax.language_at(byte));
+    buf.filetype.block_comment.clone().or_else(|| {
+        let (open, close) = language?.block_comment()?;
+        Some((open.to_string(), close.to_string()))
+    })
+}
+
+/// Comments or uncomments the lines of a selection, or wraps it in a block comment if `block` is set
+pub fn toggle(buf: &Buffer, selection: Selection, block: bool) -> Result<BufferAction, &'static str> {
+    let range = selection.bounds(buf);
+    let first = buf.char_to_row(range.start);
+    let last = buf.char_to_row(usize::max(*range.start, range.end.saturating_sub(1)).into());
+    let byte = buf.text.char_to_byte(*range.start);
+    if !block {
+        let token = line_token(buf, byte).ok_or("No line comment token for this file type")?;
+        return Ok(toggle_lines(buf, *first..*last + 1, &token));
+    }
+    let (open, close) = block_tokens(buf, byte).ok_or("No block comment token for this file type")?;
+    if let Selection::Selected = selection {
+        return Ok(toggle_block(buf, range.start, range.end, &open, &close));
+    }
+    // Motions comment the lines they cover, from the first non-blank character to the end of the text
+    let start = buf.row_to_char(first) + indentation(&buf.line_str(first)).chars().count().into();
+    let line = buf.line_str(last);
+    let end = buf.row_to_char(last) + line.trim_end().chars().count().into();
+    Ok(toggle_block(buf, start, BufCharIdx::max(start, end), &open, &close))
+}
+
+/// Uncomments the non-blank lines in `rows` if all of them are commented, and comments all of them otherwise. The
+/// tokens line up at the smallest indentation of the lines.
+fn toggle_lines(buf: &Buffer, rows: std::ops::Range<usize>, token: &str) -> BufferAction {
+    let lines: Vec<_> =
+        rows.map(|row| (row, buf.line_str(row.into()))).filter(|(_, line)| !line.trim().is_empty()).collect();
+    let commented = lines.iter().all(|(_, line)| line.trim_start().starts_with(token));
+    let col = lines.iter().map(|(_, line)| indentation(line).chars().count()).min().unwrap_or(0);
+    let mut actions = Vec::new();
+    // Lines are changed from the bottom up, so that the positions of the lines above stay the same
+    for (row, line) in lines.iter().rev() {
+        let start = buf.row_to_char((*row).into());
+        if commented {
+            let indent = indentation(line).chars().count();
+            let rest = &line.trim_start()[token.len()..];
+            let len = token.chars().count() + rest.starts_with(' ') as usize;
+            let from = start + indent.into();
+            actions.push(BufferAction::Delete(Selection::Bounds(from, from + len.into())));
+        } else {
+            actions.push(BufferAction::InsertAt(start + col.into(), format!("{} ", token)));
+        }
+    }
+    BufferAction::Batch(actions)
+}
+
+/// Removes the block comment tokens around the text from `start` to `end` if it is wrapped in them, and wraps it in
+/// them otherwise
+fn toggle_block(buf: &Buffer, start: BufCharIdx, end: BufCharIdx, open: &str, close: &str) -> BufferAction {
+    let text = buf.slice((start..end).into()).to_string();
+    let (open_len, close_len) = (open.chars().count(), close.chars().count());
+    if text.starts_with(open) && text.ends_with(close) && text.chars().count() >= open_len + close_len {
+        let inner = &text[open.len()..text.len() - close.len()];
+        let open_len = open_len + inner.starts_with(' ') as usize;
+        let close_len = close_len + (inner.ends_with(' ') && inner.len() > 1) as usize;
+        return BufferAction::Batch(vec![
+            BufferAction::Delete(Selection::Bounds(end - close_len.into(), end)),
+            BufferAction::Delete(Selection::Bounds(start, start + open_len.into())),
+        ]);
+    }
+    BufferAction::Batch(vec![
+        BufferAction::InsertAt(end, format!(" {}", close)),
+        BufferAction::InsertAt(start, format!("{} ", open)),
+    ])
+}
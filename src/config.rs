@@ -1,6 +1,8 @@
+use crate::{grapheme, message::Error, rect::Margins, status::StatusLine};
 use crossterm::style::{self, ContentStyle};
+use log::LevelFilter;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path, time::Duration};
 
 #[derive(Clone, Debug)]
 pub struct HighlightStyles {
@@ -18,29 +20,179 @@ impl HighlightStyles {
 pub struct Config {
     pub line_nr_active: ContentStyle,
     pub line_nr_column: ContentStyle,
+    pub selection: ContentStyle,
+    pub search: ContentStyle,
+    /// The style of the bracket matching the one at the cursor
+    pub matching_bracket: ContentStyle,
+    /// The style of the line shown in place of a closed fold
+    pub fold: ContentStyle,
+    /// The style of the status line of the focused window, and of the other windows
+    pub status_line: ContentStyle,
+    pub status_line_inactive: ContentStyle,
+    /// The styles of the mode in the status line, in normal, insert and visual mode
+    pub status_normal: ContentStyle,
+    pub status_insert: ContentStyle,
+    pub status_visual: ContentStyle,
+    /// What the status line shows
+    pub status: StatusLine,
+    /// The styles of informational messages, warnings and errors on the message line
+    pub message_info: ContentStyle,
+    pub message_warning: ContentStyle,
+    pub message_error: ContentStyle,
+    /// How long a message is shown before it disappears
+    pub message_timeout: Duration,
+    /// The styles of popups such as pickers, of the selected item and of the characters that match the query
+    pub popup: ContentStyle,
+    pub popup_selected: ContentStyle,
+    pub popup_match: ContentStyle,
+    /// The most detailed level of records written to the log file
+    pub log_level: LevelFilter,
     pub hl: HighlightStyles,
+    /// Settings per file type, by file extension
+    pub filetypes: HashMap<String, Filetype>,
+    /// The rows and columns kept visible around the cursor
+    pub margins: Margins,
+}
+
+/// Settings that depend on the type of the file being edited
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Filetype {
+    /// The number of columns a tab reaches up to
+    pub tabstop: usize,
+    /// Whether indentation and Tab in insert mode use spaces instead of tabs
+    pub expandtab: bool,
+    /// The number of columns of every level of indentation
+    pub shiftwidth: usize,
+    /// The number of columns Tab and Backspace in insert mode move over by adding and removing whitespace, or 0 to
+    /// add a single tab and remove a single character
+    pub softtabstop: usize,
+    /// The brackets and quotes that are closed automatically in insert mode, as an opening and a closing character
+    pub pairs: Vec<String>,
+    /// The token that starts a line comment, instead of the one of the language
+    pub line_comment: Option<String>,
+    /// The tokens that start and end a block comment, instead of the ones of the language
+    pub block_comment: Option<(String, String)>,
+    /// Whether lines longer than the window continue on the rows below instead of scrolling sideways
+    pub wrap: bool,
+    /// Whether wrapped lines break after whitespace instead of at any character
+    pub linebreak: bool,
+    /// Whether the rows that continue a wrapped line are indented like the line
+    pub breakindent: bool,
+    /// The text shown at the start of the rows that continue a wrapped line
+    pub showbreak: String,
+    /// How the ranges of lines that can be folded are found
+    pub foldmethod: FoldMethod,
+}
+
+/// How the ranges of lines that can be folded are found, besides the folds created with `zf`
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum FoldMethod {
+    /// From the syntax tree, or by indentation if the language of the file is unknown
+    Syntax,
+    /// A line followed by lines that are indented further can be folded along with them
+    Indent,
+    /// Only folds created with `zf`
+    Manual,
+}
+
+/// The pairs of files without settings of their own
+const DEFAULT_PAIRS: [&str; 6] = ["()", "[]", "{}", "\"\"", "''", "``"];
+
+impl Filetype {
+    fn new(shiftwidth: usize, expandtab: bool, pairs: &[&str]) -> Self {
+        Self {
+            // Files indented with tabs show every tab as a level of indentation
+            tabstop: if expandtab { 8 } else { shiftwidth },
+            expandtab,
+            shiftwidth,
+            softtabstop: if expandtab { shiftwidth } else { 0 },
+            pairs: pairs.iter().map(|pair| pair.to_string()).collect(),
+            line_comment: None,
+            block_comment: None,
+            wrap: false,
+            linebreak: false,
+            breakindent: false,
+            showbreak: String::new(),
+            foldmethod: FoldMethod::Syntax,
+        }
+    }
+
+    /// Wraps lines at whitespace for files that hold prose
+    fn wrapped(mut self) -> Self {
+        self.wrap = true;
+        self.linebreak = true;
+        self.breakindent = true;
+        self
+    }
+
+    fn comments(mut self, line: Option<&str>, block: Option<(&str, &str)>) -> Self {
+        self.line_comment = line.map(String::from);
+        self.block_comment = block.map(|(open, close)| (open.to_string(), close.to_string()));
+        self
+    }
+
+    /// Returns the whitespace of a level of indentation
+    pub fn indent(&self) -> String {
+        self.whitespace(0, self.shiftwidth)
+    }
+
+    /// Returns the whitespace that reaches from one display column to another, using tabs where they fit unless
+    /// `expandtab` is set
+    pub fn whitespace(&self, from: usize, to: usize) -> String {
+        let tabstop = self.tabstop.max(1);
+        let mut whitespace = String::new();
+        let mut col = from;
+        while !self.expandtab && (col / tabstop + 1) * tabstop <= to {
+            whitespace.push('\t');
+            col = (col / tabstop + 1) * tabstop;
+        }
+        whitespace + &" ".repeat(to.saturating_sub(col))
+    }
+
+    /// Returns the number of display columns of text at the start of a line
+    pub fn width(&self, text: &str) -> usize {
+        grapheme::text_width(text, 0, self.tabstop)
+    }
+}
+
+impl Default for Filetype {
+    fn default() -> Self {
+        Self::new(4, true, &DEFAULT_PAIRS)
+    }
 }
 
 impl Config {
-    pub fn load(file: &std::path::Path) -> Result<Config, Box<dyn std::error::Error>> {
-        let contents = std::fs::read_to_string(file)?;
-        let conf: SerDeConfig = serde_yaml::from_str(&contents)?;
+    /// Returns the settings for the type of a file
+    pub fn filetype(&self, path: &Path) -> Filetype {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        extension.and_then(|extension| self.filetypes.get(extension)).cloned().unwrap_or_default()
+    }
+
+    pub fn load(file: &Path) -> Result<Config, Error> {
+        let contents = std::fs::read_to_string(file).map_err(|error| Error::Io { path: file.to_path_buf(), error })?;
+        let conf: SerDeConfig = serde_yaml::from_str(&contents).map_err(|e| Error::Config {
+            path: file.to_path_buf(),
+            error: e.to_string(),
+        })?;
         Ok(conf.into())
     }
 
-    pub fn write_default(file: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn write_default(file: &Path) -> Result<(), Error> {
+        let io_error = |error| Error::Io { path: file.to_path_buf(), error };
         if file.exists() {
-            return Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "File already exists",
-            )));
+            return Err(io_error(std::io::Error::other("File already exists")));
         }
 
         let conf = SerDeConfig::default();
-        let contents = serde_yaml::to_string(&conf)?;
-        std::fs::create_dir_all(file.parent().unwrap())?;
-        std::fs::write(file, &contents)?;
-        Ok(())
+        let contents = serde_yaml::to_string(&conf).map_err(|e| Error::Config {
+            path: file.to_path_buf(),
+            error: e.to_string(),
+        })?;
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir).map_err(io_error)?;
+        }
+        std::fs::write(file, &contents).map_err(io_error)
     }
 }
 
@@ -49,7 +201,27 @@ impl From<SerDeConfig> for Config {
         Config {
             line_nr_active: c.line_nr_active.into(),
             line_nr_column: c.line_nr_column.into(),
+            selection: c.selection.into(),
+            search: c.search.into(),
+            matching_bracket: c.matching_bracket.into(),
+            fold: c.fold.into(),
+            status_line: c.status_line.into(),
+            status_line_inactive: c.status_line_inactive.into(),
+            status_normal: c.status_normal.into(),
+            status_insert: c.status_insert.into(),
+            status_visual: c.status_visual.into(),
+            status: c.status,
+            message_info: c.message_info.into(),
+            message_warning: c.message_warning.into(),
+            message_error: c.message_error.into(),
+            message_timeout: Duration::from_millis(c.message_timeout),
+            popup: c.popup.into(),
+            popup_selected: c.popup_selected.into(),
+            popup_match: c.popup_match.into(),
+            log_level: c.log_level,
             hl: HighlightStyles::new(c.hl.keys().cloned().collect(), c.hl.into_values().map(ContentStyle::from).collect()),
+            filetypes: c.filetypes,
+            margins: c.margins,
         }
     }
 }
@@ -58,7 +230,175 @@ impl From<SerDeConfig> for Config {
 struct SerDeConfig {
     line_nr_active: Style,
     line_nr_column: Style,
+    #[serde(default = "default_selection")]
+    selection: Style,
+    #[serde(default = "default_search")]
+    search: Style,
+    #[serde(default = "default_matching_bracket")]
+    matching_bracket: Style,
+    #[serde(default = "default_fold")]
+    fold: Style,
+    #[serde(default = "default_status_line")]
+    status_line: Style,
+    #[serde(default = "default_status_line_inactive")]
+    status_line_inactive: Style,
+    #[serde(default = "default_status_normal")]
+    status_normal: Style,
+    #[serde(default = "default_status_insert")]
+    status_insert: Style,
+    #[serde(default = "default_status_visual")]
+    status_visual: Style,
+    #[serde(default)]
+    status: StatusLine,
+    #[serde(default = "default_message_info")]
+    message_info: Style,
+    #[serde(default = "default_message_warning")]
+    message_warning: Style,
+    #[serde(default = "default_message_error")]
+    message_error: Style,
+    /// In milliseconds
+    #[serde(default = "default_message_timeout")]
+    message_timeout: u64,
+    #[serde(default = "default_popup")]
+    popup: Style,
+    #[serde(default = "default_popup_selected")]
+    popup_selected: Style,
+    #[serde(default = "default_popup_match")]
+    popup_match: Style,
+    #[serde(default = "default_log_level")]
+    log_level: LevelFilter,
     hl: HashMap<String, Style>,
+    #[serde(default = "default_filetypes")]
+    filetypes: HashMap<String, Filetype>,
+    #[serde(default)]
+    margins: Margins,
+}
+
+fn default_filetypes() -> HashMap<String, Filetype> {
+    let c = (Some("//"), Some(("/*", "*/")));
+    let hash = (Some("#"), None);
+    let mut filetypes: HashMap<String, Filetype> =
+        [("go", 4, false, c), ("js", 2, true, c), ("json", 2, true, (None, None)), ("ts", 2, true, c), ("yaml", 2, true, hash), ("yml", 2, true, hash)]
+            .iter()
+            .map(|(extension, shiftwidth, expandtab, (line, block))| {
+                (extension.to_string(), Filetype::new(*shiftwidth, *expandtab, &DEFAULT_PAIRS).comments(*line, *block))
+            })
+            .collect();
+    // Single quotes aren't paired in Rust because of lifetimes, angle brackets are paired in HTML
+    filetypes.insert(String::from("rs"), Filetype::new(4, true, &["()", "[]", "{}", "\"\""]));
+    filetypes.insert(String::from("md"), Filetype::new(2, true, &DEFAULT_PAIRS).wrapped());
+    filetypes.insert(
+        String::from("html"),
+        Filetype::new(2, true, &["()", "[]", "{}", "\"\"", "''", "<>"]).comments(None, Some(("<!--", "-->"))),
+    );
+    filetypes
+}
+
+fn default_selection() -> Style {
+    Style::new().attr(Attribute::Reversed)
+}
+
+fn default_search() -> Style {
+    Style::new()
+        .bg(Color::Rgb {
+            r: 90,
+            g: 80,
+            b: 20,
+        })
+}
+
+fn default_matching_bracket() -> Style {
+    Style::new()
+        .bg(Color::Rgb {
+            r: 70,
+            g: 70,
+            b: 70,
+        })
+        .attr(Attribute::Bold)
+}
+
+fn default_fold() -> Style {
+    Style::new()
+        .fg(Color::Grey)
+        .bg(Color::Rgb {
+            r: 40,
+            g: 40,
+            b: 50,
+        })
+}
+
+fn default_status_line() -> Style {
+    Style::new()
+        .fg(Color::White)
+        .bg(Color::Rgb {
+            r: 50,
+            g: 50,
+            b: 60,
+        })
+}
+
+fn default_status_line_inactive() -> Style {
+    Style::new()
+        .fg(Color::Grey)
+        .bg(Color::Rgb {
+            r: 30,
+            g: 30,
+            b: 35,
+        })
+}
+
+fn default_status_normal() -> Style {
+    Style::new().fg(Color::Black).bg(Color::Blue).attr(Attribute::Bold)
+}
+
+fn default_status_insert() -> Style {
+    Style::new().fg(Color::Black).bg(Color::Green).attr(Attribute::Bold)
+}
+
+fn default_status_visual() -> Style {
+    Style::new().fg(Color::Black).bg(Color::Magenta).attr(Attribute::Bold)
+}
+
+fn default_message_info() -> Style {
+    Style::new()
+}
+
+fn default_message_warning() -> Style {
+    Style::new().fg(Color::Yellow)
+}
+
+fn default_message_error() -> Style {
+    Style::new().fg(Color::White).bg(Color::DarkRed).attr(Attribute::Bold)
+}
+
+fn default_message_timeout() -> u64 {
+    4000
+}
+
+fn default_popup() -> Style {
+    Style::new()
+        .fg(Color::White)
+        .bg(Color::Rgb {
+            r: 35,
+            g: 35,
+            b: 45,
+        })
+}
+
+fn default_popup_selected() -> Style {
+    Style::new().bg(Color::Rgb {
+        r: 60,
+        g: 60,
+        b: 80,
+    })
+}
+
+fn default_popup_match() -> Style {
+    Style::new().fg(Color::Yellow).attr(Attribute::Bold)
+}
+
+fn default_log_level() -> LevelFilter {
+    LevelFilter::Info
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -236,7 +576,27 @@ impl Default for SerDeConfig {
                     b: 80,
                 })
                 .bg(Color::Black),
-            hl: hl_types.zip(hl_styles.into_iter()).collect(),
+            selection: default_selection(),
+            search: default_search(),
+            matching_bracket: default_matching_bracket(),
+            fold: default_fold(),
+            status_line: default_status_line(),
+            status_line_inactive: default_status_line_inactive(),
+            status_normal: default_status_normal(),
+            status_insert: default_status_insert(),
+            status_visual: default_status_visual(),
+            status: StatusLine::default(),
+            message_info: default_message_info(),
+            message_warning: default_message_warning(),
+            message_error: default_message_error(),
+            message_timeout: default_message_timeout(),
+            popup: default_popup(),
+            popup_selected: default_popup_selected(),
+            popup_match: default_popup_match(),
+            log_level: default_log_level(),
+            hl: hl_types.zip(hl_styles).collect(),
+            filetypes: default_filetypes(),
+            margins: Margins::default(),
         }
     }
 }
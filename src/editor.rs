@@ -0,0 +1,931 @@
+use crate::{
+    action::{Action, BufferAction},
+    anchor::{Anchor, Gravity},
+    buffer::Buffer,
+    config::Config,
+    jumplist::Jump,
+    logger,
+    message::{Error, History, Message},
+    picker::{self, Location, Locations, Open, Picker, PickerKind, Source},
+    quickfix::{self, GrepEvent, Quickfix},
+    render::Renderer,
+    replace::Preview,
+    substitute,
+    utils::{BufCharIdx, Selection},
+    window::{Window, WindowKind},
+};
+use crossterm::{
+    cursor::{RestorePosition, SavePosition},
+    event,
+    event::{DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers},
+    execute,
+    terminal::{self, DisableLineWrap, EnableLineWrap, EnterAlternateScreen, LeaveAlternateScreen},
+    Result,
+};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::mpsc::Receiver,
+    time::Duration,
+};
+use log::trace;
+use regex::Regex;
+
+/// The maximum height of the quickfix window
+const QUICKFIX_HEIGHT: u16 = 10;
+/// The minimum height of a window opened in a split, including its status line
+const MIN_HEIGHT: u16 = 3;
+
+pub struct Editor {
+    windows: Vec<Window>,
+    config: Config,
+    selected_window: usize,
+    /// The file window that was selected last, which quickfix jumps open files in
+    previous_window: usize,
+    /// Buffers that were opened before but aren't shown in any window
+    hidden: Vec<Buffer>,
+    quickfix: Quickfix,
+    /// The replacements shown in the preview window
+    preview: Option<Preview>,
+    /// The results of the project search that is still running
+    grep: Option<Receiver<GrepEvent>>,
+    /// The messages reported by all windows, which `:messages` lists
+    messages: History,
+    /// The picker that is open, drawn over the windows
+    picker: Option<Picker>,
+    /// The renderer used to draw popups
+    renderer: Renderer,
+    width: u16,
+    height: u16,
+}
+
+impl Editor {
+    pub fn new(path: PathBuf, config: Config) -> Self {
+        let (width, height) = terminal::size().unwrap();
+        let mut editor = Editor {
+            windows: vec![Window::new(path, config.clone())],
+            config,
+            selected_window: 0,
+            previous_window: 0,
+            hidden: Vec::new(),
+            quickfix: Quickfix::default(),
+            preview: None,
+            grep: None,
+            messages: History::default(),
+            picker: None,
+            renderer: Renderer::new(),
+            width,
+            height,
+        };
+        editor.select_window(0);
+        editor.layout();
+        editor
+    }
+
+    pub fn run<W: Write>(&mut self, w: &mut W) -> Result<()> {
+        terminal::enable_raw_mode()?;
+        execute!(
+            w,
+            SavePosition,
+            EnterAlternateScreen,
+            EnableMouseCapture,
+            DisableLineWrap,
+        )?;
+        self.draw_all()?;
+        loop {
+            // Wait for input only briefly, so that search results can be received in between
+            if event::poll(Duration::from_millis(50))? {
+                let input = event::read()?;
+                if let Event::Key(KeyEvent { code: KeyCode::Char('c'), modifiers: KeyModifiers::CONTROL }) = input {
+                    self.quit(w)?;
+                } else {
+                    self.handle_input(input)?;
+                }
+            }
+            self.receive_grep_results()?;
+            self.receive_picker_items()?;
+            self.collect_messages();
+            self.window_mut().expire_message()?;
+        }
+    }
+
+    /// Shows a message in the selected window and adds it to the history of `:messages`
+    pub fn report(&mut self, message: impl Into<Message>) {
+        self.window_mut().report(message);
+    }
+
+    /// Adds the messages the windows reported to the history
+    fn collect_messages(&mut self) {
+        for window in self.windows.iter_mut() {
+            for message in window.pending_messages.drain(..) {
+                self.messages.push(message);
+            }
+        }
+    }
+
+    /// Shows the messages reported since the editor started
+    pub fn list_messages(&mut self) {
+        self.collect_messages();
+        let message = match self.messages.is_empty() {
+            true => Message::info("No messages"),
+            false => Message::info(self.messages.lines().join("\n")),
+        };
+        self.window_mut().message = Some(message);
+    }
+
+    pub fn update_size(&mut self, width: u16, height: u16) {
+        self.width = width;
+        self.height = height;
+        self.layout();
+    }
+
+    /// Stacks the windows on top of each other, with the quickfix window at the bottom
+    fn layout(&mut self) {
+        // The last row is used for the command line
+        let rows = self.height - 1;
+        let list_height = match self.quickfix_window() {
+            Some(_) => u16::min(QUICKFIX_HEIGHT, rows / 2),
+            None => 0,
+        };
+        let files = self.windows.iter().filter(|w| w.kind != WindowKind::Quickfix).count() as u16;
+        let file_height = (rows - list_height) / files;
+        let (width, mut y, mut i) = (self.width, 0, 0);
+        for window in self.windows.iter_mut() {
+            let height = match window.kind {
+                WindowKind::Quickfix => list_height,
+                // The last window takes up the rows that are left after dividing
+                _ if i + 1 == files => rows - list_height - y,
+                _ => file_height,
+            };
+            if window.kind != WindowKind::Quickfix {
+                i += 1;
+            }
+            window.resize(width, height, y, rows);
+            y += height;
+        }
+    }
+
+    pub fn window_mut(&mut self) -> &mut Window {
+        self.windows
+            .get_mut(self.selected_window)
+            .expect("Window index was out of range for editor")
+    }
+
+    /// Gives the keyboard focus to a window
+    fn select_window(&mut self, i: usize) {
+        if self.windows[self.selected_window].kind == WindowKind::File {
+            self.previous_window = self.selected_window;
+        }
+        for (j, window) in self.windows.iter_mut().enumerate() {
+            window.focused = i == j;
+        }
+        self.selected_window = i;
+    }
+
+    /// Draws every window, the selected one last so that it gets the cursor
+    pub fn draw_all(&mut self) -> Result<()> {
+        let selected = self.selected_window;
+        for (i, window) in self.windows.iter_mut().enumerate() {
+            if i != selected {
+                window.draw_all()?;
+            }
+        }
+        self.window_mut().draw_all()?;
+        if self.picker.is_some() {
+            return self.draw_picker();
+        }
+        self.window_mut().flush()
+    }
+
+    pub fn handle_input(&mut self, event: Event) -> Result<()> {
+        match event {
+            Event::Resize(width, height) => {
+                self.update_size(width, height);
+                self.draw_all()?;
+            }
+            Event::Key(event) if self.picker.is_some() => self.handle_picker_key(event)?,
+            Event::Key(event) => {
+                // A message with multiple lines covers other windows, which have to be redrawn when it disappears
+                let covered = self.window_mut().message.as_ref().is_some_and(Message::is_multiline);
+                self.window_mut().handle_keyevent(event)?;
+                self.add_pending_jumps(self.selected_window);
+                if let Some(action) = self.window_mut().editor_action.take() {
+                    trace!("{:?}", action);
+                    action.apply(self)?;
+                    self.draw_all()?;
+                } else if covered {
+                    self.draw_all()?;
+                }
+            }
+            Event::Mouse(_event) => (),
+        }
+        Ok(())
+    }
+
+    /// Opens a picker over the windows, or the list of pickers if `kind` is `None`
+    pub fn open_picker(&mut self, kind: Option<PickerKind>) {
+        let source: Box<dyn Source> = match kind {
+            None => Box::new(picker::Pickers),
+            Some(PickerKind::Files) => Box::new(picker::Files::new(quickfix::project_root())),
+            Some(PickerKind::Commands) => Box::new(picker::Commands),
+            Some(PickerKind::Searches) => {
+                let history = self.windows[self.selected_window].search_history();
+                Box::new(picker::Searches::new(history.iter().rev().cloned().collect()))
+            }
+            Some(PickerKind::Buffers) => Box::new(Locations::new("Buffers", self.buffer_locations())),
+            Some(PickerKind::Marks) => Box::new(Locations::new("Marks", self.mark_locations())),
+            Some(PickerKind::Jumps) => Box::new(Locations::new("Jumps", self.jump_locations())),
+            Some(PickerKind::Symbols) => Box::new(Locations::new("Symbols", self.symbol_locations())),
+        };
+        self.picker = Some(Picker::new(source));
+    }
+
+    /// Returns the buffers of all files that were opened, the selected one first
+    fn buffer_locations(&self) -> Vec<(String, Location)> {
+        let current = &self.windows[self.selected_window].buf;
+        let others = self.buffers().filter(|buf| !std::ptr::eq(*buf, current));
+        std::iter::once(current)
+            .chain(others)
+            .map(|buf| {
+                let text = format!("{}{}", buf.path.display(), if buf.edited { " [+]" } else { "" });
+                (text, location(buf, buf.idx))
+            })
+            .collect()
+    }
+
+    /// Returns the marks of the selected buffer, followed by the uppercase marks of all buffers, with the buffer each
+    /// mark is in
+    fn marks(&self) -> Vec<(char, BufCharIdx, &Buffer)> {
+        let current = &self.windows[self.selected_window].buf;
+        let local = current.all_marks().into_iter().filter(|(name, _)| !name.is_ascii_uppercase()).map(|(name, idx)| (name, idx, current));
+        let mut global: Vec<_> = self
+            .buffers()
+            .flat_map(|buf| {
+                buf.all_marks().into_iter().filter(|(name, _)| name.is_ascii_uppercase()).map(move |(name, idx)| (name, idx, buf))
+            })
+            .collect();
+        global.sort_by_key(|(name, _, _)| *name);
+        local.chain(global).collect()
+    }
+
+    /// Returns the marks of the selected buffer, followed by the uppercase marks of all buffers
+    fn mark_locations(&self) -> Vec<(String, Location)> {
+        self.marks()
+            .into_iter()
+            .map(|(name, idx, buf)| {
+                let row = buf.char_to_row(idx);
+                let text = format!("{} {}:{}  {}", name, buf.path.display(), *row + 1, buf.line_str(row).trim());
+                (text, location(buf, idx))
+            })
+            .collect()
+    }
+
+    /// Returns the jump list of the last selected file window, the latest jump first
+    fn jump_locations(&self) -> Vec<(String, Location)> {
+        let i = match self.windows[self.selected_window].kind {
+            WindowKind::File => self.selected_window,
+            _ => self.previous_window,
+        };
+        self.windows[i]
+            .jumps
+            .iter()
+            .rev()
+            .filter_map(|jump| {
+                let buf = self.find_buffer(&jump.path)?;
+                let idx = buf.anchors.idx(jump.anchor)?;
+                let row = buf.char_to_row(idx);
+                let text = format!("{}:{}  {}", buf.path.display(), *row + 1, buf.line_str(row).trim());
+                Some((text, location(buf, idx)))
+            })
+            .collect()
+    }
+
+    /// Returns the definitions in the selected buffer, in the order they appear
+    fn symbol_locations(&mut self) -> Vec<(String, Location)> {
+        let buf = &mut self.window_mut().buf;
+        buf.update_syntax();
+        let symbols = buf.syntax.as_ref().map(|syntax| syntax.symbols(&buf.text)).unwrap_or_default();
+        symbols
+            .into_iter()
+            .map(|symbol| {
+                let idx = buf.text.byte_to_char(symbol.byte).into();
+                (format!("{} {}", symbol.kind, symbol.name), location(buf, idx))
+            })
+            .collect()
+    }
+
+    /// Adds the items the picker's source sent since the last call, and redraws it if there were any
+    fn receive_picker_items(&mut self) -> Result<()> {
+        if self.picker.as_mut().is_some_and(Picker::receive) {
+            self.draw_picker()?;
+        }
+        Ok(())
+    }
+
+    fn draw_picker(&mut self) -> Result<()> {
+        if let Some(picker) = &mut self.picker {
+            picker.draw(&mut self.renderer, &self.config, self.width, self.height)?;
+            self.renderer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Handles a key pressed while a picker is open. Typing narrows down the items, Tab marks them, Enter picks them
+    /// for the selected window and Ctrl-S for a new window below it.
+    fn handle_picker_key(&mut self, event: KeyEvent) -> Result<()> {
+        let picker = match &mut self.picker {
+            Some(picker) => picker,
+            None => return Ok(()),
+        };
+        let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+        let open = match event.code {
+            KeyCode::Enter => Some(Open::Window),
+            KeyCode::Char('s') | KeyCode::Char('x') if ctrl => Some(Open::Split),
+            KeyCode::Esc => None,
+            KeyCode::Up => return self.move_picker_selection(-1),
+            KeyCode::Down => return self.move_picker_selection(1),
+            KeyCode::Char('p') if ctrl => return self.move_picker_selection(-1),
+            KeyCode::Char('n') if ctrl => return self.move_picker_selection(1),
+            KeyCode::Tab => {
+                picker.toggle_mark(1);
+                return self.draw_picker();
+            }
+            KeyCode::BackTab => {
+                picker.toggle_mark(-1);
+                return self.draw_picker();
+            }
+            KeyCode::Char('u') if ctrl => {
+                picker.set_query(String::new());
+                return self.draw_picker();
+            }
+            KeyCode::Backspace => {
+                let mut query = picker.query.clone();
+                query.pop();
+                picker.set_query(query);
+                return self.draw_picker();
+            }
+            KeyCode::Char(c) if !ctrl => {
+                picker.set_query(format!("{}{}", picker.query, c));
+                return self.draw_picker();
+            }
+            _ => return Ok(()),
+        };
+        let picker = self.picker.take();
+        if let (Some(open), Some(picker)) = (open, picker) {
+            picker.pick(self, open);
+        }
+        self.draw_all()
+    }
+
+    fn move_picker_selection(&mut self, amount: isize) -> Result<()> {
+        if let Some(picker) = &mut self.picker {
+            picker.select(amount);
+        }
+        self.draw_picker()
+    }
+
+    /// Opens a file in the last selected file window, or in a new window below it
+    pub fn open_file(&mut self, path: &Path, open: Open) {
+        if self.windows[self.selected_window].kind != WindowKind::File {
+            self.select_window(self.previous_window);
+        }
+        let result = match open {
+            Open::Window => {
+                self.push_jump(self.selected_window);
+                self.show_file(self.selected_window, path).map_err(|error| Error::Io { path: path.to_path_buf(), error })
+            }
+            Open::Split => self.split(path),
+        };
+        if let Err(e) = result {
+            self.report(e);
+        }
+    }
+
+    /// Opens a file like `open_file` and moves the cursor to a row and column of it
+    pub fn show_location(&mut self, path: &Path, row: usize, col: usize, open: Open) {
+        self.open_file(path, open);
+        let buf = &mut self.window_mut().buf;
+        if !same_file(&buf.path, path) {
+            return;
+        }
+        let row = usize::min(row, *buf.last_row()).into();
+        let idx = buf.row_to_char(row) + usize::min(col, *buf.max_col(row)).into();
+        let col = buf.display_col(idx);
+        buf.apply(BufferAction::MoveTo(idx, col)).unwrap_or(());
+    }
+
+    /// Shows a file in a new window below the selected one, and selects it
+    fn split(&mut self, path: &Path) -> std::result::Result<(), Error> {
+        let files = self.windows.iter().filter(|w| w.kind != WindowKind::Quickfix).count() as u16;
+        if (self.height - 1) / (files + 1) < MIN_HEIGHT {
+            return Err(Error::Action("Not enough room for another window"));
+        }
+        let buf = match self.hidden.iter().position(|buf| same_file(&buf.path, path)) {
+            Some(j) => self.hidden.remove(j),
+            None => Buffer::open(path.to_path_buf()).map_err(|error| Error::Io { path: path.to_path_buf(), error })?,
+        };
+        let i = self.selected_window + 1;
+        self.windows.insert(i, Window::with_buffer(buf, WindowKind::File, self.config.clone()));
+        if self.previous_window >= i {
+            self.previous_window += 1;
+        }
+        self.layout();
+        self.select_window(i);
+        Ok(())
+    }
+
+    /// Starts searching the project in the background, the results replace the quickfix list
+    pub fn grep(&mut self, regex: Regex, pattern: String) {
+        self.quickfix = Quickfix::new(format!(":grep {}", pattern));
+        self.grep = Some(quickfix::grep(regex, quickfix::project_root()));
+        self.refresh_quickfix_window();
+    }
+
+    /// Adds the results that were found since the last call to the quickfix list
+    fn receive_grep_results(&mut self) -> Result<()> {
+        let receiver = match &self.grep {
+            Some(receiver) => receiver,
+            None => return Ok(()),
+        };
+        let mut received = false;
+        let mut done = false;
+        while let Ok(event) = receiver.try_recv() {
+            received = true;
+            match event {
+                GrepEvent::Found(entry) => self.quickfix.entries.push(entry),
+                GrepEvent::Done => done = true,
+            }
+        }
+        if done {
+            self.grep = None;
+            let message = match self.quickfix.entries.len() {
+                0 => Message::warning(format!("No matches for {}", self.quickfix.title)),
+                1 => Message::info(format!("1 match for {}", self.quickfix.title)),
+                n => Message::info(format!("{} matches for {}", n, self.quickfix.title)),
+            };
+            self.report(message);
+        }
+        if received {
+            self.refresh_quickfix_window();
+            self.draw_all()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the index of the quickfix entry that was jumped to last
+    pub fn quickfix_idx(&self) -> usize {
+        self.quickfix.idx
+    }
+
+    pub fn selected_window(&self) -> usize {
+        self.selected_window
+    }
+
+    pub fn quickfix_window(&self) -> Option<usize> {
+        self.windows.iter().position(|w| w.kind == WindowKind::Quickfix)
+    }
+
+    /// Shows the quickfix list below the other windows and selects it
+    pub fn open_quickfix(&mut self) {
+        let i = match self.quickfix_window() {
+            Some(i) => i,
+            None => {
+                let buf = self.quickfix.buffer();
+                self.windows.push(Window::with_buffer(buf, WindowKind::Quickfix, self.config.clone()));
+                self.layout();
+                self.windows.len() - 1
+            }
+        };
+        self.refresh_quickfix_window();
+        self.select_window(i);
+    }
+
+    /// Closes a window, unless it is the last window showing a file
+    pub fn close_window(&mut self, i: usize) {
+        let kind = self.windows[i].kind;
+        let files = self.windows.iter().filter(|w| w.kind == WindowKind::File).count();
+        if kind == WindowKind::File && files == 1 {
+            self.report(Error::Action("Cannot close the last window"));
+            return;
+        }
+        if self.selected_window == i {
+            let next = match kind {
+                WindowKind::File => self.windows.iter().position(|w| w.kind == WindowKind::File).filter(|j| *j != i),
+                _ => None,
+            };
+            self.select_window(next.unwrap_or(self.previous_window));
+        }
+        let window = self.windows.remove(i);
+        match window.kind {
+            WindowKind::File => self.hidden.push(window.buf),
+            WindowKind::Preview => self.preview = None,
+            WindowKind::Quickfix => (),
+        }
+        if self.selected_window > i {
+            self.selected_window -= 1;
+        }
+        if self.previous_window >= i {
+            self.previous_window = self.previous_window.saturating_sub(1);
+        }
+        self.select_window(self.selected_window);
+        self.layout();
+    }
+
+    /// Updates the contents of the quickfix window, with the cursor on the current entry
+    fn refresh_quickfix_window(&mut self) {
+        let buf = self.quickfix.buffer();
+        let idx = self.quickfix.idx;
+        if let Some(i) = self.quickfix_window() {
+            let window = &mut self.windows[i];
+            window.set_buffer(buf);
+            let row = usize::min(idx, *window.buf.last_row()).into();
+            let start = window.buf.row_to_char(row);
+            window.buf.apply(BufferAction::MoveTo(start, 0.into())).unwrap_or(());
+        }
+    }
+
+    /// Jumps to an entry of the quickfix list, opening its file in the last selected file window
+    pub fn jump_to_entry(&mut self, idx: usize) -> Result<()> {
+        let count = self.quickfix.entries.len();
+        if count == 0 {
+            self.report(Error::Action("No entries in the quickfix list"));
+            return Ok(());
+        }
+        let idx = usize::min(idx, count - 1);
+        self.quickfix.idx = idx;
+        let target = match self.windows[self.selected_window].kind {
+            WindowKind::File => self.selected_window,
+            WindowKind::Quickfix | WindowKind::Preview => self.previous_window,
+        };
+        let entry = &self.quickfix.entries[idx];
+        let (path, row, col) = (entry.path.clone(), entry.row, entry.col);
+        let message = format!("({} of {}): {}", idx + 1, count, entry.text.trim());
+        self.refresh_quickfix_window();
+        self.select_window(target);
+        self.push_jump(target);
+        if let Err(e) = self.show_file(target, &path) {
+            self.report(Message::error(format!("Could not open {}: {}", path.display(), e)));
+            return Ok(());
+        }
+        let buf = &mut self.window_mut().buf;
+        let row = usize::min(*row, *buf.last_row()).into();
+        let idx = buf.row_to_char(row) + usize::min(*col, *buf.max_col(row)).into();
+        let col = buf.display_col(idx);
+        buf.apply(BufferAction::MoveTo(idx, col)).unwrap_or(());
+        self.window_mut().message = Some(Message::info(message));
+        Ok(())
+    }
+
+    /// Jumps to the entry after the current one, or before it if `reverse` is set
+    pub fn jump_to_next_entry(&mut self, reverse: bool) -> Result<()> {
+        let count = self.quickfix.entries.len();
+        let next = match reverse {
+            true => self.quickfix.idx.checked_sub(1),
+            false => Some(self.quickfix.idx + 1).filter(|idx| *idx < count),
+        };
+        match next {
+            Some(idx) => self.jump_to_entry(idx),
+            None if count == 0 => self.jump_to_entry(0),
+            None => {
+                self.report(Error::Action("No more items"));
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the lines of a file, from its buffer if it was opened
+    fn file_lines(&self, path: &Path) -> Option<Vec<String>> {
+        let text = match self.find_buffer(path) {
+            Some(buf) => buf.text.to_string(),
+            None => fs::read_to_string(path).ok()?,
+        };
+        Some(text.lines().map(String::from).collect())
+    }
+
+    fn find_buffer(&self, path: &Path) -> Option<&Buffer> {
+        self.buffers().find(|buf| same_file(&buf.path, path))
+    }
+
+    /// Returns the buffers of all files that were opened
+    fn buffers(&self) -> impl Iterator<Item = &Buffer> {
+        let shown = self.windows.iter().filter(|w| w.kind == WindowKind::File).map(|w| &w.buf);
+        shown.chain(self.hidden.iter())
+    }
+
+    fn buffers_mut(&mut self) -> impl Iterator<Item = &mut Buffer> {
+        let shown = self.windows.iter_mut().filter(|w| w.kind == WindowKind::File).map(|w| &mut w.buf);
+        shown.chain(self.hidden.iter_mut())
+    }
+
+    /// Returns the buffer of a file, opening it if it wasn't opened before
+    fn buffer_mut(&mut self, path: &Path) -> io::Result<&mut Buffer> {
+        let shown = self.windows.iter().position(|w| w.kind == WindowKind::File && same_file(&w.buf.path, path));
+        if let Some(i) = shown {
+            return Ok(&mut self.windows[i].buf);
+        }
+        let i = match self.hidden.iter().position(|buf| same_file(&buf.path, path)) {
+            Some(i) => i,
+            None => {
+                self.hidden.push(Buffer::open(path.to_path_buf())?);
+                self.hidden.len() - 1
+            }
+        };
+        Ok(&mut self.hidden[i])
+    }
+
+    /// Opens a window below the other windows that previews replacing matches in the lines of the quickfix list
+    pub fn preview_replace(&mut self, regex: &Regex, replacement: &str, global: bool) {
+        if self.quickfix.entries.is_empty() {
+            self.report(Error::Action("No entries in the quickfix list"));
+            return;
+        }
+        let preview = Preview::new(
+            &self.quickfix.entries,
+            |line| substitute::replace_line(regex, line, replacement, global),
+            |path| self.file_lines(path),
+        );
+        if preview.hits.is_empty() {
+            self.report(Error::Command(format!("Pattern not found: {}", regex)));
+            return;
+        }
+        if preview.has_line_breaks() {
+            self.report(Error::Action("Cannot preview replacing with line breaks"));
+            return;
+        }
+        let buf = Buffer::scratch("[Replace Preview]", &preview.text(|path| self.file_lines(path)));
+        let (lines, files) = (plural(preview.hits.len(), "line"), plural(preview.paths().len(), "file"));
+        let message = format!("{} in {}, :w applies the replacements", lines, files);
+        if let Some(i) = self.windows.iter().position(|w| w.kind == WindowKind::Preview) {
+            self.close_window(i);
+        }
+        self.preview = Some(preview);
+        self.windows.push(Window::with_buffer(buf, WindowKind::Preview, self.config.clone()));
+        self.select_window(self.windows.len() - 1);
+        self.layout();
+        self.report(Message::info(message));
+    }
+
+    /// Applies the replacements in the edited text of the preview buffer, each file is changed in a single undo step
+    pub fn apply_preview(&mut self, text: &str) {
+        let preview = match self.preview.take() {
+            Some(preview) => preview,
+            None => return,
+        };
+        let replacements = preview.parse(text);
+        let mut changed = Vec::new();
+        let mut lines = 0;
+        let mut errors = Vec::new();
+        for path in preview.paths() {
+            let mut replacements: Vec<_> = replacements.iter().filter(|(hit, _)| hit.path == path).collect();
+            // Lines are replaced from the bottom up, so the positions of the lines above stay the same
+            replacements.sort_by_key(|(hit, _)| std::cmp::Reverse(*hit.row));
+            let buf = match self.buffer_mut(path) {
+                Ok(buf) => buf,
+                Err(e) => {
+                    errors.push(format!("{}: {}", path.display(), e));
+                    continue;
+                }
+            };
+            let mut actions = Vec::new();
+            for (hit, replaced) in replacements {
+                // Lines that were changed since the preview was made are left alone
+                if *hit.row > *buf.last_row() || buf.line_str(hit.row) != hit.original.as_str() || *replaced == hit.original {
+                    continue;
+                }
+                let start = buf.row_to_char(hit.row);
+                let end = start + hit.original.chars().count().into();
+                actions.push(BufferAction::Delete(Selection::Bounds(start, end)));
+                actions.push(BufferAction::InsertAt(start, replaced.clone()));
+            }
+            if actions.is_empty() {
+                continue;
+            }
+            lines += actions.len() / 2;
+            if let Err(e) = buf.apply(BufferAction::Batch(actions)) {
+                errors.push(format!("{}: {}", path.display(), e));
+                continue;
+            }
+            changed.push(path.display().to_string());
+        }
+        self.preview = Some(preview);
+        if let Some(i) = self.windows.iter().position(|w| w.kind == WindowKind::Preview) {
+            self.close_window(i);
+        }
+        self.report(match (errors.is_empty(), changed.is_empty()) {
+            (false, _) => Message::error(format!("Could not change {}", errors.join(", "))),
+            (true, true) => Message::warning("Nothing was changed"),
+            (true, false) => Message::info(format!("Changed {} in {}, :wa saves them", plural(lines, "line"), changed.join(", "))),
+        });
+    }
+
+    /// Sets an uppercase mark in the selected buffer, and removes it from all other buffers
+    pub fn set_global_mark(&mut self, name: char) {
+        for buf in self.buffers_mut() {
+            buf.delete_mark(name);
+        }
+        let buf = &mut self.window_mut().buf;
+        buf.set_mark(name, buf.idx);
+    }
+
+    /// Jumps to an uppercase mark, showing its buffer in the last selected file window
+    pub fn jump_to_global_mark(&mut self, name: char, line: bool) {
+        let path = self.buffers().find(|buf| buf.marks.contains_key(&name)).map(|buf| buf.path.clone());
+        let path = match path {
+            Some(path) => path,
+            None => {
+                self.report(Error::Action("Mark not set"));
+                return;
+            }
+        };
+        if self.windows[self.selected_window].kind != WindowKind::File {
+            self.select_window(self.previous_window);
+        }
+        self.push_jump(self.selected_window);
+        if let Err(e) = self.show_file(self.selected_window, &path) {
+            self.report(Message::error(format!("Could not open {}: {}", path.display(), e)));
+            return;
+        }
+        if let Err(e) = self.window_mut().buf.apply(BufferAction::JumpToMark { name, line }) {
+            self.report(Error::Action(e));
+        }
+    }
+
+    /// Adds the cursor position of a window to its jump list
+    fn push_jump(&mut self, i: usize) {
+        let buf = &mut self.windows[i].buf;
+        let anchor = buf.anchors.add(Anchor::point(buf.idx, Gravity::Right));
+        let jump = Jump { path: buf.path.clone(), anchor };
+        self.add_jump(i, jump);
+    }
+
+    /// Adds the positions a window jumped away from while handling a key to its jump list
+    fn add_pending_jumps(&mut self, i: usize) {
+        let window = &mut self.windows[i];
+        let path = window.buf.path.clone();
+        for anchor in std::mem::take(&mut window.pending_jumps) {
+            self.add_jump(i, Jump { path: path.clone(), anchor });
+        }
+    }
+
+    /// Adds a jump to the jump list of a window, replacing jumps to the same line and jumps whose buffer was closed
+    fn add_jump(&mut self, i: usize, jump: Jump) {
+        let line = self.jump_line(&jump);
+        let mut jumps = std::mem::take(&mut self.windows[i].jumps);
+        let dropped = jumps.push(jump, |other| {
+            let other = self.jump_line(other);
+            other.is_none() || other == line
+        });
+        self.windows[i].jumps = jumps;
+        for jump in dropped {
+            if let Some(buf) = self.buffers_mut().find(|buf| same_file(&buf.path, &jump.path)) {
+                buf.anchors.delete(jump.anchor);
+            }
+        }
+    }
+
+    /// Returns the file and row a jump leads to
+    fn jump_line(&self, jump: &Jump) -> Option<(PathBuf, usize)> {
+        let buf = self.find_buffer(&jump.path)?;
+        let idx = buf.anchors.idx(jump.anchor)?;
+        Some((buf.path.clone(), *buf.char_to_row(idx)))
+    }
+
+    /// Moves through the jump list of the last selected file window, skipping jumps to the line of the cursor
+    pub fn jump(&mut self, older: bool) {
+        if self.windows[self.selected_window].kind != WindowKind::File {
+            self.select_window(self.previous_window);
+        }
+        let i = self.selected_window;
+        if older && self.windows[i].jumps.at_end() {
+            // Remembers where the cursor was, so that `Ctrl-I` can return to it
+            self.push_jump(i);
+        }
+        let buf = &self.windows[i].buf;
+        let current = Some((buf.path.clone(), *buf.row()));
+        loop {
+            let jumps = &mut self.windows[i].jumps;
+            let jump = match if older { jumps.back() } else { jumps.forward() } {
+                Some(jump) => jump.clone(),
+                None => return,
+            };
+            let line = self.jump_line(&jump);
+            if line.is_none() || line == current {
+                continue;
+            }
+            if let Err(e) = self.show_file(i, &jump.path) {
+                self.report(Message::error(format!("Could not open {}: {}", jump.path.display(), e)));
+                return;
+            }
+            let buf = &mut self.windows[i].buf;
+            if let Some(idx) = buf.anchors.idx(jump.anchor) {
+                let col = buf.display_col(idx);
+                buf.apply(BufferAction::MoveTo(idx, col)).unwrap_or(());
+            }
+            return;
+        }
+    }
+
+    /// Shows the marks of the selected buffer, followed by the uppercase marks of all buffers
+    pub fn list_marks(&mut self) {
+        let current = &self.windows[self.selected_window].buf;
+        let mut lines = vec![String::from("mark line  col file/text")];
+        for (name, idx, buf) in self.marks() {
+            let (row, col) = (buf.char_to_row(idx), buf.char_to_col(idx));
+            let text = match std::ptr::eq(buf, current) {
+                true => buf.line_str(row).trim().to_string(),
+                false => buf.path.display().to_string(),
+            };
+            lines.push(format!(" {} {:>6} {:>4} {}", name, *row + 1, *col, text));
+        }
+        self.window_mut().message = Some(Message::info(lines.join("\n")));
+    }
+
+    /// Saves every buffer that was edited
+    pub fn write_all(&mut self) {
+        let mut written = 0;
+        let mut errors = Vec::new();
+        for buf in self.buffers_mut().filter(|buf| buf.edited) {
+            match buf.write() {
+                Ok(()) => written += 1,
+                Err(e) => errors.push(format!("{}: {}", buf.path.display(), e)),
+            }
+        }
+        self.report(match errors.is_empty() {
+            true => Message::info(format!("{} written", plural(written, "file"))),
+            false => Message::error(format!("Could not write {}", errors.join(", "))),
+        });
+    }
+
+    /// Shows the log file in the last selected file window. It is read again every time, as it grows while the editor
+    /// runs.
+    pub fn open_log(&mut self) {
+        let path = logger::path();
+        let mut buf = match Buffer::open(path.clone()) {
+            Ok(buf) => buf,
+            Err(e) => return self.report(Message::error(format!("Could not open {}: {}", path.display(), e))),
+        };
+        buf.readonly = true;
+        if self.windows[self.selected_window].kind != WindowKind::File {
+            self.select_window(self.previous_window);
+        }
+        self.push_jump(self.selected_window);
+        self.hidden.retain(|buf| !same_file(&buf.path, &path));
+        let old = self.window_mut().set_buffer(buf);
+        if !same_file(&old.path, &path) {
+            self.hidden.push(old);
+        }
+        let buf = &mut self.window_mut().buf;
+        let start = buf.row_to_char(buf.last_row());
+        buf.apply(BufferAction::MoveTo(start, 0.into())).unwrap_or(());
+    }
+
+    /// Shows a file in a window, reusing the buffer if the file was opened before
+    fn show_file(&mut self, i: usize, path: &Path) -> io::Result<()> {
+        if same_file(&self.windows[i].buf.path, path) {
+            return Ok(());
+        }
+        let buf = match self.hidden.iter().position(|buf| same_file(&buf.path, path)) {
+            Some(j) => self.hidden.remove(j),
+            None => Buffer::open(path.to_path_buf())?,
+        };
+        let old = self.windows[i].set_buffer(buf);
+        self.hidden.push(old);
+        Ok(())
+    }
+
+    /// Cleans up and quits the application
+    fn quit<W: Write>(&mut self, w: &mut W) -> Result<()> {
+        execute!(
+            w,
+            DisableMouseCapture,
+            LeaveAlternateScreen,
+            RestorePosition,
+            EnableLineWrap,
+        )?;
+        terminal::disable_raw_mode()?;
+        process::exit(0);
+    }
+}
+
+/// Formats an amount of things, such as `1 line` or `3 lines`
+fn plural(amount: usize, thing: &str) -> String {
+    match amount {
+        1 => format!("1 {}", thing),
+        amount => format!("{} {}s", amount, thing),
+    }
+}
+
+/// Returns a position in a buffer for a picker
+fn location(buf: &Buffer, idx: BufCharIdx) -> Location {
+    Location { path: buf.path.clone(), text: buf.text.clone(), row: *buf.char_to_row(idx), col: *buf.char_to_col(idx) }
+}
+
+/// Whether two paths point to the same file
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
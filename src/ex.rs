@@ -0,0 +1,420 @@
+use crate::{
+    buffer::Buffer,
+    picker::PickerKind,
+    substitute::Flags,
+    utils::BufRow,
+};
+
+/// A command typed on the command line after `:`
+#[derive(Clone)]
+pub enum ExCommand {
+    /// Stops highlighting the matches of the last search until the next search
+    NoHighlight,
+    /// Deletes the lines in the range
+    Delete { range: LineRange },
+    /// Moves the lines in the range below the line at `dest`, which may be 0 to move them to the top
+    Move { range: LineRange, dest: LineAddress },
+    /// Types keys in normal mode on every line in the range
+    Normal { range: LineRange, keys: String },
+    /// Searches all files in the project and fills the quickfix list with the matches
+    Grep { pattern: String },
+    /// Shows the quickfix list in a window
+    QuickfixOpen,
+    QuickfixClose,
+    /// Jumps to the next entry of the quickfix list, or the previous one if `reverse` is set
+    QuickfixNext { reverse: bool },
+    /// Jumps to the entry of the quickfix list with the given number, or the current one
+    QuickfixGoto(Option<usize>),
+    /// Previews replacing matches in the lines of the quickfix list, an empty pattern uses the last search
+    ProjectReplace {
+        pattern: String,
+        replacement: String,
+        flags: Flags,
+    },
+    /// Saves the buffer, or applies the replacements of a preview buffer
+    Write,
+    /// Saves every buffer that was edited
+    WriteAll,
+    /// Closes the selected window
+    Close,
+    /// Lists the marks
+    Marks,
+    /// Lists the messages reported since the editor started
+    Messages,
+    /// Shows the log file in a read-only buffer
+    Log,
+    /// Opens a picker, or the list of pickers if none is given
+    Pick(Option<PickerKind>),
+    /// Executes a command on every line that matches a pattern, or that doesn't if `invert` is set
+    Global {
+        range: LineRange,
+        pattern: String,
+        invert: bool,
+        command: Box<ExCommand>,
+    },
+    /// Replaces matches of a pattern, an empty pattern uses the last search
+    Substitute {
+        range: LineRange,
+        pattern: String,
+        replacement: String,
+        flags: Flags,
+    },
+}
+
+/// The names of the commands with what they do, which the command picker lists
+pub const COMMANDS: [(&str, &str); 21] = [
+    ("nohlsearch", "Stop highlighting the matches of the last search"),
+    ("substitute", "Replace matches of a pattern in the lines"),
+    ("delete", "Delete the lines"),
+    ("move", "Move the lines below another line"),
+    ("normal", "Type keys in normal mode on every line"),
+    ("grep", "Search the files of the project"),
+    ("vimgrep", "Search the files of the project for a delimited pattern"),
+    ("copen", "Show the quickfix list"),
+    ("cclose", "Close the quickfix list"),
+    ("cnext", "Jump to the next quickfix entry"),
+    ("cprevious", "Jump to the previous quickfix entry"),
+    ("cc", "Jump to a quickfix entry"),
+    ("creplace", "Preview replacing matches in the quickfix lines"),
+    ("write", "Save the buffer"),
+    ("wall", "Save every edited buffer"),
+    ("close", "Close the window"),
+    ("marks", "List the marks"),
+    ("messages", "List the reported messages"),
+    ("log", "Show the log file"),
+    ("pick", "Open a picker"),
+    ("global", "Execute a command on the lines that match a pattern"),
+];
+
+impl ExCommand {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let (range, rest) = LineRange::parse(input.trim())?;
+        let rest = rest.trim_start();
+        let name_len = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+        let (name, args) = rest.split_at(name_len);
+        let current = range.unwrap_or_else(LineRange::current);
+        if abbreviates(name, "nohlsearch", 3) {
+            Ok(ExCommand::NoHighlight)
+        } else if abbreviates(name, "substitute", 1) {
+            let (parts, flags) = split_delimited(args, 2)?;
+            let mut parts = parts.into_iter();
+            Ok(ExCommand::Substitute {
+                range: current,
+                pattern: parts.next().unwrap_or_default(),
+                replacement: parts.next().unwrap_or_default(),
+                flags: Flags::parse(flags)?,
+            })
+        } else if abbreviates(name, "delete", 1) {
+            Ok(ExCommand::Delete { range: current })
+        } else if abbreviates(name, "move", 1) {
+            match LineAddress::parse(args.trim())? {
+                (Some(dest), "") => Ok(ExCommand::Move { range: current, dest }),
+                _ => Err(String::from("Expected a line to move to")),
+            }
+        } else if abbreviates(name, "normal", 4) {
+            let keys = args.strip_prefix(' ').unwrap_or(args);
+            Ok(ExCommand::Normal { range: current, keys: keys.to_string() })
+        } else if abbreviates(name, "grep", 2) {
+            match args.trim() {
+                "" => Err(String::from("Expected a pattern to search for")),
+                pattern => Ok(ExCommand::Grep { pattern: pattern.to_string() }),
+            }
+        } else if abbreviates(name, "vimgrep", 3) {
+            let (mut parts, _) = split_delimited(args.trim_start(), 1)?;
+            Ok(ExCommand::Grep { pattern: parts.remove(0) })
+        } else if abbreviates(name, "copen", 4) {
+            Ok(ExCommand::QuickfixOpen)
+        } else if abbreviates(name, "cclose", 3) {
+            Ok(ExCommand::QuickfixClose)
+        } else if abbreviates(name, "cnext", 2) {
+            Ok(ExCommand::QuickfixNext { reverse: false })
+        } else if abbreviates(name, "cprevious", 2) {
+            Ok(ExCommand::QuickfixNext { reverse: true })
+        } else if name == "cc" {
+            match args.trim() {
+                "" => Ok(ExCommand::QuickfixGoto(None)),
+                nr => nr.parse().map(|nr| ExCommand::QuickfixGoto(Some(nr))).map_err(|_| String::from("Invalid entry number")),
+            }
+        } else if abbreviates(name, "creplace", 4) {
+            let (parts, flags) = split_delimited(args, 2)?;
+            let mut parts = parts.into_iter();
+            Ok(ExCommand::ProjectReplace {
+                pattern: parts.next().unwrap_or_default(),
+                replacement: parts.next().unwrap_or_default(),
+                flags: Flags::parse(flags)?,
+            })
+        } else if abbreviates(name, "write", 1) {
+            Ok(ExCommand::Write)
+        } else if abbreviates(name, "wall", 2) {
+            Ok(ExCommand::WriteAll)
+        } else if abbreviates(name, "close", 3) {
+            Ok(ExCommand::Close)
+        } else if abbreviates(name, "marks", 4) {
+            Ok(ExCommand::Marks)
+        } else if abbreviates(name, "messages", 3) {
+            Ok(ExCommand::Messages)
+        } else if name == "log" {
+            Ok(ExCommand::Log)
+        } else if abbreviates(name, "pick", 2) {
+            match args.trim() {
+                "" => Ok(ExCommand::Pick(None)),
+                kind => PickerKind::parse(kind).map(|kind| ExCommand::Pick(Some(kind))).ok_or(format!("No picker named {}", kind)),
+            }
+        } else if abbreviates(name, "global", 1) || abbreviates(name, "vglobal", 1) {
+            let (invert, args) = match args.strip_prefix('!') {
+                Some(args) => (true, args),
+                None => (name.starts_with('v'), args),
+            };
+            let (mut parts, command) = split_delimited(args, 1)?;
+            let command = match ExCommand::parse(command)? {
+                ExCommand::Global { .. } => return Err(String::from("Cannot use :global recursively")),
+                command => command,
+            };
+            Ok(ExCommand::Global {
+                range: range.unwrap_or_else(LineRange::all),
+                pattern: parts.remove(0),
+                invert,
+                command: Box::new(command),
+            })
+        } else {
+            Err(format!("Not an editor command: {}", input.trim()))
+        }
+    }
+}
+
+/// Whether `name` is `command` shortened to at least `min_len` characters
+fn abbreviates(name: &str, command: &str, min_len: usize) -> bool {
+    name.len() >= min_len && command.starts_with(name)
+}
+
+/// Splits `count` parts off arguments like `/pattern/replacement/flags`, separated by the delimiter they
+/// start with, and returns the text after them. An escaped delimiter is unescaped, all other escapes are kept.
+fn split_delimited(args: &str, count: usize) -> Result<(Vec<String>, &str), String> {
+    let mut chars = args.char_indices();
+    let delimiter = match chars.next() {
+        Some((_, c)) if !c.is_alphanumeric() && c != '\\' && c != '"' && c != ' ' => c,
+        _ => return Err(String::from("Expected a delimiter such as /")),
+    };
+    let mut parts = vec![String::new()];
+    while let Some((i, c)) = chars.next() {
+        if c == delimiter {
+            if parts.len() == count {
+                return Ok((parts, &args[i + c.len_utf8()..]));
+            }
+            parts.push(String::new());
+            continue;
+        }
+        let part = parts.last_mut().unwrap();
+        match c {
+            '\\' => match chars.next() {
+                Some((_, c)) if c == delimiter => part.push(c),
+                Some((_, c)) => {
+                    part.push('\\');
+                    part.push(c);
+                }
+                None => part.push('\\'),
+            },
+            c => part.push(c),
+        }
+    }
+    parts.resize(count, String::new());
+    Ok((parts, ""))
+}
+
+/// The base of a line address
+#[derive(Clone, Copy)]
+pub enum Address {
+    /// A line number, starting at 1
+    Line(usize),
+    Current,
+    Last,
+    Mark(char),
+}
+
+/// A line in a range such as `.`, `$`, `'a` or `12`, followed by an offset such as `+3` or `-`
+#[derive(Clone, Copy)]
+pub struct LineAddress {
+    pub base: Address,
+    pub offset: isize,
+}
+
+impl LineAddress {
+    pub fn new(base: Address, offset: isize) -> Self {
+        Self { base, offset }
+    }
+
+    /// Parses an address at the start of the input, and returns the rest of the input
+    fn parse(input: &str) -> Result<(Option<Self>, &str), String> {
+        let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (base, mut rest) = match input.chars().next() {
+            Some('.') => (Some(Address::Current), &input[1..]),
+            Some('$') => (Some(Address::Last), &input[1..]),
+            Some('\'') => match input[1..].chars().next() {
+                Some(c) => (Some(Address::Mark(c)), &input[1 + c.len_utf8()..]),
+                None => return Err(String::from("Missing mark name")),
+            },
+            Some(c) if c.is_ascii_digit() => {
+                let len = digits(input);
+                let line = input[..len].parse().map_err(|_| String::from("Invalid line number"))?;
+                (Some(Address::Line(line)), &input[len..])
+            }
+            _ => (None, input),
+        };
+        let mut offset: Option<isize> = None;
+        while let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+            let len = digits(&rest[1..]);
+            let amount = if len == 0 { 1 } else { rest[1..1 + len].parse().map_err(|_| String::from("Invalid offset"))? };
+            *offset.get_or_insert(0) += if sign == '+' { amount } else { -amount };
+            rest = &rest[1 + len..];
+        }
+        Ok(match (base, offset) {
+            (None, None) => (None, rest),
+            (base, offset) => (Some(LineAddress::new(base.unwrap_or(Address::Current), offset.unwrap_or(0))), rest),
+        })
+    }
+
+    /// Returns the line number of the address, which is 0 for the position above the first line
+    pub fn line(&self, buf: &Buffer) -> Result<usize, String> {
+        let line = match self.base {
+            Address::Line(line) => line,
+            Address::Current => *buf.row() + 1,
+            Address::Last => *buf.last_row() + 1,
+            Address::Mark(c) => *buf.char_to_row(buf.mark(c).ok_or_else(|| format!("Mark not set: {}", c))?) + 1,
+        } as isize + self.offset;
+        if line < 0 || line as usize > *buf.last_row() + 1 {
+            return Err(String::from("Invalid range"));
+        }
+        Ok(line as usize)
+    }
+
+    pub fn row(&self, buf: &Buffer) -> Result<BufRow, String> {
+        Ok(self.line(buf)?.saturating_sub(1).into())
+    }
+}
+
+/// A range of lines such as `%`, `'<,'>` or `.,+5`
+#[derive(Clone, Copy)]
+pub struct LineRange {
+    pub start: LineAddress,
+    pub end: LineAddress,
+}
+
+impl LineRange {
+    /// The line the cursor is on
+    pub fn current() -> Self {
+        let current = LineAddress::new(Address::Current, 0);
+        Self { start: current, end: current }
+    }
+
+    /// Every line in the buffer
+    pub fn all() -> Self {
+        Self {
+            start: LineAddress::new(Address::Line(1), 0),
+            end: LineAddress::new(Address::Last, 0),
+        }
+    }
+
+    /// Parses a range at the start of the input, and returns the rest of the input
+    pub fn parse(input: &str) -> Result<(Option<Self>, &str), String> {
+        if let Some(rest) = input.strip_prefix('%') {
+            return Ok((Some(Self::all()), rest));
+        }
+        let (start, rest) = LineAddress::parse(input)?;
+        let rest = rest.trim_start();
+        match rest.strip_prefix(',').or_else(|| rest.strip_prefix(';')) {
+            Some(rest) => {
+                let start = start.unwrap_or(LineAddress::new(Address::Current, 0));
+                let (end, rest) = LineAddress::parse(rest.trim_start())?;
+                Ok((Some(Self { start, end: end.unwrap_or(start) }), rest))
+            }
+            None => Ok((start.map(|start| Self { start, end: start }), rest)),
+        }
+    }
+
+    /// Returns the first and last row of the range
+    pub fn rows(&self, buf: &Buffer) -> Result<(BufRow, BufRow), String> {
+        let start = self.start.row(buf)?;
+        let end = self.end.row(buf)?;
+        Ok(if *end < *start { (end, start) } else { (start, end) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A buffer of five lines with the cursor on the second, `'a` on the third and `'b` on the fifth
+    fn buffer() -> Buffer {
+        let mut buf = Buffer::scratch("test", "one\ntwo\nthree\nfour\nfive\n");
+        buf.idx = buf.row_to_char(1.into());
+        buf.set_mark('a', buf.row_to_char(2.into()));
+        buf.set_mark('b', buf.row_to_char(4.into()));
+        buf
+    }
+
+    fn rows(input: &str) -> Result<(usize, usize), String> {
+        let (range, rest) = LineRange::parse(input)?;
+        assert_eq!(rest, "d");
+        let (start, end) = range.expect("Expected a range").rows(&buffer())?;
+        Ok((*start, *end))
+    }
+
+    #[test]
+    fn parse_ranges() {
+        assert_eq!(rows("%d"), Ok((0, 4)));
+        assert_eq!(rows("2,4d"), Ok((1, 3)));
+        assert_eq!(rows(".,+2d"), Ok((1, 3)));
+        assert_eq!(rows(".;+3d"), Ok((1, 4)));
+        assert_eq!(rows("'a,'bd"), Ok((2, 4)));
+        assert_eq!(rows("$-1,$d"), Ok((3, 4)));
+        assert_eq!(rows("-,+d"), Ok((0, 2)));
+        assert_eq!(rows("4,2d"), Ok((1, 3)));
+        assert_eq!(rows("3d"), Ok((2, 2)));
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        assert_eq!(rows("'z,.d"), Err(String::from("Mark not set: z")));
+        assert_eq!(rows("1,9d"), Err(String::from("Invalid range")));
+        assert_eq!(rows(".-5d"), Err(String::from("Invalid range")));
+        assert!(LineRange::parse("'").is_err());
+        assert!(matches!(LineRange::parse("d"), Ok((None, "d"))));
+    }
+
+    #[test]
+    fn split_escaped_delimiters() {
+        assert_eq!(split_delimited(r"/a\/b/c/g", 2), Ok((vec![String::from("a/b"), String::from("c")], "g")));
+        assert_eq!(split_delimited("#a/b#c#", 2), Ok((vec![String::from("a/b"), String::from("c")], "")));
+        // Other escapes are left for the regex and the replacement
+        assert_eq!(split_delimited(r"/a\.b/\1/", 2), Ok((vec![String::from(r"a\.b"), String::from(r"\1")], "")));
+        assert_eq!(split_delimited("/a", 2), Ok((vec![String::from("a"), String::new()], "")));
+        assert!(split_delimited("abc", 2).is_err());
+    }
+
+    #[test]
+    fn parse_substitute() {
+        match ExCommand::parse("%s/a\\/b/c/gi") {
+            Ok(ExCommand::Substitute { range, pattern, replacement, flags }) => {
+                assert!(matches!(range.start.base, Address::Line(1)) && matches!(range.end.base, Address::Last));
+                assert_eq!((pattern.as_str(), replacement.as_str()), ("a/b", "c"));
+                assert!(flags.global && !flags.confirm && flags.ignore_case == Some(true));
+            }
+            _ => panic!("Expected a substitution"),
+        }
+        assert!(matches!(ExCommand::parse("s/a/b/z"), Err(e) if e == "Unknown substitute flag: z"));
+    }
+
+    #[test]
+    fn parse_global() {
+        match ExCommand::parse("g!/x/s//y/") {
+            Ok(ExCommand::Global { pattern, invert, command, .. }) => {
+                assert_eq!(pattern, "x");
+                assert!(invert);
+                assert!(matches!(*command, ExCommand::Substitute { .. }));
+            }
+            _ => panic!("Expected :global"),
+        }
+        assert!(matches!(ExCommand::parse("v/x/d"), Ok(ExCommand::Global { invert: true, .. })));
+        assert!(ExCommand::parse("g/x/g/y/d").is_err());
+    }
+}
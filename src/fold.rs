@@ -0,0 +1,289 @@
+use crate::{
+    anchor::{Anchor, AnchorId, Gravity},
+    buffer::Buffer,
+    config::FoldMethod,
+    indent::indentation,
+    utils::{BufCharIdx, BufRange, BufRow},
+};
+use std::collections::HashMap;
+
+/// What `z` commands do to the folds at the cursor
+#[derive(Clone, Copy, Debug)]
+pub enum FoldCommand {
+    /// Opens the closed fold at the cursor, or closes the innermost open one
+    Toggle,
+    /// Opens the closed fold at the cursor
+    Open,
+    /// Closes the innermost open fold around the line of the cursor
+    Close,
+    OpenAll,
+    CloseAll,
+    /// Folds the lines of the selection
+    Create,
+    /// Deletes the innermost fold created with `zf` around the cursor
+    Delete,
+}
+
+/// A range of lines that can be hidden behind a single line, kept up to date by an anchor from the start of its first
+/// line to the start of its last line
+pub struct Fold {
+    anchor: AnchorId,
+    closed: bool,
+    /// Whether the fold was created with `zf`, instead of found with the `foldmethod` of the file
+    manual: bool,
+}
+
+/// The outermost closed folds of a buffer as first and last rows, ordered by their first row
+pub struct ClosedFolds(Vec<(usize, usize)>);
+
+impl ClosedFolds {
+    /// Returns the closed fold that hides a row
+    pub fn containing(&self, row: usize) -> Option<(usize, usize)> {
+        let i = self.0.partition_point(|(_, last)| *last < row);
+        self.0.get(i).copied().filter(|(first, _)| *first <= row)
+    }
+
+    /// Returns the first row of the line a row is shown on, which is the first row of its fold if it is folded
+    pub fn first(&self, row: usize) -> usize {
+        self.containing(row).map_or(row, |(first, _)| first)
+    }
+
+    /// Returns the last row of the line a row is shown on
+    pub fn last(&self, row: usize) -> usize {
+        self.containing(row).map_or(row, |(_, last)| last)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the row `amount` lines above a row, counting closed folds as a single line
+    pub fn up(&self, row: usize, amount: usize) -> usize {
+        let mut row = self.first(row);
+        for _ in 0..amount {
+            if row == 0 {
+                break;
+            }
+            row = self.first(row - 1);
+        }
+        row
+    }
+
+    /// Returns the row `amount` lines below a row, but not after `last`
+    pub fn down(&self, row: usize, amount: usize, last: usize) -> usize {
+        let mut row = self.first(row);
+        for _ in 0..amount {
+            let end = self.last(row);
+            if end >= last {
+                break;
+            }
+            row = end + 1;
+        }
+        row
+    }
+}
+
+/// Returns the first and last row of a fold, or `None` if edits have left it with a single row
+fn rows(buf: &Buffer, fold: &Fold) -> Option<(usize, usize)> {
+    let range = buf.anchors.range(fold.anchor)?;
+    let (first, last) = (*buf.char_to_row(range.start), *buf.char_to_row(range.end));
+    Some((first, last)).filter(|_| last > first)
+}
+
+/// Returns the first and last row of every fold, ordered by their first row
+pub fn all(buf: &Buffer) -> Vec<(usize, usize)> {
+    let mut folds: Vec<_> = buf.folds.iter().filter_map(|fold| rows(buf, fold)).collect();
+    folds.sort_unstable();
+    folds
+}
+
+/// Returns the closed folds, leaving out the ones hidden inside others
+pub fn closed(buf: &Buffer) -> ClosedFolds {
+    let mut folds: Vec<_> = buf.folds.iter().filter(|fold| fold.closed).filter_map(|fold| rows(buf, fold)).collect();
+    folds.sort_by_key(|&(first, last)| (first, std::cmp::Reverse(last)));
+    let mut outermost: Vec<(usize, usize)> = Vec::with_capacity(folds.len());
+    for (first, last) in folds {
+        match outermost.last_mut() {
+            Some((_, end)) if first <= *end => *end = usize::max(*end, last),
+            _ => outermost.push((first, last)),
+        }
+    }
+    ClosedFolds(outermost)
+}
+
+/// Returns the ranges of lines that start with a line followed by lines that are indented further, up to the last
+/// non-blank line among them
+fn by_indent(buf: &Buffer) -> Vec<(usize, usize)> {
+    let mut folds = Vec::new();
+    // The first row and indentation of every line that may still be followed by lines indented further
+    let mut open: Vec<(usize, usize)> = Vec::new();
+    let mut previous = 0;
+    for row in 0..buf.text.len_lines() {
+        let line = buf.line_str(row.into());
+        if line.trim().is_empty() {
+            continue;
+        }
+        let width = buf.filetype.width(indentation(&line));
+        while let Some(&(first, _)) = open.last().filter(|(_, indent)| *indent >= width) {
+            open.pop();
+            if previous > first {
+                folds.push((first, previous));
+            }
+        }
+        open.push((row, width));
+        previous = row;
+    }
+    folds.extend(open.into_iter().filter(|(first, _)| previous > *first).map(|(first, _)| (first, previous)));
+    folds.sort_unstable();
+    folds
+}
+
+/// Finds the folds of the `foldmethod` of the file again. The folds that still start on the same row stay closed if
+/// they were, and the ones that no longer exist are deleted.
+pub fn update(buf: &mut Buffer) {
+    buf.update_syntax();
+    let found = match (buf.filetype.foldmethod, &buf.syntax) {
+        (FoldMethod::Manual, _) => Vec::new(),
+        (FoldMethod::Syntax, Some(syntax)) => syntax.folds(),
+        _ => by_indent(buf),
+    };
+    let mut folds = Vec::with_capacity(found.len());
+    let mut by_first: HashMap<usize, Fold> = HashMap::new();
+    for fold in std::mem::take(&mut buf.folds) {
+        match rows(buf, &fold) {
+            Some(_) if fold.manual => folds.push(fold),
+            Some((first, _)) => {
+                if let Some(duplicate) = by_first.insert(first, fold) {
+                    buf.anchors.delete(duplicate.anchor);
+                }
+            }
+            None => buf.anchors.delete(fold.anchor),
+        }
+    }
+    for (first, last) in found {
+        let range = BufRange::new(buf.row_to_char(first.into()), buf.row_to_char(last.into()));
+        match by_first.remove(&first) {
+            Some(fold) => {
+                buf.anchors.set(fold.anchor, range);
+                folds.push(fold);
+            }
+            None => folds.push(Fold { anchor: buf.anchors.add(anchor(range)), closed: false, manual: false }),
+        }
+    }
+    for fold in by_first.into_values() {
+        buf.anchors.delete(fold.anchor);
+    }
+    buf.folds = folds;
+}
+
+/// Text inserted before the first or last line of a fold stays outside of it, line breaks inserted at the start of the
+/// last line make the fold grow
+fn anchor(range: BufRange) -> Anchor {
+    Anchor::range(range, Gravity::Right, Gravity::Right).delete_on_collapse()
+}
+
+/// Returns the indices of the folds that contain the rows from `first` to `last`, innermost first
+fn around(buf: &Buffer, first: usize, last: usize) -> Vec<usize> {
+    let mut around: Vec<_> = buf
+        .folds
+        .iter()
+        .enumerate()
+        .filter_map(|(i, fold)| Some((i, rows(buf, fold)?)))
+        .filter(|(_, (start, end))| *start <= first && last <= *end)
+        .collect();
+    around.sort_by_key(|(_, (start, end))| end - start);
+    around.into_iter().map(|(i, _)| i).collect()
+}
+
+/// Applies a fold command at the cursor, finding the folds again first
+pub fn apply(buf: &mut Buffer, command: FoldCommand) -> Result<(), &'static str> {
+    update(buf);
+    let (first, last) = {
+        let closed = closed(buf);
+        let row = *buf.row();
+        (closed.first(row), closed.last(row))
+    };
+    let around = around(buf, first, last);
+    match command {
+        FoldCommand::Toggle if last > first => return apply_folded(buf, FoldCommand::Open, &around),
+        FoldCommand::Toggle => return apply_folded(buf, FoldCommand::Close, &around),
+        FoldCommand::Open | FoldCommand::Close | FoldCommand::Delete => return apply_folded(buf, command, &around),
+        FoldCommand::OpenAll | FoldCommand::CloseAll => {
+            for fold in buf.folds.iter_mut() {
+                fold.closed = matches!(command, FoldCommand::CloseAll);
+            }
+        }
+        FoldCommand::Create => {
+            let selection = buf.selection().ok_or("Nothing selected to fold")?;
+            let (first, last) = (buf.char_to_row(selection.start), buf.char_to_row(selection.end - 1.into()));
+            if last <= first {
+                return Err("A fold needs more than one line");
+            }
+            let range = BufRange::new(buf.row_to_char(first), buf.row_to_char(last));
+            buf.folds.push(Fold { anchor: buf.anchors.add(anchor(range)), closed: true, manual: true });
+        }
+    }
+    show_cursor(buf);
+    Ok(())
+}
+
+/// Applies a command to a single fold out of the folds around the line of the cursor
+fn apply_folded(buf: &mut Buffer, command: FoldCommand, around: &[usize]) -> Result<(), &'static str> {
+    if around.is_empty() {
+        return Err("No fold found");
+    }
+    match command {
+        // The outermost closed fold is the one that is shown
+        FoldCommand::Open => {
+            if let Some(&i) = around.iter().rev().find(|&&i| buf.folds[i].closed) {
+                buf.folds[i].closed = false;
+            }
+        }
+        FoldCommand::Close => {
+            if let Some(&i) = around.iter().find(|&&i| !buf.folds[i].closed) {
+                buf.folds[i].closed = true;
+            }
+        }
+        _ => {
+            let &i = around.iter().find(|&&i| buf.folds[i].manual).ok_or("No fold created with zf found")?;
+            let fold = buf.folds.remove(i);
+            buf.anchors.delete(fold.anchor);
+        }
+    }
+    show_cursor(buf);
+    Ok(())
+}
+
+/// Moves the cursor out of the rows that are hidden, onto the line of the fold that hides them
+fn show_cursor(buf: &mut Buffer) {
+    let row = *buf.row();
+    let first = closed(buf).first(row);
+    if first != row {
+        buf.idx = buf.row_to_char(first.into()) + (*buf.display_to_col(first.into(), buf.saved_col)).into();
+    }
+}
+
+/// Returns the first non-blank character of the first row of the next fold, or of the last row of the previous fold
+/// if `backward` is set
+pub fn next(buf: &Buffer, backward: bool) -> BufCharIdx {
+    let row = *buf.row();
+    let folds = all(buf);
+    let dest = match backward {
+        true => folds.iter().map(|(_, last)| *last).filter(|last| *last < row).max(),
+        false => folds.iter().map(|(first, _)| *first).find(|first| *first > row),
+    };
+    match dest {
+        Some(dest) => {
+            let dest = BufRow(dest);
+            let indent = indentation(&buf.line_str(dest)).chars().count();
+            buf.row_to_char(dest) + usize::min(indent, *buf.max_col(dest)).into()
+        }
+        None => buf.idx,
+    }
+}
+
+/// Returns the text of the line shown in place of a closed fold
+pub fn summary(buf: &Buffer, first: BufRow, last: BufRow) -> String {
+    let line = buf.line_str(first);
+    format!("+--{:>4} lines: {}", *last - *first + 1, line.trim())
+}
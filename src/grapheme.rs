@@ -0,0 +1,34 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+/// Returns the number of columns a grapheme takes up on screen when it starts at display column `col`
+pub fn width(grapheme: &str, col: usize, tabstop: usize) -> usize {
+    let first = match grapheme.chars().next() {
+        Some(first) => first,
+        None => return 0,
+    };
+    if first == '\t' {
+        let tabstop = tabstop.max(1);
+        return tabstop - col % tabstop;
+    }
+    // Emoji joined by zero width joiners and characters with combining marks are drawn in the width of their first
+    // character, flags and emoji with the emoji presentation selector are drawn wide
+    if grapheme.contains('\u{fe0f}') || ('\u{1f1e6}'..='\u{1f1ff}').contains(&first) {
+        return 2;
+    }
+    first.width().unwrap_or(0)
+}
+
+/// Returns the number of columns of text that starts at display column `col`
+pub fn text_width(text: &str, col: usize, tabstop: usize) -> usize {
+    text.graphemes(true).fold(col, |end, grapheme| end + width(grapheme, end, tabstop)) - col
+}
+
+/// Returns the char offsets at which the graphemes of a line start, followed by the length of the line
+pub fn boundaries(line: &str) -> Vec<usize> {
+    let mut bounds = vec![0];
+    for grapheme in line.graphemes(true) {
+        bounds.push(bounds[bounds.len() - 1] + grapheme.chars().count());
+    }
+    bounds
+}
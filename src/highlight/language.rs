@@ -1,16 +1,75 @@
-use std::path::Path;
+use std::{convert::TryFrom, path::Path};
+use tree_sitter::QueryError;
 use tree_sitter_highlight::HighlightConfiguration;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Language {
     Rust,
     Toml,
 }
 
+impl Language {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::Rust => "rust",
+            Language::Toml => "toml",
+        }
+    }
+
+    pub fn grammar(&self) -> tree_sitter::Language {
+        match self {
+            Language::Rust => tree_sitter_rust::language(),
+            Language::Toml => tree_sitter_toml::language(),
+        }
+    }
+
+    /// The query whose `@indent` captures indent the lines inside them, and whose `@outdent` captures move the line
+    /// they start back by one level
+    pub fn indent_query(&self) -> &'static str {
+        match self {
+            Language::Rust => include_str!("queries/rust/indents.scm"),
+            Language::Toml => include_str!("queries/toml/indents.scm"),
+        }
+    }
+
+    /// The query whose `@fold` captures are the ranges of lines that can be folded
+    pub fn fold_query(&self) -> &'static str {
+        match self {
+            Language::Rust => include_str!("queries/rust/folds.scm"),
+            Language::Toml => include_str!("queries/toml/folds.scm"),
+        }
+    }
+
+    /// The query whose `@name` captures name the definitions listed by the symbol picker, and whose other capture
+    /// is the kind of the definition
+    pub fn symbol_query(&self) -> &'static str {
+        match self {
+            Language::Rust => include_str!("queries/rust/symbols.scm"),
+            Language::Toml => include_str!("queries/toml/symbols.scm"),
+        }
+    }
+
+    /// The token that starts a line comment
+    pub fn line_comment(&self) -> Option<&'static str> {
+        match self {
+            Language::Rust => Some("//"),
+            Language::Toml => Some("#"),
+        }
+    }
+
+    /// The tokens that start and end a block comment
+    pub fn block_comment(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Language::Rust => Some(("/*", "*/")),
+            Language::Toml => None,
+        }
+    }
+}
+
 pub fn detect(path: &Path) -> Option<Language> {
     Some(match path.extension() {
         None => return None,
-        Some(extension) => match extension.to_str().unwrap() {
+        Some(extension) => match extension.to_str()? {
             "rs" => Language::Rust,
             "toml" => Language::Toml,
             _ => return None
@@ -18,20 +77,22 @@ pub fn detect(path: &Path) -> Option<Language> {
     })
 }
 
-impl From<Language> for HighlightConfiguration {
-    fn from(lang: Language) -> HighlightConfiguration {
+impl TryFrom<Language> for HighlightConfiguration {
+    type Error = QueryError;
+
+    fn try_from(lang: Language) -> Result<HighlightConfiguration, QueryError> {
         match lang {
             Language::Rust => {
                 HighlightConfiguration::new(
                 tree_sitter_rust::language(),
                 tree_sitter_rust::HIGHLIGHT_QUERY,
-                "", "").unwrap()
+                "", "")
             }
             Language::Toml => {
                 HighlightConfiguration::new(
                 tree_sitter_toml::language(),
                 tree_sitter_toml::HIGHLIGHT_QUERY,
-                "", "").unwrap()
+                "", "")
             }
         }
     }
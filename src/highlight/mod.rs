@@ -2,8 +2,11 @@ use crate::{
     buffer::Buffer,
     config::HighlightStyles,
     highlight::language::Language,
+    message::Error,
+    utils::BufRange,
 };
-use crossterm::style::ContentStyle;
+use crossterm::style::{Color, ContentStyle};
+use std::convert::TryFrom;
 use tree_sitter_highlight::{HighlightConfiguration, HighlightEvent, Highlight};
 
 pub mod language;
@@ -13,51 +16,105 @@ pub struct Highlighter {
     conf: Option<HighlightConfiguration>,
     style: HighlightStyles,
     cache: Option<Vec<HighlightEvent>>,
+    /// The version of the buffer the cache was created for
+    version: usize,
 }
 
 impl Highlighter {
-    pub fn new(lang: Option<Language>, style: HighlightStyles) -> Self {
+    /// Creates a highlighter that shows text without highlighting until a language is set
+    pub fn new(style: HighlightStyles) -> Self {
         Self {
             hl: tree_sitter_highlight::Highlighter::new(),
-            conf: lang.map(|l| {
-                let mut hl_conf: HighlightConfiguration = l.into();
-                hl_conf.configure(&style.types);
-                hl_conf
-            }),
+            conf: None,
             style,
             cache: None,
+            version: 0,
         }
     }
 
-    pub fn has_hl(&self) -> bool {
-        self.cache.is_some()
+    /// Highlights text in a language from now on. If the queries of the language are invalid, the text isn't
+    /// highlighted.
+    pub fn set_language(&mut self, lang: Option<Language>) -> Result<(), Error> {
+        self.cache = None;
+        self.conf = None;
+        if let Some(lang) = lang {
+            let mut conf = HighlightConfiguration::try_from(lang).map_err(|e| Error::Highlight(e.to_string()))?;
+            conf.configure(&self.style.types);
+            self.conf = Some(conf);
+        }
+        Ok(())
+    }
+
+    /// Whether the cached highlights are up to date with the buffer
+    pub fn has_hl(&self, buf: &Buffer) -> bool {
+        self.cache.is_some() && self.version == buf.version
     }
 
     pub fn get_hl(&self) -> &[HighlightEvent] {
-        self.cache.as_ref().unwrap()
-    }
-
-    pub fn update_hl(&mut self, buf: &Buffer) {
-        match &self.conf {
-            None => self.cache = Some(vec![HighlightEvent::Source {start: 0, end: buf.text.len_bytes() - 1}]),
-            Some(conf) => {
-                self.cache = Some(
-                    self.hl.highlight(
-                        &conf,
-                        &buf.text.bytes().collect::<Vec<u8>>(),
-                        None,
-                        |_| None,
-                    )
-                    .unwrap()
-                    .map(|event| event.unwrap())
-                    .collect()
-                );
+        self.cache.as_deref().unwrap_or_default()
+    }
+
+    /// Highlights the text of the buffer. If highlighting fails, the text isn't highlighted from now on.
+    pub fn update_hl(&mut self, buf: &Buffer) -> Result<(), Error> {
+        self.version = buf.version;
+        let plain = vec![HighlightEvent::Source { start: 0, end: buf.text.len_bytes() }];
+        let conf = match &self.conf {
+            Some(conf) => conf,
+            None => {
+                self.cache = Some(plain);
+                return Ok(());
+            }
+        };
+        let text: Vec<u8> = buf.text.bytes().collect();
+        let events = self
+            .hl
+            .highlight(conf, &text, None, |_| None)
+            .and_then(|events| events.collect::<Result<Vec<_>, _>>());
+        match events {
+            Ok(events) => {
+                self.cache = Some(events);
+                Ok(())
+            }
+            Err(e) => {
+                self.cache = Some(plain);
+                self.conf = None;
+                Err(Error::Highlight(e.to_string()))
             }
         }
     }
 
-    pub fn get_style(&self, hl_type: &Highlight) -> &ContentStyle {
-        self.style.styles.get(hl_type.0).expect("Style index out of bounds for HighlightStyles instance. Perhaps the amount of types does not match the amount of styles.")
+    pub fn get_style(&self, hl_type: &Highlight) -> ContentStyle {
+        self.style.styles.get(hl_type.0).copied().unwrap_or_default()
     }
 }
 
+
+/// Ranges of text that are drawn with a style on top of the syntax highlighting
+pub struct Layer {
+    pub ranges: Vec<BufRange>,
+    pub style: ContentStyle,
+}
+
+impl Layer {
+    pub fn new(ranges: Vec<BufRange>, style: ContentStyle) -> Self {
+        Self { ranges, style }
+    }
+
+    /// Whether the layer covers the whole range
+    pub fn covers(&self, range: BufRange) -> bool {
+        self.ranges.iter().any(|r| *r.start <= *range.start && *range.end <= *r.end)
+    }
+}
+
+/// Combines two styles, the colors of `top` take precedence unless they are reset
+pub fn combine(base: &ContentStyle, top: &ContentStyle) -> ContentStyle {
+    let pick = |base: Option<Color>, top: Option<Color>| match top {
+        None | Some(Color::Reset) => base,
+        top => top,
+    };
+    ContentStyle {
+        foreground_color: pick(base.foreground_color, top.foreground_color),
+        background_color: pick(base.background_color, top.background_color),
+        attributes: base.attributes | top.attributes,
+    }
+}
@@ -0,0 +1,11 @@
+[
+  (enum_item)
+  (foreign_mod_item)
+  (function_item)
+  (impl_item)
+  (macro_definition)
+  (mod_item)
+  (struct_item)
+  (trait_item)
+  (union_item)
+] @fold
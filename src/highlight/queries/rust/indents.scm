@@ -0,0 +1,32 @@
+[
+  (arguments)
+  (array_expression)
+  (binary_expression)
+  (block)
+  (closure_parameters)
+  (declaration_list)
+  (enum_variant_list)
+  (field_declaration_list)
+  (field_expression)
+  (field_initializer_list)
+  (let_declaration)
+  (match_block)
+  (ordered_field_declaration_list)
+  (parameters)
+  (parenthesized_expression)
+  (struct_pattern)
+  (token_tree)
+  (tuple_expression)
+  (tuple_pattern)
+  (tuple_type)
+  (type_arguments)
+  (type_parameters)
+  (use_list)
+  (where_clause)
+] @indent
+
+[
+  ")"
+  "]"
+  "}"
+] @outdent
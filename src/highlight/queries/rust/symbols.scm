@@ -0,0 +1,12 @@
+(function_item name: (identifier) @name) @function
+(function_signature_item name: (identifier) @name) @function
+(struct_item name: (type_identifier) @name) @struct
+(enum_item name: (type_identifier) @name) @enum
+(union_item name: (type_identifier) @name) @union
+(trait_item name: (type_identifier) @name) @trait
+(impl_item type: (_) @name) @impl
+(mod_item name: (identifier) @name) @mod
+(const_item name: (identifier) @name) @const
+(static_item name: (identifier) @name) @static
+(type_item name: (type_identifier) @name) @type
+(macro_definition name: (identifier) @name) @macro
@@ -0,0 +1,232 @@
+use crate::{
+    action::BufferAction,
+    buffer::Buffer,
+    utils::{BufRow, Movement, Selection},
+};
+
+pub const BRACKETS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+/// Returns the whitespace at the start of a line
+pub fn indentation(line: &str) -> &str {
+    &line[..line.len() - line.trim_start_matches(&[' ', '\t'][..]).len()]
+}
+
+/// Returns the opening bracket of a closing bracket
+fn opener(closer: char) -> Option<char> {
+    BRACKETS.iter().find(|(_, close)| *close == closer).map(|(open, _)| *open)
+}
+
+/// Returns the closing bracket of an opening bracket
+fn closer(opener: char) -> Option<char> {
+    BRACKETS.iter().find(|(open, _)| *open == opener).map(|(_, close)| *close)
+}
+
+/// Returns the text of the cursor line before and after the cursor
+fn split_line(buf: &Buffer) -> (String, String) {
+    let line = buf.line_str(buf.row());
+    let byte = line.char_indices().nth(*buf.col()).map_or(line.len(), |(i, _)| i);
+    (line[..byte].to_string(), line[byte..].to_string())
+}
+
+/// Breaks the line at the cursor and indents the new line. Without a syntax tree, the new line is indented like the
+/// cursor line and one level deeper after an opening bracket. A line holding only indentation is left empty.
+pub fn new_line(buf: &Buffer) -> BufferAction {
+    let (before, after) = split_line(buf);
+    let indent = indentation(&before);
+    let close = before.trim_end().chars().last().and_then(closer).filter(|close| after.trim_start().starts_with(*close));
+    let syntax = buf.syntax.is_some();
+    if before.len() == indent.len() && after.trim().is_empty() && !(before.is_empty() && after.is_empty()) {
+        let start = buf.row_to_char(buf.row());
+        let end = start + (before.chars().count() + after.chars().count()).into();
+        let indent = if syntax { "" } else { indent };
+        let mut actions = vec![
+            BufferAction::Delete(Selection::Bounds(start, end)),
+            BufferAction::Insert(format!("\n{}", indent)),
+        ];
+        actions.extend(Some(BufferAction::IndentLine).filter(|_| syntax));
+        return BufferAction::Batch(actions);
+    }
+    if syntax {
+        return BufferAction::Batch(match close {
+            // The closing bracket goes on a line of its own below the cursor
+            Some(_) => vec![
+                BufferAction::Insert(String::from("\n\n")),
+                BufferAction::IndentLine,
+                BufferAction::Move(Movement::Up(1)),
+                BufferAction::IndentLine,
+            ],
+            None => vec![BufferAction::Insert(String::from("\n")), BufferAction::IndentLine],
+        });
+    }
+    let unit = buf.filetype.indent();
+    let inner = match before.trim_end().chars().last().and_then(closer) {
+        Some(_) => format!("\n{}{}", indent, unit),
+        None => return BufferAction::Insert(format!("\n{}", indent)),
+    };
+    if close.is_none() {
+        return BufferAction::Insert(inner);
+    }
+    let idx = buf.idx + inner.chars().count().into();
+    let col = buf.filetype.width(&format!("{}{}", indent, unit)).into();
+    BufferAction::Batch(vec![
+        BufferAction::Insert(inner),
+        BufferAction::Insert(format!("\n{}", indent)),
+        BufferAction::MoveTo(idx, col),
+    ])
+}
+
+/// Adds a line below the cursor line, or above it if `above` is set, with the same indentation
+pub fn open_line(buf: &Buffer, above: bool) -> BufferAction {
+    let line = buf.line_str(buf.row());
+    let start = buf.row_to_char(buf.row());
+    let end = start + line.chars().count().into();
+    if buf.syntax.is_some() {
+        return BufferAction::Batch(match above {
+            true => vec![
+                BufferAction::MoveTo(start, 0.into()),
+                BufferAction::Insert(String::from("\n")),
+                BufferAction::Move(Movement::Up(1)),
+                BufferAction::IndentLine,
+            ],
+            false => vec![
+                BufferAction::MoveTo(end, buf.display_col(end)),
+                BufferAction::Insert(String::from("\n")),
+                BufferAction::IndentLine,
+            ],
+        });
+    }
+    let indent = indentation(&line);
+    let len = indent.chars().count();
+    if above {
+        return BufferAction::Batch(vec![
+            BufferAction::MoveTo(start, 0.into()),
+            BufferAction::Insert(format!("{}\n", indent)),
+            BufferAction::MoveTo(start + len.into(), buf.filetype.width(indent).into()),
+        ]);
+    }
+    let deeper = line.trim_end().chars().last().and_then(closer).is_some();
+    let unit = if deeper { buf.filetype.indent() } else { String::new() };
+    BufferAction::Batch(vec![
+        BufferAction::MoveTo(end, buf.display_col(end)),
+        BufferAction::Insert(format!("\n{}{}", indent, unit)),
+    ])
+}
+
+/// Inserts a typed character, a closing bracket typed after only indentation is indented again
+pub fn type_char(buf: &Buffer, c: char) -> BufferAction {
+    let insert = BufferAction::Insert(c.to_string());
+    let (before, _) = split_line(buf);
+    if opener(c).is_none() || !before.trim().is_empty() {
+        return insert;
+    }
+    if buf.syntax.is_some() {
+        return BufferAction::Batch(vec![insert, BufferAction::IndentLine]);
+    }
+    let unit = buf.filetype.indent();
+    if !before.ends_with(&unit) {
+        return insert;
+    }
+    let start = buf.idx - unit.chars().count().into();
+    BufferAction::Batch(vec![BufferAction::Delete(Selection::Bounds(start, buf.idx)), insert])
+}
+
+/// Inserts a tab, or the whitespace up to the next multiple of `softtabstop` if it is set. The whitespace before the
+/// cursor is replaced along with it, so that tabs take the place of spaces where they fit.
+pub fn tab(buf: &Buffer) -> BufferAction {
+    let filetype = &buf.filetype;
+    let step = match filetype.softtabstop {
+        0 if !filetype.expandtab => return BufferAction::Insert(String::from("\t")),
+        0 => filetype.tabstop.max(1),
+        step => step,
+    };
+    let col = *buf.display_col(buf.idx);
+    replace_whitespace(buf, (col / step + 1) * step)
+}
+
+/// Deletes the character before the cursor, or the whitespace back to the previous multiple of `softtabstop` if it is
+/// set and there is only whitespace in between
+pub fn backspace(buf: &Buffer) -> BufferAction {
+    let step = buf.filetype.softtabstop;
+    let (before, _) = split_line(buf);
+    if step == 0 || !before.ends_with(&[' ', '\t'][..]) {
+        return BufferAction::Delete(Selection::UpTo(Movement::Left(1)));
+    }
+    let col = *buf.display_col(buf.idx);
+    replace_whitespace(buf, (col - 1) / step * step)
+}
+
+/// Replaces the whitespace before the cursor with the whitespace that reaches up to a display column, or as close
+/// to it as the text before the whitespace allows
+fn replace_whitespace(buf: &Buffer, to: usize) -> BufferAction {
+    let (before, _) = split_line(buf);
+    let len = before.len() - before.trim_end_matches(&[' ', '\t'][..]).len();
+    let start = buf.idx - len.into();
+    let from = *buf.display_col(start);
+    let mut actions = vec![BufferAction::Delete(Selection::Bounds(start, buf.idx))];
+    actions.extend(Some(buf.filetype.whitespace(from, to)).filter(|w| !w.is_empty()).map(BufferAction::Insert));
+    BufferAction::Batch(actions)
+}
+
+/// Replaces the indentation of the cursor line with the one computed from the syntax tree
+pub fn indent_line(buf: &Buffer) -> BufferAction {
+    reindent_rows(buf, buf.row(), buf.row(), true)
+}
+
+/// Replaces the indentation of the non-blank lines in the selection with the one computed from the syntax tree
+pub fn reindent(buf: &Buffer, selection: Selection) -> BufferAction {
+    let range = selection.bounds(buf);
+    let last = usize::max(*range.start, range.end.saturating_sub(1));
+    reindent_rows(buf, buf.char_to_row(range.start), buf.char_to_row(last.into()), false)
+}
+
+fn reindent_rows(buf: &Buffer, first: BufRow, last: BufRow, blank: bool) -> BufferAction {
+    let syntax = match &buf.syntax {
+        Some(syntax) => syntax,
+        None => return BufferAction::Nothing,
+    };
+    let levels = syntax.indent_levels(&buf.text, *first..=*last);
+    let mut actions = Vec::new();
+    // Lines are indented from the bottom up, so that the positions of the lines above stay the same
+    for (row, level) in (*first..*last + 1).zip(levels).rev() {
+        let line = buf.line_str(row.into());
+        let new = match level {
+            Some(level) if blank || !line.trim().is_empty() => buf.filetype.whitespace(0, level * buf.filetype.shiftwidth),
+            // Typed lines that the tree can't tell are indented like the line above
+            None if blank => guess_indentation(buf, row.into()),
+            _ => continue,
+        };
+        let old = indentation(&line);
+        if old == new {
+            continue;
+        }
+        let start = buf.row_to_char(row.into());
+        if !old.is_empty() {
+            actions.push(BufferAction::Delete(Selection::Bounds(start, start + old.chars().count().into())));
+        }
+        if !new.is_empty() {
+            actions.push(BufferAction::InsertAt(start, new));
+        }
+    }
+    BufferAction::Batch(actions)
+}
+
+/// Returns the indentation of the line above a row, one level deeper after an opening bracket and one level less
+/// before a closing bracket
+fn guess_indentation(buf: &Buffer, row: BufRow) -> String {
+    let above = (0..*row).rev().map(|row| buf.line_str(row.into())).find(|line| !line.trim().is_empty());
+    let above = match above {
+        Some(line) => line,
+        None => return String::new(),
+    };
+    let unit = buf.filetype.indent();
+    let unit = unit.as_str();
+    let mut indent = indentation(&above).to_string();
+    if above.trim_end().chars().last().and_then(closer).is_some() {
+        indent += unit;
+    }
+    let line = buf.line_str(row);
+    if line.trim_start().chars().next().and_then(opener).is_some() && indent.ends_with(unit) {
+        indent.truncate(indent.len() - unit.len());
+    }
+    indent
+}
@@ -1,19 +1,27 @@
 use crate::{
-    action::{BufferAction, RenderAction, Command},
+    action::{BufferAction, EditorAction, RenderAction, Command, WindowAction},
     buffer::EditMode,
-    utils::{Movement, Selection},
+    fold::FoldCommand,
+    picker::PickerKind,
+    prompt::PromptKind,
+    search::Direction,
+    utils::{BlockSplit, Movement, Selection},
 };
-use crossterm::event::{KeyCode, KeyEvent};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
-pub struct InputHandler;
+#[derive(Default)]
+pub struct InputHandler {
+    /// The keys typed so far of a command that takes multiple keys, such as `ma`
+    pending: Vec<char>,
+}
 
 impl InputHandler {
     pub fn parse_insert(key: KeyEvent) -> Option<Command> {
         Some(Command::new(match key.code {
             KeyCode::Esc => BufferAction::SetMode(EditMode::Normal),
-            KeyCode::Char(c) => BufferAction::Insert(String::from(c)),
-            KeyCode::Tab => BufferAction::Insert(String::from("\t")),
-            KeyCode::Enter => BufferAction::Insert(String::from("\n")),
+            KeyCode::Char(c) => BufferAction::Type(c),
+            KeyCode::Tab => BufferAction::Tab,
+            KeyCode::Enter => BufferAction::NewLine,
             KeyCode::Up => BufferAction::Move(Movement::Up(1)),
             KeyCode::Down => BufferAction::Move(Movement::Down(1)),
             KeyCode::Left => BufferAction::Move(Movement::Left(1)),
@@ -22,19 +30,25 @@ impl InputHandler {
             KeyCode::End => BufferAction::Move(Movement::End),
             KeyCode::PageUp => BufferAction::Move(Movement::Up(25)),
             KeyCode::PageDown => BufferAction::Move(Movement::Down(25)),
-            KeyCode::Backspace => BufferAction::Delete(Selection::UpTo(Movement::Left(1))),
+            KeyCode::Backspace => BufferAction::Backspace,
             KeyCode::Delete => BufferAction::Delete(Selection::UpTo(Movement::Right(1))),
             _ => return None,
-        }, match key.code {
-            _ => RenderAction::DrawAll,
-            // KeyCode::Esc => RenderAction::UpdateCursor,
-            // KeyCode::Char(_) | KeyCode::Tab | KeyCode::Enter | KeyCode::Backspace | KeyCode::Delete => RenderAction::DrawFromCursor,
-            // KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right | KeyCode::Home | KeyCode::End | KeyCode::PageUp | KeyCode::PageDown => RenderAction::UpdateCursor,
-            // _ => RenderAction::Nothing
-        }))
+        }, RenderAction::DrawAll))
+        // KeyCode::Esc => RenderAction::UpdateCursor,
+        // KeyCode::Char(_) | KeyCode::Tab | KeyCode::Enter | KeyCode::Backspace | KeyCode::Delete => RenderAction::DrawFromCursor,
+        // KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right | KeyCode::Home | KeyCode::End | KeyCode::PageUp | KeyCode::PageDown => RenderAction::UpdateCursor,
+        // _ => RenderAction::Nothing
     }
 
-    pub fn parse_normal(key: KeyEvent) -> Option<Command> {
+    pub fn parse_normal(&mut self, key: KeyEvent) -> Option<Command> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let c = match key.code {
+            KeyCode::Char(c) if !ctrl => Some(c),
+            _ => None,
+        };
+        if !self.pending.is_empty() || matches!(c, Some('m') | Some('`') | Some('\'') | Some('g') | Some('=') | Some('z')) {
+            return self.parse_sequence(c);
+        }
         Some(Command::new(match key.code {
             KeyCode::Up => BufferAction::Move(Movement::Up(1)),
             KeyCode::Down => BufferAction::Move(Movement::Down(1)),
@@ -44,19 +58,197 @@ impl InputHandler {
             KeyCode::End => BufferAction::Move(Movement::End),
             KeyCode::PageUp => BufferAction::Move(Movement::Up(25)),
             KeyCode::PageDown => BufferAction::Move(Movement::Down(25)),
+            KeyCode::Esc => BufferAction::CollapseCursors,
+            KeyCode::Char('n') if ctrl => BufferAction::SelectNextMatch,
+            KeyCode::Char('v') if ctrl => BufferAction::SetMode(EditMode::VisualBlock),
+            KeyCode::Char('o') if ctrl => return Some(Command::window(WindowAction::Editor(EditorAction::Jump { older: true }))),
+            KeyCode::Char('p') if ctrl => return Some(Command::window(WindowAction::Editor(EditorAction::OpenPicker(Some(PickerKind::Files))))),
+            // Most terminals send Ctrl-I as Tab
+            KeyCode::Char('i') if ctrl => return Some(Command::window(WindowAction::Editor(EditorAction::Jump { older: false }))),
+            KeyCode::Tab => return Some(Command::window(WindowAction::Editor(EditorAction::Jump { older: false }))),
+            KeyCode::Char('v') => BufferAction::SetMode(EditMode::Visual),
+            KeyCode::Char('G') => BufferAction::Move(Movement::Bottom),
+            KeyCode::Char('%') => BufferAction::Move(Movement::MatchingBracket),
+            KeyCode::Char(':') => return Some(Command::window(WindowAction::OpenPrompt(PromptKind::Command))),
+            KeyCode::Char('/') => return Some(Command::window(WindowAction::OpenPrompt(PromptKind::Search(Direction::Forward)))),
+            KeyCode::Char('?') => return Some(Command::window(WindowAction::OpenPrompt(PromptKind::Search(Direction::Backward)))),
+            KeyCode::Char('n') => return Some(Command::window(WindowAction::SearchNext { reverse: false })),
+            KeyCode::Char('N') => return Some(Command::window(WindowAction::SearchNext { reverse: true })),
+            KeyCode::Char('*') => return Some(Command::window(WindowAction::SearchWord(Direction::Forward))),
+            KeyCode::Char('#') => return Some(Command::window(WindowAction::SearchWord(Direction::Backward))),
             KeyCode::Char('i') => BufferAction::SetMode(EditMode::Insert),
+            KeyCode::Char('o') => BufferAction::Batch(vec![BufferAction::OpenLine { above: false }, BufferAction::SetMode(EditMode::Insert)]),
+            KeyCode::Char('O') => BufferAction::Batch(vec![BufferAction::OpenLine { above: true }, BufferAction::SetMode(EditMode::Insert)]),
             KeyCode::Char('d') => BufferAction::Delete(Selection::Lines(1)),
             KeyCode::Char('u') => BufferAction::Undo,
             KeyCode::Char('U') => BufferAction::Redo,
             KeyCode::Delete => BufferAction::Delete(Selection::UpTo(Movement::Right(1))),
             _ => return None,
-        }, match key.code {
-            _ => RenderAction::DrawAll
-            // KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right | KeyCode::Home | KeyCode::End | KeyCode::PageUp | KeyCode::PageDown => RenderAction::UpdateCursor,
-            // KeyCode::Char('i') => RenderAction::UpdateCursor,
-            // KeyCode::Char('d') => RenderAction::DrawFromCursor,
-            // KeyCode::Char('u') | KeyCode::Char('U') => RenderAction::DrawAll,
-            // _ => RenderAction::Nothing
-        }))
+        }, RenderAction::DrawAll))
+        // KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right | KeyCode::Home | KeyCode::End | KeyCode::PageUp | KeyCode::PageDown => RenderAction::UpdateCursor,
+        // KeyCode::Char('i') => RenderAction::UpdateCursor,
+        // KeyCode::Char('d') => RenderAction::DrawFromCursor,
+        // KeyCode::Char('u') | KeyCode::Char('U') => RenderAction::DrawAll,
+        // _ => RenderAction::Nothing
+    }
+
+    /// Parses the next key of a command that takes multiple keys, any other key cancels the command
+    fn parse_sequence(&mut self, c: Option<char>) -> Option<Command> {
+        match c {
+            Some(c) => self.pending.push(c),
+            None => {
+                self.pending.clear();
+                return None;
+            }
+        }
+        let command = match self.pending[..] {
+            [_] => return None,
+            ['m', name @ 'A'..='Z'] => Command::window(WindowAction::Editor(EditorAction::SetGlobalMark(name))),
+            ['m', name] => Command::new(BufferAction::SetMark(name), RenderAction::DrawAll),
+            ['g', 'g'] => Command::new(BufferAction::Move(Movement::Top), RenderAction::DrawAll),
+            ['g', 'j'] => Command::new(BufferAction::Move(Movement::ScreenDown(1)), RenderAction::DrawAll),
+            ['g', 'k'] => Command::new(BufferAction::Move(Movement::ScreenUp(1)), RenderAction::DrawAll),
+            ['g', '0'] => Command::new(BufferAction::Move(Movement::ScreenHome), RenderAction::DrawAll),
+            ['g', '$'] => Command::new(BufferAction::Move(Movement::ScreenEnd), RenderAction::DrawAll),
+            ['g', ';'] => Command::new(BufferAction::JumpToChange { older: true }, RenderAction::DrawAll),
+            ['g', ','] => Command::new(BufferAction::JumpToChange { older: false }, RenderAction::DrawAll),
+            ['=', 'g'] | ['=', 'a'] | ['=', 'i'] => return None,
+            ['g', 'c'] | ['g', 'b'] | ['g', _, 'g'] | ['g', _, 'a'] | ['g', _, 'i'] => return None,
+            ['g', op @ 'c', 'c'] | ['g', op @ 'b', 'c'] => Self::toggle_comment(Selection::Lines(1), op),
+            ['g', op, 'G'] => Self::toggle_comment(Selection::UpTo(Movement::Bottom), op),
+            ['g', op, 'g', 'g'] => Self::toggle_comment(Selection::UpTo(Movement::Top), op),
+            ['g', op, object @ 'a', 'p'] | ['g', op, object @ 'i', 'p'] => {
+                Self::toggle_comment(Selection::Paragraph { inclusive: object == 'a' }, op)
+            }
+            ['z', command @ ('a' | 'o' | 'c' | 'R' | 'M' | 'd')] => Command::new(
+                BufferAction::Fold(match command {
+                    'a' => FoldCommand::Toggle,
+                    'o' => FoldCommand::Open,
+                    'c' => FoldCommand::Close,
+                    'R' => FoldCommand::OpenAll,
+                    'M' => FoldCommand::CloseAll,
+                    _ => FoldCommand::Delete,
+                }),
+                RenderAction::DrawAll,
+            ),
+            ['z', 'j'] => Command::new(BufferAction::Move(Movement::NextFold), RenderAction::DrawAll),
+            ['z', 'k'] => Command::new(BufferAction::Move(Movement::PrevFold), RenderAction::DrawAll),
+            ['=', '='] => Command::new(BufferAction::Reindent(Selection::Lines(1)), RenderAction::DrawAll),
+            ['=', 'G'] => Command::new(BufferAction::Reindent(Selection::UpTo(Movement::Bottom)), RenderAction::DrawAll),
+            ['=', 'g', 'g'] => Command::new(BufferAction::Reindent(Selection::UpTo(Movement::Top)), RenderAction::DrawAll),
+            ['=', object @ 'a', 'p'] | ['=', object @ 'i', 'p'] => Command::new(
+                BufferAction::Reindent(Selection::Paragraph { inclusive: object == 'a' }),
+                RenderAction::DrawAll,
+            ),
+            [jump @ '`', name @ 'A'..='Z'] | [jump @ '\'', name @ 'A'..='Z'] => {
+                Command::window(WindowAction::Editor(EditorAction::JumpToGlobalMark { name, line: jump == '\'' }))
+            }
+            [jump @ '`', name] | [jump @ '\'', name] => {
+                Command::new(BufferAction::JumpToMark { name, line: jump == '\'' }, RenderAction::DrawAll)
+            }
+            _ => {
+                self.pending.clear();
+                return None;
+            }
+        };
+        self.pending.clear();
+        Some(command)
+    }
+
+    /// Parses keys in both visual and visual block mode
+    pub fn parse_visual(&mut self, key: KeyEvent, mode: EditMode) -> Option<Command> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let block = mode == EditMode::VisualBlock;
+        if let (true, KeyCode::Char(c @ ('g' | 'z')), false) = (self.pending.is_empty(), key.code, ctrl) {
+            self.pending.push(c);
+            return None;
+        }
+        if !self.pending.is_empty() {
+            let first = self.pending.remove(0);
+            self.pending.clear();
+            if first == 'z' {
+                return match key.code {
+                    KeyCode::Char('f') => Some(Command::new(
+                        BufferAction::Batch(vec![BufferAction::Fold(FoldCommand::Create), BufferAction::SetMode(EditMode::Normal)]),
+                        RenderAction::DrawAll,
+                    )),
+                    _ => None,
+                };
+            }
+            return Some(Command::new(match key.code {
+                KeyCode::Char('g') => BufferAction::Move(Movement::Top),
+                KeyCode::Char('j') => BufferAction::Move(Movement::ScreenDown(1)),
+                KeyCode::Char('k') => BufferAction::Move(Movement::ScreenUp(1)),
+                KeyCode::Char('0') => BufferAction::Move(Movement::ScreenHome),
+                KeyCode::Char('$') => BufferAction::Move(Movement::ScreenEnd),
+                // Line comments toggle the lines of the block together, block comments wrap every line of it
+                KeyCode::Char('c') => Self::visual_operation(false, vec![
+                    BufferAction::ToggleComment { selection: Selection::Selected, block: false },
+                    BufferAction::SetMode(EditMode::Normal),
+                ]),
+                KeyCode::Char('b') => Self::visual_operation(block, vec![
+                    BufferAction::ToggleComment { selection: Selection::Selected, block: true },
+                    BufferAction::SetMode(EditMode::Normal),
+                ]),
+                _ => return None,
+            }, RenderAction::DrawAll));
+        }
+        Some(Command::new(match key.code {
+            KeyCode::Up => BufferAction::Move(Movement::Up(1)),
+            KeyCode::Down => BufferAction::Move(Movement::Down(1)),
+            KeyCode::Left => BufferAction::Move(Movement::Left(1)),
+            KeyCode::Right => BufferAction::Move(Movement::Right(1)),
+            KeyCode::Home => BufferAction::Move(Movement::Home),
+            KeyCode::End => BufferAction::Move(Movement::End),
+            KeyCode::PageUp => BufferAction::Move(Movement::Up(25)),
+            KeyCode::PageDown => BufferAction::Move(Movement::Down(25)),
+            KeyCode::Char('%') => BufferAction::Move(Movement::MatchingBracket),
+            KeyCode::Esc => BufferAction::SetMode(EditMode::Normal),
+            KeyCode::Char('n') if ctrl => BufferAction::SelectNextMatch,
+            KeyCode::Char('v') if ctrl => BufferAction::SetMode(EditMode::VisualBlock),
+            KeyCode::Char('v') => BufferAction::SetMode(EditMode::Visual),
+            KeyCode::Char('n') => return Some(Command::window(WindowAction::SearchNext { reverse: false })),
+            KeyCode::Char('N') => return Some(Command::window(WindowAction::SearchNext { reverse: true })),
+            KeyCode::Char('I') if block => BufferAction::Batch(vec![
+                BufferAction::SplitBlock(BlockSplit::Insert),
+                BufferAction::SetMode(EditMode::Insert),
+            ]),
+            KeyCode::Char('A') if block => BufferAction::Batch(vec![
+                BufferAction::SplitBlock(BlockSplit::Append),
+                BufferAction::SetMode(EditMode::Insert),
+            ]),
+            KeyCode::Char('S') => return Some(Command::window(WindowAction::OpenPrompt(PromptKind::SplitSelection))),
+            KeyCode::Char(':') => return Some(Command::window(WindowAction::OpenPrompt(PromptKind::Command))),
+            KeyCode::Char('d') | KeyCode::Char('x') | KeyCode::Delete => Self::visual_operation(block, vec![
+                BufferAction::Delete(Selection::Selected),
+                BufferAction::SetMode(EditMode::Normal),
+            ]),
+            KeyCode::Char('=') => Self::visual_operation(block, vec![
+                BufferAction::Reindent(Selection::Selected),
+                BufferAction::SetMode(EditMode::Normal),
+            ]),
+            KeyCode::Char('c') => Self::visual_operation(block, vec![
+                BufferAction::Delete(Selection::Selected),
+                BufferAction::SetMode(EditMode::Insert),
+            ]),
+            KeyCode::Char('y') => Self::visual_operation(block, vec![
+                BufferAction::Yank(Selection::Selected),
+                BufferAction::SetMode(EditMode::Normal),
+            ]),
+            _ => return None,
+        }, RenderAction::DrawAll))
+    }
+
+    /// Returns the command of `gc`, which toggles line comments, or of `gb`, which toggles a block comment
+    fn toggle_comment(selection: Selection, op: char) -> Command {
+        Command::new(BufferAction::ToggleComment { selection, block: op == 'b' }, RenderAction::DrawAll)
+    }
+
+    /// Applies the actions to the selection, a visual block is first split into a selection per line
+    fn visual_operation(block: bool, mut actions: Vec<BufferAction>) -> BufferAction {
+        if block {
+            actions.insert(0, BufferAction::SplitBlock(BlockSplit::Select));
+        }
+        BufferAction::Batch(actions)
     }
 }
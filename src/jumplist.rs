@@ -0,0 +1,60 @@
+use crate::anchor::AnchorId;
+use std::path::PathBuf;
+
+/// The number of jumps that are remembered
+const MAX_JUMPS: usize = 100;
+
+/// A position in a file, kept up to date by an anchor in the buffer of the file
+#[derive(Clone)]
+pub struct Jump {
+    pub path: PathBuf,
+    pub anchor: AnchorId,
+}
+
+/// The positions the cursor jumped away from, which `Ctrl-O` and `Ctrl-I` move through
+#[derive(Default)]
+pub struct JumpList {
+    jumps: Vec<Jump>,
+    /// The jump that was moved to last, or the length of the list when a new jump was added after it
+    idx: usize,
+}
+
+impl JumpList {
+    /// Adds a jump after all others, replacing the jumps for which `same` returns true. Returns the jumps that
+    /// were dropped, whose anchors are no longer needed.
+    pub fn push(&mut self, jump: Jump, same: impl Fn(&Jump) -> bool) -> Vec<Jump> {
+        let (mut dropped, mut jumps): (Vec<Jump>, Vec<Jump>) = self.jumps.drain(..).partition(same);
+        if jumps.len() >= MAX_JUMPS {
+            dropped.extend(jumps.drain(..=jumps.len() - MAX_JUMPS));
+        }
+        jumps.push(jump);
+        self.jumps = jumps;
+        self.idx = self.jumps.len();
+        dropped
+    }
+
+    /// Returns the jumps, the oldest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Jump> {
+        self.jumps.iter()
+    }
+
+    /// Whether no jump was moved back to since the last one was added
+    pub fn at_end(&self) -> bool {
+        self.idx >= self.jumps.len()
+    }
+
+    /// Moves to the jump before the current one
+    pub fn back(&mut self) -> Option<&Jump> {
+        self.idx = self.idx.checked_sub(1)?;
+        self.jumps.get(self.idx)
+    }
+
+    /// Moves to the jump after the current one
+    pub fn forward(&mut self) -> Option<&Jump> {
+        if self.idx + 1 >= self.jumps.len() {
+            return None;
+        }
+        self.idx += 1;
+        self.jumps.get(self.idx)
+    }
+}
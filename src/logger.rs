@@ -0,0 +1,108 @@
+use crate::message::Error;
+use log::{error, info, LevelFilter, Log, Metadata, Record};
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    panic,
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The environment variable that sets the log level, instead of the config
+const LEVEL_VAR: &str = "EDITOR_LOG";
+
+/// Appends the records of the enabled levels to the log file, a line per record
+struct Logger(Mutex<File>);
+
+impl Log for Logger {
+    /// Other crates only log their warnings and errors, as their tracing would drown out the records of the editor
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let max = match metadata.target().starts_with(env!("CARGO_PKG_NAME")) {
+            true => log::max_level(),
+            false => LevelFilter::min(log::max_level(), LevelFilter::Warn),
+        };
+        metadata.level() <= max
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if let Ok(mut file) = self.0.lock() {
+            // A log that can't be written to is left behind, as there is nowhere to report it
+            let _ = writeln!(file, "{} {:<5} {}: {}", timestamp(), record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.0.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Returns the path of the log file, in `$XDG_STATE_HOME` or the default state directory of the XDG spec
+pub fn path() -> PathBuf {
+    let state = match (env::var("XDG_STATE_HOME"), env::var("HOME")) {
+        (Ok(state), _) if !state.is_empty() => PathBuf::from(state),
+        (_, Ok(home)) => PathBuf::from(home).join(".local/state"),
+        _ => PathBuf::from("."),
+    };
+    state.join("editor/log")
+}
+
+/// Starts logging to the log file, at the level of the `EDITOR_LOG` environment variable if it is set and otherwise
+/// at `level`
+pub fn init(level: LevelFilter) -> Result<(), Error> {
+    let path = path();
+    let level = env::var(LEVEL_VAR).ok().and_then(|level| level.parse().ok()).unwrap_or(level);
+    if level == LevelFilter::Off {
+        return Ok(());
+    }
+    let io_error = |error| Error::Io { path: path.clone(), error };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(io_error)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(&path).map_err(io_error)?;
+    // Only fails if a logger was set before, which keeps logging
+    if log::set_boxed_logger(Box::new(Logger(Mutex::new(file)))).is_ok() {
+        log::set_max_level(level);
+    }
+    // Panics are logged as well, as the message printed to the alternate screen disappears with it
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        error!("{}", info);
+        default_hook(info);
+    }));
+    info!("Started logging at level {}", level);
+    Ok(())
+}
+
+/// Returns the current time in UTC as `YYYY-MM-DD HH:MM:SS.mmm`
+fn timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let (days, secs) = (now.as_secs() / 86400, now.as_secs() % 86400);
+    // Converts days since 1970-01-01 to a date of the proleptic Gregorian calendar, with years starting in March so
+    // that leap days come last
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = era * 400 + year_of_era + (month <= 2) as u64;
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        now.subsec_millis()
+    )
+}
@@ -1,50 +1,61 @@
-use crate::{config::Config, window::Window};
-use crossterm::{
-    cursor::{RestorePosition, SavePosition},
-    event,
-    event::{DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers},
-    execute,
-    terminal::{self, DisableLineWrap, EnableLineWrap, EnterAlternateScreen, LeaveAlternateScreen},
-    Result,
-};
-use std::{
-    env,
-    io::{self, Write},
-    path::PathBuf,
-    process,
-};
+use crate::{config::Config, editor::Editor, message::Error};
+use crossterm::terminal;
+use std::{env, io, path::PathBuf};
 
 mod action;
+mod anchor;
+mod brackets;
 mod buffer;
+mod comment;
 mod config;
+mod editor;
+mod ex;
+mod fold;
+mod grapheme;
 mod highlight;
+mod indent;
 mod input;
+mod jumplist;
+mod logger;
+mod message;
+mod pairs;
+mod picker;
+mod prompt;
+mod quickfix;
 mod rect;
 mod render;
+mod replace;
+mod search;
+mod status;
+mod substitute;
+mod syntax;
 mod utils;
 mod window;
+mod wrap;
 
 fn main() {
     let config_path = PathBuf::from(match env::var("XDG_CONFIG_HOME") {
         Ok(config_path) => config_path + "/editor/config.yml",
         Err(_) => String::from("./config.yml"),
     });
-    let config = match Config::load(&config_path) {
-        Ok(conf) => conf,
-        Err(e) => {
-            println!(
-                "Error: {} while trying to load config from path: {:?}",
-                e, &config_path
-            );
-            Config::write_default(&config_path).unwrap();
-            Config::default()
+    // Errors are reported once the editor has started, with the default config in place of an invalid one
+    let (config, config_error) = match Config::load(&config_path) {
+        Ok(conf) => (conf, None),
+        Err(Error::Io { error, .. }) if error.kind() == io::ErrorKind::NotFound => {
+            (Config::default(), Config::write_default(&config_path).err())
         }
+        Err(e) => (Config::default(), Some(e)),
     };
 
+    let log_error = logger::init(config.log_level).err();
+
     let _cleanup = CleanUp;
     let path = env::args().nth(1).expect("No file argument given!").into();
 
     let mut editor = Editor::new(path, config);
+    for e in config_error.into_iter().chain(log_error) {
+        editor.report(e);
+    }
     editor.run(&mut io::stdout()).unwrap();
 }
 
@@ -55,81 +66,3 @@ impl Drop for CleanUp {
         terminal::disable_raw_mode().expect("Unable to disable raw mode");
     }
 }
-
-struct Editor {
-    windows: Vec<Window>,
-    _config: Config,
-    selected_window: usize,
-    width: u16,
-    height: u16,
-}
-
-impl Editor {
-    pub fn new(path: PathBuf, config: Config) -> Self {
-        let (width, height) = terminal::size().unwrap();
-        Editor {
-            windows: vec![Window::new(path, config.clone())],
-            _config: config,
-            selected_window: 0,
-            width,
-            height,
-        }
-    }
-
-    pub fn run<W: Write>(&mut self, w: &mut W) -> Result<()> {
-        terminal::enable_raw_mode()?;
-        execute!(
-            w,
-            SavePosition,
-            EnterAlternateScreen,
-            EnableMouseCapture,
-            DisableLineWrap,
-        )?;
-        self.buffer_mut().draw_all()?;
-        loop {
-            let input = event::read()?;
-            if let Event::Key(KeyEvent { code: KeyCode::Char('c'), modifiers: KeyModifiers::CONTROL }) = input {
-                self.quit(w)?;
-            } else {
-                self.handle_input(input)?;
-            }
-        }
-    }
-
-    pub fn update_size(&mut self, width: u16, height: u16) {
-        self.buffer_mut().update_size(width, height);
-        self.width = width;
-        self.height = height;
-    }
-
-    pub fn buffer_mut(&mut self) -> &mut Window {
-        self.windows
-            .get_mut(self.selected_window)
-            .expect("Window index was out of range for editor")
-    }
-
-    pub fn handle_input(&mut self, event: Event) -> Result<()> {
-        match event {
-            Event::Resize(width, height) => {
-                self.update_size(width, height);
-                self.buffer_mut().draw_all()?;
-            }
-            Event::Key(event) => self.buffer_mut().handle_keyevent(event)?,
-            Event::Mouse(_event) => (),
-        }
-        Ok(())
-    }
-
-    /// Cleans up and quits the application
-    fn quit<W: Write>(&mut self, w: &mut W) -> Result<()> {
-        execute!(
-            w,
-            DisableMouseCapture,
-            LeaveAlternateScreen,
-            RestorePosition,
-            EnableLineWrap,
-        )?;
-        terminal::disable_raw_mode()?;
-        process::exit(0);
-    }
-}
@@ -0,0 +1,131 @@
+use std::{
+    fmt, io,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// An error that is reported on the message line instead of stopping the editor
+#[derive(Debug)]
+pub enum Error {
+    /// An action that can't be applied to the buffer, such as undoing without any changes
+    Action(&'static str),
+    /// An Ex command that couldn't be parsed or executed
+    Command(String),
+    /// A file that couldn't be read or written
+    Io { path: PathBuf, error: io::Error },
+    /// A configuration file that couldn't be loaded or written
+    Config { path: PathBuf, error: String },
+    /// A file that couldn't be highlighted, it is shown without highlighting
+    Highlight(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Action(e) => write!(f, "{}", e),
+            Error::Command(e) => write!(f, "{}", e),
+            Error::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            Error::Config { path, error } => write!(f, "Invalid config {}: {}", path.display(), error),
+            Error::Highlight(e) => write!(f, "Could not highlight: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<&'static str> for Error {
+    fn from(e: &'static str) -> Self {
+        Error::Action(e)
+    }
+}
+
+impl From<String> for Error {
+    fn from(e: String) -> Self {
+        Error::Command(e)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// A message shown on the message line below the status line
+#[derive(Clone)]
+pub struct Message {
+    pub text: String,
+    pub severity: Severity,
+    /// When the message was created, it disappears once the timeout of the config has passed
+    created: Instant,
+}
+
+impl Message {
+    pub fn new(text: impl Into<String>, severity: Severity) -> Self {
+        Self { text: text.into(), severity, created: Instant::now() }
+    }
+
+    pub fn info(text: impl Into<String>) -> Self {
+        Self::new(text, Severity::Info)
+    }
+
+    pub fn warning(text: impl Into<String>) -> Self {
+        Self::new(text, Severity::Warning)
+    }
+
+    pub fn error(text: impl Into<String>) -> Self {
+        Self::new(text, Severity::Error)
+    }
+
+    /// Whether the message has been shown for longer than `timeout`. Messages with multiple lines, such as lists, stay
+    /// until the next key press.
+    pub fn is_expired(&self, timeout: Duration) -> bool {
+        !self.is_multiline() && self.created.elapsed() >= timeout
+    }
+
+    pub fn is_multiline(&self) -> bool {
+        self.text.contains('\n')
+    }
+}
+
+impl From<Error> for Message {
+    fn from(e: Error) -> Self {
+        Message::error(e.to_string())
+    }
+}
+
+/// The messages reported since the editor started, which `:messages` lists
+#[derive(Default)]
+pub struct History(Vec<Message>);
+
+impl History {
+    /// The number of messages that are kept, older ones are forgotten
+    const LEN: usize = 200;
+
+    pub fn push(&mut self, message: Message) {
+        if self.0.len() == Self::LEN {
+            self.0.remove(0);
+        }
+        self.0.push(message);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the lines that list the messages, marking warnings and errors
+    pub fn lines(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .flat_map(|message| {
+                let prefix = match message.severity {
+                    Severity::Info => "",
+                    Severity::Warning => "W: ",
+                    Severity::Error => "E: ",
+                };
+                message.text.lines().map(move |line| format!("{}{}", prefix, line))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,59 @@
+use crate::{
+    action::BufferAction,
+    buffer::Buffer,
+    indent,
+    utils::{BufCharIdx, Movement, Selection},
+};
+
+/// Returns the configured pairs of the buffer as opening and closing characters
+fn pairs(buf: &Buffer) -> impl Iterator<Item = (char, char)> + '_ {
+    buf.filetype.pairs.iter().filter_map(|pair| {
+        let mut chars = pair.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some(open), Some(close), None) => Some((open, close)),
+            _ => None,
+        }
+    })
+}
+
+fn char_at(buf: &Buffer, idx: BufCharIdx) -> Option<char> {
+    Some(*idx).filter(|idx| *idx < buf.text.len_chars()).map(|idx| buf.text.char(idx))
+}
+
+/// Returns what typing a character does when it is part of a pair: typing the closing character in front of the same
+/// character moves over it, and typing an opening character also inserts the closing one. Quotes and other pairs that
+/// open and close with the same character are only paired outside of words and strings.
+pub fn type_char(buf: &Buffer, c: char) -> Option<BufferAction> {
+    let (open, close) = pairs(buf).find(|(open, close)| *open == c || *close == c)?;
+    let next = char_at(buf, buf.idx);
+    if c == close && next == Some(close) {
+        return Some(BufferAction::MoveTo(buf.idx + 1.into(), buf.display_col(buf.idx + 1.into())));
+    }
+    if c != open || next.is_some_and(char::is_alphanumeric) {
+        return None;
+    }
+    if open == close {
+        let previous = buf.idx.checked_sub(1).and_then(|idx| char_at(buf, idx.into()));
+        let byte = buf.text.char_to_byte(*buf.idx);
+        let in_literal = buf.syntax.as_ref().is_some_and(|syntax| syntax.in_literal(byte));
+        if previous.is_some_and(|c| c.is_alphanumeric() || c == open) || in_literal {
+            return None;
+        }
+    }
+    Some(BufferAction::Batch(vec![
+        BufferAction::Insert(format!("{}{}", open, close)),
+        BufferAction::Move(Movement::Left(1)),
+    ]))
+}
+
+/// Deletes the character before the cursor, and the character after it if the two form an empty pair
+pub fn backspace(buf: &Buffer) -> BufferAction {
+    let previous = buf.idx.checked_sub(1).and_then(|idx| char_at(buf, idx.into()));
+    let next = char_at(buf, buf.idx);
+    match (previous, next) {
+        (Some(previous), Some(next)) if pairs(buf).any(|pair| pair == (previous, next)) => {
+            BufferAction::Delete(Selection::Bounds(buf.idx - 1.into(), buf.idx + 1.into()))
+        }
+        _ => indent::backspace(buf),
+    }
+}
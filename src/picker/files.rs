@@ -0,0 +1,86 @@
+use super::{Item, Open, Preview, Source};
+use crate::editor::Editor;
+use ignore::WalkBuilder;
+use std::{
+    env,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+/// The number of paths the file walk collects before sending them
+const BATCH: usize = 256;
+/// The number of bytes of a file read for its preview
+const PREVIEW_BYTES: u64 = 16 * 1024;
+
+/// The files under a directory that aren't ignored by `.gitignore`
+pub struct Files {
+    root: PathBuf,
+}
+
+impl Files {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+}
+
+impl Source for Files {
+    fn title(&self) -> &str {
+        "Files"
+    }
+
+    /// Walks the directory on a background thread, the paths are relative to the working directory. The walk stops
+    /// early when the receiver is dropped.
+    fn items(&self) -> Receiver<Vec<Item>> {
+        let (sender, receiver) = mpsc::channel();
+        let root = self.root.clone();
+        thread::spawn(move || {
+            let cwd = env::current_dir().unwrap_or_default();
+            let mut batch = Vec::with_capacity(BATCH);
+            let mut id = 0;
+            for file in WalkBuilder::new(&root).build().flatten() {
+                if !file.file_type().is_some_and(|t| t.is_file()) {
+                    continue;
+                }
+                let path = file.path().strip_prefix(&cwd).unwrap_or_else(|_| file.path());
+                batch.push(Item::new(path.display().to_string(), id));
+                id += 1;
+                if batch.len() == BATCH && sender.send(std::mem::take(&mut batch)).is_err() {
+                    return;
+                }
+            }
+            sender.send(batch).ok();
+        });
+        receiver
+    }
+
+    fn has_preview(&self) -> bool {
+        true
+    }
+
+    fn preview(&self, item: &Item, lines: usize) -> Option<Preview> {
+        let path = PathBuf::from(&item.text);
+        Some(Preview { lines: preview(&path, lines), path })
+    }
+
+    /// Opens the first file as asked and the others in new windows below it
+    fn pick(&self, editor: &mut Editor, items: Vec<Item>, open: Open) {
+        for (i, item) in items.iter().enumerate() {
+            editor.open_file(Path::new(&item.text), if i == 0 { open } else { Open::Split });
+        }
+    }
+}
+
+/// Returns the first lines of a file, or a note if it can't be shown
+fn preview(path: &Path, lines: usize) -> Vec<String> {
+    let mut bytes = Vec::new();
+    if let Err(e) = File::open(path).and_then(|file| file.take(PREVIEW_BYTES).read_to_end(&mut bytes)) {
+        return vec![format!("[{}]", e)];
+    }
+    if bytes.contains(&0) {
+        return vec![String::from("[binary file]")];
+    }
+    String::from_utf8_lossy(&bytes).lines().take(lines).map(String::from).collect()
+}
@@ -0,0 +1,421 @@
+mod files;
+mod sources;
+
+pub use files::Files;
+pub use sources::{Commands, Location, Locations, Pickers, Searches};
+
+use crate::{
+    config::Config,
+    editor::Editor,
+    grapheme, highlight,
+    render::{self, Renderer},
+};
+use crossterm::{cursor::CursorShape, Result};
+use ropey::Rope;
+use std::{
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, TryRecvError},
+};
+
+/// Popups narrower than this don't show a preview
+const MIN_PREVIEW_WIDTH: u16 = 60;
+
+/// The lists a picker can be opened on
+#[derive(Clone, Copy, Debug)]
+pub enum PickerKind {
+    /// The files of the project
+    Files,
+    /// The files that were opened
+    Buffers,
+    /// The Ex commands, picking one types it on the command line
+    Commands,
+    /// The marks of the selected buffer and the uppercase marks of all buffers
+    Marks,
+    /// The jump list of the last selected file window
+    Jumps,
+    /// The patterns that were searched for
+    Searches,
+    /// The definitions in the selected buffer
+    Symbols,
+}
+
+impl PickerKind {
+    pub const ALL: [PickerKind; 7] = [
+        PickerKind::Files,
+        PickerKind::Buffers,
+        PickerKind::Commands,
+        PickerKind::Marks,
+        PickerKind::Jumps,
+        PickerKind::Searches,
+        PickerKind::Symbols,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PickerKind::Files => "files",
+            PickerKind::Buffers => "buffers",
+            PickerKind::Commands => "commands",
+            PickerKind::Marks => "marks",
+            PickerKind::Jumps => "jumps",
+            PickerKind::Searches => "searches",
+            PickerKind::Symbols => "symbols",
+        }
+    }
+
+    /// Returns the picker whose name starts with `name`
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|kind| !name.is_empty() && kind.name().starts_with(name))
+    }
+}
+
+/// An entry of the list of a picker
+#[derive(Clone)]
+pub struct Item {
+    /// The text shown in the list, which the query is matched against
+    pub text: String,
+    /// What the source uses to tell its items apart, such as an index into its own list
+    pub id: usize,
+}
+
+impl Item {
+    pub fn new(text: impl Into<String>, id: usize) -> Self {
+        Self { text: text.into(), id }
+    }
+}
+
+/// What a picker does with the picked items
+#[derive(Clone, Copy)]
+pub enum Open {
+    /// Shows the item in the selected window
+    Window,
+    /// Shows the item in a new window below the selected one
+    Split,
+}
+
+/// The lines of a file shown next to the list for the selected item
+pub struct Preview {
+    /// The file the lines are from, which decides how wide tabs are
+    pub path: PathBuf,
+    pub lines: Vec<String>,
+}
+
+/// Where the items of a picker come from and what picking them does
+pub trait Source {
+    /// The title of the popup
+    fn title(&self) -> &str;
+
+    /// Starts listing the items. A source that takes a while sends them in batches from a background thread, the list
+    /// is complete once the sender is dropped.
+    fn items(&self) -> Receiver<Vec<Item>>;
+
+    /// Whether the popup makes room for previews of the items
+    fn has_preview(&self) -> bool {
+        false
+    }
+
+    /// Returns up to `lines` lines that show what an item refers to
+    fn preview(&self, _item: &Item, _lines: usize) -> Option<Preview> {
+        None
+    }
+
+    /// Acts on the picked items, which are the marked ones in the order they were marked or else the selected one.
+    /// Sources that can only act on one item use the first.
+    fn pick(&self, editor: &mut Editor, items: Vec<Item>, open: Open);
+}
+
+/// Returns a receiver of items that are all known up front
+pub fn ready(items: Vec<Item>) -> Receiver<Vec<Item>> {
+    let (sender, receiver) = mpsc::channel();
+    // Can't fail, the receiver is still there
+    sender.send(items).ok();
+    receiver
+}
+
+/// Returns the lines of a text around a row, for previewing a position in it
+pub fn lines_around(text: &Rope, row: usize, lines: usize) -> Vec<String> {
+    let start = usize::min(row.saturating_sub(lines / 3), text.len_lines().saturating_sub(1));
+    text.lines_at(start).take(lines).map(|line| line.to_string().trim_end_matches(&['\n', '\r'][..]).to_string()).collect()
+}
+
+/// Scores how well a query matches a text as a subsequence of its characters, and returns the score with the
+/// indices of the characters that matched, or `None` if the text doesn't contain the query. Matches that start
+/// words or follow each other score higher, gaps between them lower. The query only matches case sensitively if it
+/// has uppercase letters.
+pub fn score(query: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    let case_sensitive = query.chars().any(char::is_uppercase);
+    let eq = |a: char, b: char| match case_sensitive {
+        true => a == b,
+        false => a.to_lowercase().eq(b.to_lowercase()),
+    };
+    let query: Vec<char> = query.chars().collect();
+    let chars: Vec<char> = text.chars().collect();
+    // The last start of a match is found from the right, which favors the file name over the directories, and the
+    // shortest match is taken from that start onwards
+    let mut start = chars.len();
+    for q in query.iter().rev() {
+        start = (0..start).rev().find(|&i| eq(chars[i], *q))?;
+    }
+    let mut positions = Vec::with_capacity(query.len());
+    let mut i = start;
+    for q in &query {
+        i = (i..chars.len()).find(|&i| eq(chars[i], *q))?;
+        positions.push(i);
+        i += 1;
+    }
+    let mut score = 0;
+    for (k, &i) in positions.iter().enumerate() {
+        score += 16;
+        score += match i.checked_sub(1).map(|prev| chars[prev]) {
+            None | Some('/') => 10,
+            Some('_' | '-' | '.' | ' ') => 8,
+            Some(prev) if prev.is_lowercase() && chars[i].is_uppercase() => 7,
+            _ => 0,
+        };
+        if k > 0 {
+            score += match i - positions[k - 1] - 1 {
+                0 => 6,
+                gap => -i64::min(3 + gap as i64, 12),
+            };
+        }
+    }
+    Some((score, positions))
+}
+
+/// An item that matches the query
+struct Match {
+    /// The index of the item
+    idx: usize,
+    score: i64,
+    /// The indices of the characters of the text that matched the query
+    positions: Vec<usize>,
+}
+
+/// A popup that narrows down the items of a source to the ones matching the query typed into it
+pub struct Picker {
+    source: Box<dyn Source>,
+    items: Vec<Item>,
+    pub query: String,
+    /// The matching items, best first
+    matches: Vec<Match>,
+    /// The index of the selected match
+    selected: usize,
+    /// The index of the first match shown in the list
+    scroll: usize,
+    /// The indices of the marked items, in the order they were marked
+    marked: Vec<usize>,
+    /// The items the source is still sending
+    receiver: Option<Receiver<Vec<Item>>>,
+    /// The index of the previewed item and its preview
+    preview: Option<(usize, Option<Preview>)>,
+}
+
+impl Picker {
+    pub fn new(source: Box<dyn Source>) -> Self {
+        let receiver = source.items();
+        let mut picker = Self {
+            source,
+            items: Vec::new(),
+            query: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+            scroll: 0,
+            marked: Vec::new(),
+            receiver: Some(receiver),
+            preview: None,
+        };
+        // Sources that know their items up front show them right away
+        picker.receive();
+        picker
+    }
+
+    /// Adds the items the source sent since the last call, and returns whether any were sent or the list is complete
+    pub fn receive(&mut self) -> bool {
+        let receiver = match &self.receiver {
+            Some(receiver) => receiver,
+            None => return false,
+        };
+        let start = self.items.len();
+        let done = loop {
+            match receiver.try_recv() {
+                Ok(items) => self.items.extend(items),
+                Err(TryRecvError::Empty) => break false,
+                Err(TryRecvError::Disconnected) => break true,
+            }
+        };
+        if done {
+            self.receiver = None;
+        }
+        let found = self.items.len() > start;
+        if found {
+            let query = &self.query;
+            let items = &self.items;
+            self.matches.extend((start..items.len()).filter_map(|idx| {
+                score(query, &items[idx].text).map(|(score, positions)| Match { idx, score, positions })
+            }));
+            self.sort();
+        }
+        found || done
+    }
+
+    /// Changes the query. A query that extends the previous one only has to narrow down the items that matched it.
+    pub fn set_query(&mut self, query: String) {
+        let narrowed = query.starts_with(&self.query);
+        let candidates: Vec<usize> = match narrowed {
+            true => self.matches.iter().map(|m| m.idx).collect(),
+            false => (0..self.items.len()).collect(),
+        };
+        self.matches = candidates
+            .into_iter()
+            .filter_map(|idx| score(&query, &self.items[idx].text).map(|(score, positions)| Match { idx, score, positions }))
+            .collect();
+        self.query = query;
+        self.sort();
+        self.selected = 0;
+        self.scroll = 0;
+    }
+
+    /// Orders the matches by score, then shorter texts first. Without a query the items keep the order of the source.
+    fn sort(&mut self) {
+        let items = &self.items;
+        let by_length = !self.query.is_empty();
+        self.matches.sort_by(|a, b| {
+            let length = match by_length {
+                true => items[a.idx].text.len().cmp(&items[b.idx].text.len()),
+                false => std::cmp::Ordering::Equal,
+            };
+            b.score.cmp(&a.score).then(length).then(a.idx.cmp(&b.idx))
+        });
+    }
+
+    /// Moves the selection by `amount` matches, wrapping around at the ends
+    pub fn select(&mut self, amount: isize) {
+        let len = self.matches.len() as isize;
+        if len > 0 {
+            self.selected = (self.selected as isize + amount).rem_euclid(len) as usize;
+        }
+    }
+
+    /// Marks the selected item to be picked along with the other marked ones, or unmarks it, and moves the selection
+    /// by `amount`
+    pub fn toggle_mark(&mut self, amount: isize) {
+        if let Some(m) = self.matches.get(self.selected) {
+            match self.marked.iter().position(|&idx| idx == m.idx) {
+                Some(i) => {
+                    self.marked.remove(i);
+                }
+                None => self.marked.push(m.idx),
+            }
+            self.select(amount);
+        }
+    }
+
+    /// Closes the picker and lets the source act on the picked items, if there are any
+    pub fn pick(self, editor: &mut Editor, open: Open) {
+        let items: Vec<Item> = match self.marked.is_empty() {
+            true => self.matches.get(self.selected).map(|m| self.items[m.idx].clone()).into_iter().collect(),
+            false => self.marked.iter().map(|&idx| self.items[idx].clone()).collect(),
+        };
+        if !items.is_empty() {
+            self.source.pick(editor, items, open);
+        }
+    }
+
+    /// Draws the popup centered over the windows, with the list of matches on the left and the preview of the
+    /// selected item on the right, and moves the cursor to the end of the query
+    pub fn draw(&mut self, renderer: &mut Renderer, config: &Config, width: u16, height: u16) -> Result<()> {
+        let (w, h) = (u16::min(width, u16::max(20, width * 4 / 5)), u16::min(height, u16::max(5, height * 7 / 10)));
+        let (x, y) = ((width - w) / 2, (height - h) / 2);
+        renderer.draw_box(x, y, w, h, &format!(" {} ", self.source.title()), &config.popup)?;
+        let inner = w.saturating_sub(2);
+        let list_width = match self.source.has_preview() && w >= MIN_PREVIEW_WIDTH {
+            true => inner / 2,
+            false => inner,
+        };
+        let rows = h.saturating_sub(3) as usize;
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + rows {
+            self.scroll = self.selected + 1 - rows;
+        }
+
+        let marked = match self.marked.len() {
+            0 => String::new(),
+            len => format!(" +{}", len),
+        };
+        let count = format!(
+            "{}/{}{}{} ",
+            self.matches.len(),
+            self.items.len(),
+            if self.receiver.is_some() { "…" } else { "" },
+            marked
+        );
+        let prompt = format!("> {}", self.query);
+        let prompt_width = (list_width as usize).saturating_sub(grapheme::text_width(&count, 0, 1));
+        renderer.set_style(&config.popup)?;
+        renderer.move_to(x + 1, y + 1)?;
+        renderer.print(format!("{:<width$}", render::clip(&prompt, 0, 0, prompt_width, 1), width = prompt_width))?;
+        renderer.print(&count)?;
+
+        for row in 0..rows {
+            renderer.move_to(x + 1, y + 2 + row as u16)?;
+            let m = match self.matches.get(self.scroll + row) {
+                Some(m) => m,
+                None => {
+                    renderer.set_style(&config.popup)?;
+                    renderer.print(" ".repeat(list_width as usize))?;
+                    continue;
+                }
+            };
+            let base = match self.scroll + row == self.selected {
+                true => highlight::combine(&config.popup, &config.popup_selected),
+                false => config.popup,
+            };
+            let matched = highlight::combine(&base, &config.popup_match);
+            let mut col = 1;
+            renderer.set_style(&base)?;
+            renderer.print(if self.marked.contains(&m.idx) { "+" } else { " " })?;
+            for (i, c) in self.items[m.idx].text.chars().enumerate() {
+                let c = c.to_string();
+                let end = col + grapheme::text_width(&c, col, 1);
+                if end > list_width as usize {
+                    break;
+                }
+                renderer.set_style(if m.positions.contains(&i) { &matched } else { &base })?;
+                renderer.print(&c)?;
+                col = end;
+            }
+            renderer.set_style(&base)?;
+            renderer.print(" ".repeat(list_width as usize - col))?;
+        }
+
+        if list_width < inner {
+            self.draw_preview(renderer, config, x + 1 + list_width, y + 1, inner - list_width, h - 2)?;
+        }
+        renderer.reset_style()?;
+        renderer.set_cursor_shape(CursorShape::Line)?;
+        let cursor = grapheme::text_width(&prompt, 0, 1);
+        renderer.move_to(x + 1 + u16::min(cursor as u16, prompt_width as u16), y + 1)
+    }
+
+    /// Draws the preview of the selected item, behind a line that separates it from the list
+    fn draw_preview(&mut self, renderer: &mut Renderer, config: &Config, x: u16, y: u16, width: u16, height: u16) -> Result<()> {
+        let idx = self.matches.get(self.selected).map(|m| m.idx);
+        if self.preview.as_ref().map(|(previewed, _)| *previewed) != idx {
+            self.preview = idx.map(|idx| (idx, self.source.preview(&self.items[idx], height as usize)));
+        }
+        let preview = self.preview.as_ref().and_then(|(_, preview)| preview.as_ref());
+        let (lines, tabstop) = match preview {
+            Some(preview) => (&preview.lines[..], config.filetype(&preview.path).tabstop),
+            None => (&[][..], 1),
+        };
+        renderer.set_style(&config.popup)?;
+        for row in 0..height {
+            renderer.move_to(x, y + row)?;
+            let line = lines.get(row as usize).map_or("", String::as_str);
+            let text = render::clip(line, 0, 0, width as usize - 2, tabstop);
+            let padding = (width as usize - 2).saturating_sub(grapheme::text_width(&text, 0, tabstop));
+            renderer.print(format!("│ {}{}", text, " ".repeat(padding)))?;
+        }
+        Ok(())
+    }
+}
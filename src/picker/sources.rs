@@ -0,0 +1,127 @@
+use super::{lines_around, ready, Item, Open, PickerKind, Preview, Source};
+use crate::{
+    editor::Editor,
+    ex,
+    message::Error,
+    search::{Direction, Search},
+};
+use ropey::Rope;
+use std::{path::PathBuf, sync::mpsc::Receiver};
+
+/// A position in a file, with the text of the file as it was when the picker opened
+pub struct Location {
+    pub path: PathBuf,
+    pub text: Rope,
+    pub row: usize,
+    pub col: usize,
+}
+
+/// Positions in files, such as the open buffers, marks or symbols. Picking them moves the cursor there.
+pub struct Locations {
+    title: &'static str,
+    locations: Vec<(String, Location)>,
+}
+
+impl Locations {
+    /// Lists the locations with the texts they are shown as, in the given order
+    pub fn new(title: &'static str, locations: Vec<(String, Location)>) -> Self {
+        Self { title, locations }
+    }
+}
+
+impl Source for Locations {
+    fn title(&self) -> &str {
+        self.title
+    }
+
+    fn items(&self) -> Receiver<Vec<Item>> {
+        ready(self.locations.iter().enumerate().map(|(id, (text, _))| Item::new(text.clone(), id)).collect())
+    }
+
+    fn has_preview(&self) -> bool {
+        true
+    }
+
+    fn preview(&self, item: &Item, lines: usize) -> Option<Preview> {
+        let (_, location) = &self.locations[item.id];
+        Some(Preview { path: location.path.clone(), lines: lines_around(&location.text, location.row, lines) })
+    }
+
+    /// Shows the first location as asked and the others in new windows below it
+    fn pick(&self, editor: &mut Editor, items: Vec<Item>, open: Open) {
+        for (i, item) in items.iter().enumerate() {
+            let (_, location) = &self.locations[item.id];
+            editor.show_location(&location.path, location.row, location.col, if i == 0 { open } else { Open::Split });
+        }
+    }
+}
+
+/// The Ex commands, picking one types it on the command line to add its arguments
+pub struct Commands;
+
+impl Source for Commands {
+    fn title(&self) -> &str {
+        "Commands"
+    }
+
+    fn items(&self) -> Receiver<Vec<Item>> {
+        let width = ex::COMMANDS.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        ready(
+            ex::COMMANDS
+                .iter()
+                .enumerate()
+                .map(|(id, (name, description))| Item::new(format!("{:<width$}  {}", name, description, width = width), id))
+                .collect(),
+        )
+    }
+
+    fn pick(&self, editor: &mut Editor, items: Vec<Item>, _open: Open) {
+        let (name, _) = ex::COMMANDS[items[0].id];
+        editor.window_mut().open_command_line(format!("{} ", name));
+    }
+}
+
+/// The patterns that were searched for, the latest first. Picking one searches for it again.
+pub struct Searches {
+    patterns: Vec<String>,
+}
+
+impl Searches {
+    pub fn new(patterns: Vec<String>) -> Self {
+        Self { patterns }
+    }
+}
+
+impl Source for Searches {
+    fn title(&self) -> &str {
+        "Searches"
+    }
+
+    fn items(&self) -> Receiver<Vec<Item>> {
+        ready(self.patterns.iter().enumerate().map(|(id, pattern)| Item::new(pattern.clone(), id)).collect())
+    }
+
+    fn pick(&self, editor: &mut Editor, items: Vec<Item>, _open: Open) {
+        match Search::new(&self.patterns[items[0].id], Direction::Forward) {
+            Ok(search) => editor.window_mut().start_search(search),
+            Err(_) => editor.report(Error::Action("Invalid regex")),
+        }
+    }
+}
+
+/// The other pickers, for finding the one to open
+pub struct Pickers;
+
+impl Source for Pickers {
+    fn title(&self) -> &str {
+        "Pickers"
+    }
+
+    fn items(&self) -> Receiver<Vec<Item>> {
+        ready(PickerKind::ALL.iter().enumerate().map(|(id, kind)| Item::new(kind.name(), id)).collect())
+    }
+
+    fn pick(&self, editor: &mut Editor, items: Vec<Item>, _open: Open) {
+        editor.open_picker(Some(PickerKind::ALL[items[0].id]));
+    }
+}
@@ -0,0 +1,98 @@
+use crate::search::Direction;
+use crossterm::event::{KeyCode, KeyEvent};
+
+#[derive(Clone, Copy, Debug)]
+pub enum PromptKind {
+    SplitSelection,
+    Search(Direction),
+    Command,
+}
+
+impl PromptKind {
+    /// Returns the text displayed in front of the input
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            PromptKind::SplitSelection => "split:",
+            PromptKind::Search(Direction::Forward) => "/",
+            PromptKind::Search(Direction::Backward) => "?",
+            PromptKind::Command => ":",
+        }
+    }
+}
+
+pub enum PromptStatus {
+    Editing,
+    Submitted,
+    Cancelled,
+}
+
+/// A single line of input typed on the command line
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub input: String,
+    /// The history entry currently shown, if browsing the history
+    history_idx: Option<usize>,
+    /// The input that was typed before browsing the history
+    typed: String,
+}
+
+impl Prompt {
+    pub fn new(kind: PromptKind) -> Self {
+        Self {
+            kind,
+            input: String::new(),
+            history_idx: None,
+            typed: String::new(),
+        }
+    }
+
+    /// Handles a key press, the up and down keys browse through `history`
+    pub fn handle_key(&mut self, key: KeyEvent, history: &[String]) -> PromptStatus {
+        match key.code {
+            KeyCode::Up => {
+                let idx = self.history_idx.unwrap_or(history.len());
+                if idx > 0 {
+                    if self.history_idx.is_none() {
+                        self.typed = self.input.clone();
+                    }
+                    self.history_idx = Some(idx - 1);
+                    self.input = history[idx - 1].clone();
+                }
+                PromptStatus::Editing
+            }
+            KeyCode::Down => {
+                match self.history_idx {
+                    Some(idx) if idx + 1 < history.len() => {
+                        self.history_idx = Some(idx + 1);
+                        self.input = history[idx + 1].clone();
+                    }
+                    Some(_) => {
+                        self.history_idx = None;
+                        self.input = self.typed.clone();
+                    }
+                    None => (),
+                }
+                PromptStatus::Editing
+            }
+            KeyCode::Esc => PromptStatus::Cancelled,
+            KeyCode::Enter => PromptStatus::Submitted,
+            KeyCode::Backspace => {
+                // Backspacing on an empty prompt closes it
+                match self.input.pop() {
+                    Some(_) => PromptStatus::Editing,
+                    None => PromptStatus::Cancelled,
+                }
+            }
+            KeyCode::Char(c) => {
+                self.input.push(c);
+                PromptStatus::Editing
+            }
+            _ => PromptStatus::Editing,
+        }
+    }
+
+    /// Returns the full line as it should be displayed
+    pub fn line(&self) -> String {
+        format!("{}{}", self.kind.prefix(), self.input)
+    }
+}
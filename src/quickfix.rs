@@ -0,0 +1,107 @@
+use crate::{
+    buffer::Buffer,
+    utils::{BufCol, BufRow},
+};
+use ignore::WalkBuilder;
+use regex::Regex;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+/// A location in a file, such as a match of a project search
+pub struct Entry {
+    pub path: PathBuf,
+    pub row: BufRow,
+    pub col: BufCol,
+    /// The line the location is on
+    pub text: String,
+}
+
+impl Entry {
+    /// Returns the entry as it is shown in the quickfix window, formatted as `path:line:col: text`
+    pub fn line(&self) -> String {
+        format!("{}:{}:{}: {}", self.path.display(), *self.row + 1, *self.col + 1, self.text.trim())
+    }
+}
+
+/// A list of locations to jump between with `:cn` and `:cp`
+#[derive(Default)]
+pub struct Quickfix {
+    /// The command that filled the list
+    pub title: String,
+    pub entries: Vec<Entry>,
+    /// The entry that was jumped to last
+    pub idx: usize,
+}
+
+impl Quickfix {
+    pub fn new(title: String) -> Self {
+        Self { title, ..Default::default() }
+    }
+
+    /// Returns the contents of the quickfix window, one line per entry
+    pub fn text(&self) -> String {
+        self.entries.iter().map(|entry| entry.line() + "\n").collect()
+    }
+
+    /// Returns the buffer of the quickfix window, which is read-only as its rows stand for the entries
+    pub fn buffer(&self) -> Buffer {
+        let mut buf = Buffer::scratch("[Quickfix List]", &self.text());
+        buf.readonly = true;
+        buf
+    }
+}
+
+pub enum GrepEvent {
+    Found(Entry),
+    Done,
+}
+
+/// Returns the directory containing `.git` above the working directory, or the working directory itself
+pub fn project_root() -> PathBuf {
+    let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    cwd.ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .map_or_else(|| cwd.clone(), Path::to_path_buf)
+}
+
+/// Searches every line of the files under `root` that aren't ignored by `.gitignore` on a background thread.
+/// The search stops early when the receiver is dropped.
+pub fn grep(regex: Regex, root: PathBuf) -> Receiver<GrepEvent> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        if grep_files(&regex, &root, &sender).is_ok() {
+            sender.send(GrepEvent::Done).ok();
+        }
+    });
+    receiver
+}
+
+fn grep_files(regex: &Regex, root: &Path, sender: &Sender<GrepEvent>) -> Result<(), mpsc::SendError<GrepEvent>> {
+    let cwd = env::current_dir().unwrap_or_default();
+    for file in WalkBuilder::new(root).build().flatten() {
+        if !file.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        // Binary files and files that aren't valid UTF-8 are skipped
+        let text = match fs::read_to_string(file.path()) {
+            Ok(text) if !text.contains('\0') => text,
+            _ => continue,
+        };
+        let path = file.path().strip_prefix(&cwd).unwrap_or_else(|_| file.path());
+        for (row, line) in text.lines().enumerate() {
+            if let Some(m) = regex.find(line) {
+                sender.send(GrepEvent::Found(Entry {
+                    path: path.to_path_buf(),
+                    row: row.into(),
+                    col: line[..m.start()].chars().count().into(),
+                    text: line.to_string(),
+                }))?;
+            }
+        }
+    }
+    Ok(())
+}
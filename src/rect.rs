@@ -1,15 +1,32 @@
-use crate::utils::{BufCol, BufPos, BufRow, TermCol, TermPos, TermRow};
+use crate::utils::{BufRow, DisplayCol, DisplayPos, TermCol, TermPos, TermRow};
+use serde::{Deserialize, Serialize};
 
 const MARGIN_LEFT: usize = 5;
 const MARGIN_RIGHT: usize = 5;
 const MARGIN_TOP: usize = 3;
 const MARGIN_BOTTOM: usize = 3;
 
+/// The number of rows and columns that are kept visible around the cursor when scrolling
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct Margins {
+    pub left: usize,
+    pub right: usize,
+    pub top: usize,
+    pub bottom: usize,
+}
+
+impl Default for Margins {
+    fn default() -> Self {
+        Self { left: MARGIN_LEFT, right: MARGIN_RIGHT, top: MARGIN_TOP, bottom: MARGIN_BOTTOM }
+    }
+}
+
 pub struct Rect {
     pub width: TermCol,
     pub height: TermRow,
     pub offset: TermPos,
-    pub scroll: BufPos,
+    pub scroll: DisplayPos,
 }
 
 impl Rect {
@@ -18,7 +35,7 @@ impl Rect {
             width: width.into(),
             height: height.into(),
             offset: TermPos::new(x.into(), y.into()),
-            scroll: BufPos::default(),
+            scroll: DisplayPos::default(),
         }
     }
 
@@ -28,62 +45,82 @@ impl Rect {
     }
 
     /// Scrolls to make sure the cursor is visible, and returns the amount that was scrolled vertically
-    pub fn scroll_to_cursor(&mut self, cursor: BufPos) -> isize {
+    pub fn scroll_to_cursor(&mut self, cursor: DisplayPos, margins: Margins) -> isize {
         let old_scroll_y = *self.scroll.y as isize;
+        self.scroll_to_col(cursor.x, margins);
+        // Margins that don't fit in the rect are made smaller, so that the cursor always has a place to stay
+        let height = *self.height as usize;
+        let (top, bottom) = (margins.top.min(height.saturating_sub(1) / 2), margins.bottom.min(height.saturating_sub(1) / 2));
+        // Scroll up if cursor is above bounds
+        if cursor.y.saturating_sub(*self.scroll.y) < top {
+            self.scroll.y = cursor.y.saturating_sub(top).into();
+        }
+        // Scroll down if cursor is below bounds (+2 is for status bar height)
+        if cursor.y.saturating_sub(*self.scroll.y) + bottom > height {
+            self.scroll.y = (*cursor.y + bottom)
+                .saturating_sub(height)
+                .into();
+        }
+        *self.scroll.y as isize - old_scroll_y
+    }
+
+    /// Scrolls sideways to make sure a display column is visible
+    pub fn scroll_to_col(&mut self, col: DisplayCol, margins: Margins) {
+        let width = *self.width as usize;
+        let (left, right) = (margins.left.min(width.saturating_sub(1) / 2), margins.right.min(width.saturating_sub(1) / 2));
         // Scroll left if cursor is on left side of bounds
-        if cursor.x.saturating_sub(*self.scroll.x) < MARGIN_LEFT {
-            self.scroll.x = cursor.x.saturating_sub(MARGIN_LEFT).into();
+        if col.saturating_sub(*self.scroll.x) < left {
+            self.scroll.x = col.saturating_sub(left).into();
         }
         // Scroll right if cursor is on right side of bounds
-        if cursor.x.saturating_sub(*self.scroll.x) + MARGIN_RIGHT > *self.width as usize {
-            self.scroll.x = (*cursor.x + MARGIN_RIGHT)
-                .saturating_sub(*self.width as usize)
+        if col.saturating_sub(*self.scroll.x) + right > width {
+            self.scroll.x = (*col + right)
+                .saturating_sub(width)
                 .into();
         }
-        // Scroll up if cursor is above bounds
-        if cursor.y.saturating_sub(*self.scroll.y) < MARGIN_TOP {
-            self.scroll.y = cursor.y.saturating_sub(MARGIN_TOP).into();
+    }
+
+    /// Scrolls to make sure the cursor is visible when lines take up other than a single row of the screen, because
+    /// they wrap or are hidden in a closed fold. `sub` is the row of the screen the cursor is on within its line and
+    /// `rows` returns the number of rows of the screen a line takes up. The top row always starts a line that is shown.
+    /// Returns the amount that was scrolled.
+    pub fn scroll_to_row(&mut self, row: BufRow, sub: usize, margins: Margins, rows: impl Fn(usize) -> usize) -> isize {
+        let old_scroll_y = *self.scroll.y as isize;
+        let height = *self.height as usize;
+        let (top, bottom) = (margins.top.min(height.saturating_sub(1) / 2), margins.bottom.min(height.saturating_sub(1) / 2));
+        let row = *row;
+        // The lines above the ones that fill the rect up to the cursor can't be visible
+        let (mut lowest, mut shown) = (row, 0);
+        while lowest > 0 && shown < height {
+            lowest -= 1;
+            shown += rows(lowest);
         }
-        // Scroll down if cursor is below bounds (+2 is for status bar height)
-        if cursor.y.saturating_sub(*self.scroll.y) + MARGIN_BOTTOM > *self.height as usize {
-            self.scroll.y = (*cursor.y + MARGIN_BOTTOM)
-                .saturating_sub(*self.height as usize)
-                .into();
+        let mut scroll = (*self.scroll.y).clamp(lowest, row);
+        let mut above = (scroll..row).map(&rows).sum::<usize>() + sub;
+        while scroll > 0 && above < top {
+            scroll -= 1;
+            above += rows(scroll);
+        }
+        while scroll < row && (above + 1 + bottom > height || rows(scroll) == 0) {
+            above -= rows(scroll);
+            scroll += 1;
         }
+        self.scroll.y = scroll.into();
         *self.scroll.y as isize - old_scroll_y
     }
 
-    #[allow(unused)]
     /// Returns the leftmost column of the currently visible area
-    pub fn left(&self) -> BufCol {
+    pub fn left(&self) -> DisplayCol {
         self.scroll.x
     }
 
-    #[allow(unused)]
-    /// Returns the rightmost column of the currently visible area
-    pub fn right(&self) -> BufCol {
-        self.scroll.x + self.width.as_bufcol()
+    /// Returns the column after the rightmost one of the currently visible area
+    pub fn right(&self) -> DisplayCol {
+        self.scroll.x + (*self.width as usize).into()
     }
 
     /// Returns the top row of the currently visible area
     pub fn top(&self) -> BufRow {
         self.scroll.y
     }
-
-    /// Returns the bottom row of the currently visible area
-    pub fn bottom(&self) -> BufRow {
-        self.scroll.y + self.height.as_bufrow()
-    }
-
-    pub fn terminal_x(&self, x: BufCol) -> TermCol {
-        (x - self.scroll.x).as_termcol() + self.offset.x
-    }
-
-    pub fn terminal_y(&self, y: BufRow) -> TermRow {
-        (y - self.scroll.y).as_termrow() + self.offset.y
-    }
-
-    pub fn terminal_pos(&self, pos: BufPos) -> TermPos {
-        TermPos::new(self.terminal_x(pos.x), self.terminal_y(pos.y))
-    }
 }
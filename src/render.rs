@@ -1,7 +1,9 @@
 use crate::{
     buffer::Buffer,
-    rect::Rect,
-    utils::{TermCol, TermRow, TermPos, BufRange},
+    grapheme,
+    highlight::{self, Layer},
+    utils::{TermCol, TermRow, BufRange},
+    wrap::Layout,
 };
 use crossterm::{
     cursor::{
@@ -9,10 +11,11 @@ use crossterm::{
         SetCursorShape, Show,
     },
     queue,
-    style::{ContentStyle, Print, ResetColor, SetAttributes, SetBackgroundColor, SetForegroundColor},
-    terminal::{Clear, ClearType, ScrollUp, ScrollDown},
+    style::{Attribute, ContentStyle, Print, SetAttribute, SetAttributes, SetBackgroundColor, SetForegroundColor},
+    terminal::{Clear, ClearType},
     Result,
 };
+use unicode_segmentation::UnicodeSegmentation;
 use std::{
     io::{self, Write, Stdout},
     fmt::Display,
@@ -26,6 +29,7 @@ impl Renderer {
     }
 
     pub fn set_style(&mut self, style: &ContentStyle) -> Result<()> {
+        self.reset_style()?;
         if let Some(fg) = style.foreground_color {
             queue!(self.0, SetForegroundColor(fg))?
         };
@@ -36,7 +40,7 @@ impl Renderer {
     }
 
     pub fn reset_style(&mut self) -> Result<()> {
-        queue!(self.0, ResetColor)
+        queue!(self.0, SetAttribute(Attribute::Reset))
     }
 
     pub fn save_cursor(&mut self) -> Result<()> {
@@ -51,14 +55,6 @@ impl Renderer {
         queue!(self.0, SetCursorShape(shape))
     }
 
-    pub fn scroll_down(&mut self, amount: u16) -> Result<()> {
-        queue!(self.0, ScrollDown(amount))
-    }
-
-    pub fn scroll_up(&mut self, amount: u16) -> Result<()> {
-        queue!(self.0, ScrollUp(amount))
-    }
-
     pub fn move_to(&mut self, x: impl Into<TermCol>, y: impl Into<TermRow>) -> Result<()> {
         queue!(self.0, MoveTo(*x.into(), *y.into()))
     }
@@ -71,20 +67,99 @@ impl Renderer {
         queue!(self.0, Clear(cleartype))
     }
 
-    pub fn print_range(&mut self, rect: &Rect, buf: &Buffer, range: BufRange) -> Result<()> {
-        let mut start = rect.terminal_pos(buf.char_to_pos(range.start));
-        let lines = buf.slice(range.into()).lines();
-        for line in lines {
-            self.move_to(start.x, start.y)?;
-            self.clear(ClearType::UntilNewLine)?;
-            self.print(line)?;
-            start = TermPos::new(rect.offset.x, *start.y + 1);
+    /// Prints a range line by line, into the rows of the screen the layout shows the lines on.
+    /// With an overlay style, line breaks are shown as spaces so they are visible.
+    pub fn print_range(&mut self, layout: &Layout, buf: &Buffer, range: BufRange, overlay: Option<&ContentStyle>) -> Result<()> {
+        let pos = buf.char_to_display_pos(range.start);
+        let (mut row, mut col) = (pos.y, *pos.x);
+        for line in buf.slice(range).lines() {
+            let line = line.to_string();
+            let text = match overlay {
+                Some(_) => line.replace("\r\n", " ").replace('\n', " "),
+                None => line.trim_end_matches(&['\n', '\r'][..]).to_string(),
+            };
+            let end = col + grapheme::text_width(&text, col, buf.filetype.tabstop);
+            for (y, segment) in layout.segments(row) {
+                if usize::max(col, segment.left) >= usize::min(end, segment.right) {
+                    continue;
+                }
+                let pos = layout.terminal_pos(*y, segment, usize::max(col, segment.left));
+                self.move_to(pos.x, pos.y)?;
+                if let Some(style) = overlay {
+                    self.set_style(style)?;
+                }
+                self.print(clip(&text, col, segment.left, segment.right, buf.filetype.tabstop))?;
+            }
+            row = row + 1.into();
+            col = 0;
+        }
+        Ok(())
+    }
+
+    /// Prints a range in the `base` style, with the parts covered by layers in the style of those layers
+    pub fn print_layered(&mut self, layout: &Layout, buf: &Buffer, range: BufRange, base: Option<&ContentStyle>, layers: &[Layer]) -> Result<()> {
+        let mut bounds = vec![*range.start, *range.end];
+        for r in layers.iter().flat_map(|layer| layer.ranges.iter()) {
+            bounds.extend([*r.start, *r.end].iter().filter(|b| **b > *range.start && **b < *range.end));
+        }
+        bounds.sort_unstable();
+        bounds.dedup();
+        for piece in bounds.windows(2).map(|w| BufRange::new(w[0].into(), w[1].into())) {
+            let overlay = layers
+                .iter()
+                .filter(|layer| layer.covers(piece))
+                .fold(None, |style: Option<ContentStyle>, layer| {
+                    Some(highlight::combine(&style.or_else(|| base.copied()).unwrap_or_default(), &layer.style))
+                });
+            match base {
+                Some(style) => self.set_style(style)?,
+                None => self.reset_style()?,
+            }
+            self.print_range(layout, buf, piece, overlay.as_ref())?;
         }
         Ok(())
     }
 
+    /// Draws a box with a title in its top border, clearing what was drawn inside it
+    pub fn draw_box(&mut self, x: u16, y: u16, width: u16, height: u16, title: &str, style: &ContentStyle) -> Result<()> {
+        let inner = width.saturating_sub(2) as usize;
+        let title: String = title.chars().take(inner).collect();
+        self.set_style(style)?;
+        self.move_to(x, y)?;
+        self.print(format!("┌{}{}┐", title, "─".repeat(inner - title.chars().count())))?;
+        for row in 1..height.saturating_sub(1) {
+            self.move_to(x, y + row)?;
+            self.print(format!("│{}│", " ".repeat(inner)))?;
+        }
+        self.move_to(x, y + height.saturating_sub(1))?;
+        self.print(format!("└{}┘", "─".repeat(inner)))?;
+        self.reset_style()
+    }
+
     pub fn flush(&mut self) -> Result<()> {
         self.0.flush()
     }
 }
 
+/// Returns the part of text that starts at display column `col` that lies between the display columns `left` and
+/// `right`. Tabs are replaced with the spaces that reach up to the next tab stop, and so are the visible parts of
+/// wide characters that are cut off by the edges.
+pub fn clip(text: &str, mut col: usize, left: usize, right: usize, tabstop: usize) -> String {
+    let mut clipped = String::with_capacity(text.len());
+    for grapheme in text.graphemes(true) {
+        let (start, end) = (col, col + grapheme::width(grapheme, col, tabstop));
+        col = end;
+        if end <= left {
+            continue;
+        }
+        if start >= right {
+            break;
+        }
+        if grapheme == "\t" || start < left || end > right {
+            clipped.extend(std::iter::repeat_n(' ', usize::min(end, right) - usize::max(start, left)));
+        } else {
+            clipped.push_str(grapheme);
+        }
+    }
+    clipped
+}
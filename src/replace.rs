@@ -0,0 +1,187 @@
+use crate::{quickfix::Entry, utils::BufRow};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+/// The number of lines shown above and below every hit
+const CONTEXT: usize = 1;
+
+/// A line that a project wide replacement changes
+pub struct Hit {
+    pub path: PathBuf,
+    pub row: BufRow,
+    /// The line before replacing
+    pub original: String,
+    /// The line after replacing
+    pub replaced: String,
+}
+
+/// The replacements of a project wide find and replace, shown in a buffer where they can be reviewed before
+/// applying them. Every hit is shown as the original line prefixed by `-` and the replaced line prefixed by `+`.
+/// Deleting a `+` line excludes the hit, and editing it changes what the line is replaced with.
+#[derive(Default)]
+pub struct Preview {
+    pub hits: Vec<Hit>,
+}
+
+impl Preview {
+    /// Replaces the lines of the quickfix entries, `lines` returns the lines of a file
+    pub fn new(
+        entries: &[Entry],
+        replace: impl Fn(&str) -> String,
+        lines: impl Fn(&Path) -> Option<Vec<String>>,
+    ) -> Self {
+        let mut hits: Vec<Hit> = Vec::new();
+        // Every file is read once, however many entries it has
+        let mut files: HashMap<&Path, Option<Vec<String>>> = HashMap::new();
+        let mut seen: HashSet<(&Path, usize)> = HashSet::new();
+        for entry in entries {
+            if !seen.insert((&entry.path, *entry.row)) {
+                continue;
+            }
+            let file = files.entry(&entry.path).or_insert_with(|| lines(&entry.path));
+            let original = match file.as_ref().and_then(|lines| lines.get(*entry.row)) {
+                Some(line) => line.clone(),
+                None => continue,
+            };
+            let replaced = replace(&original);
+            if replaced != original {
+                hits.push(Hit { path: entry.path.clone(), row: entry.row, original, replaced });
+            }
+        }
+        Self { hits }
+    }
+
+    /// Returns the files with hits, in the order they were found
+    pub fn paths(&self) -> Vec<&Path> {
+        let mut paths: Vec<&Path> = Vec::new();
+        for hit in &self.hits {
+            if !paths.contains(&hit.path.as_path()) {
+                paths.push(&hit.path);
+            }
+        }
+        paths
+    }
+
+    /// Returns the contents of the preview buffer, `lines` returns the lines of a file for the context
+    pub fn text(&self, lines: impl Fn(&Path) -> Option<Vec<String>>) -> String {
+        let mut text = String::new();
+        for path in self.paths() {
+            let lines = lines(path).unwrap_or_default();
+            let context = |rows: Range<usize>| -> String {
+                let shown = lines.iter().enumerate().skip(rows.start).take(rows.len());
+                shown.map(|(row, line)| format!("{:>5}  {}\n", row + 1, line)).collect()
+            };
+            let hits: Vec<&Hit> = self.hits.iter().filter(|hit| hit.path == path).collect();
+            text += &format!("{}\n", path.display());
+            let mut last_shown = None;
+            for (i, hit) in hits.iter().enumerate() {
+                let first = usize::max(hit.row.saturating_sub(CONTEXT), last_shown.map_or(0, |row| row + 1));
+                if last_shown.is_some_and(|row| first > row + 1) {
+                    text += "  ...\n";
+                }
+                text += &context(first..*hit.row);
+                text += &format!("{:>5} -{}\n", *hit.row + 1, hit.original);
+                text += &format!("{:>5} +{}\n", *hit.row + 1, hit.replaced);
+                // The context below stops before the next hit
+                let next = hits.get(i + 1).map_or(usize::MAX, |next| *next.row);
+                let last = usize::min(*hit.row + CONTEXT, lines.len().saturating_sub(1)).min(next.saturating_sub(1));
+                text += &context(*hit.row + 1..last + 1);
+                last_shown = Some(usize::max(last, *hit.row));
+            }
+            text += "\n";
+        }
+        text
+    }
+
+    /// Whether a replaced line contains a line break, which the preview can't show as a single `+` line
+    pub fn has_line_breaks(&self) -> bool {
+        self.hits.iter().any(|hit| hit.replaced.contains(&['\n', '\r'][..]))
+    }
+
+    /// Reads the replacements back from the edited preview buffer. Returns the hits that are still present,
+    /// together with the line each should be replaced with.
+    pub fn parse(&self, text: &str) -> Vec<(&Hit, String)> {
+        lazy_static! {
+            static ref REPLACED: Regex = Regex::new(r"^\s*(\d+) \+(.*)$").unwrap();
+        }
+        // Only the lines that name a file with hits start a file, as rows from 10000 on aren't indented
+        let paths: Vec<(String, &Path)> = self.paths().into_iter().map(|path| (path.display().to_string(), path)).collect();
+        let mut path = None;
+        let mut replacements = Vec::new();
+        for line in text.lines() {
+            if let Some((_, file)) = paths.iter().find(|(name, _)| name == line) {
+                path = Some(*file);
+            } else if let (Some(captures), Some(path)) = (REPLACED.captures(line), path) {
+                let row = captures[1].parse::<usize>().unwrap_or(0).saturating_sub(1);
+                if let Some(hit) = self.hits.iter().find(|hit| hit.path == path && *hit.row == row) {
+                    replacements.push((hit, captures[2].to_string()));
+                }
+            }
+        }
+        replacements
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::BufRow;
+
+    fn hit(path: &str, row: usize, original: &str, replaced: &str) -> Hit {
+        Hit {
+            path: PathBuf::from(path),
+            row: BufRow(row),
+            original: original.to_string(),
+            replaced: replaced.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_text_round_trip() {
+        let preview = Preview {
+            hits: vec![hit("a.rs", 2, "foo", "bar"), hit("a.rs", 12344, "foo()", "bar()"), hit("b.rs", 0, "x", "y")],
+        };
+        let lines = |path: &Path| -> Option<Vec<String>> {
+            let len = if path == Path::new("a.rs") { 12400 } else { 3 };
+            Some((0..len).map(|row| format!("line {}", row)).collect())
+        };
+        let text = preview.text(lines);
+        assert!(text.contains("12345 +bar()\n"));
+        let edited = text.replace("12345 +bar()", "12345 +baz()");
+        let parsed: Vec<(usize, String)> = preview.parse(&edited).into_iter().map(|(hit, line)| (*hit.row, line)).collect();
+        assert_eq!(parsed, vec![(2, "bar".into()), (12344, "baz()".into()), (0, "y".into())]);
+    }
+
+    #[test]
+    fn new_reads_every_file_once() {
+        let entry = |path: &str, row: usize| Entry {
+            path: PathBuf::from(path),
+            row: BufRow(row),
+            col: 0.into(),
+            text: String::new(),
+        };
+        let entries = [entry("a.rs", 0), entry("a.rs", 2), entry("a.rs", 0), entry("b.rs", 1)];
+        let reads = std::cell::Cell::new(0);
+        let preview = Preview::new(&entries, |line| line.replace("foo", "bar"), |_| {
+            reads.set(reads.get() + 1);
+            Some(vec![String::from("foo"), String::from("foo"), String::from("foo")])
+        });
+        assert_eq!(reads.get(), 2);
+        let hits: Vec<(&Path, usize)> = preview.hits.iter().map(|hit| (hit.path.as_path(), *hit.row)).collect();
+        assert_eq!(hits, vec![(Path::new("a.rs"), 0), (Path::new("a.rs"), 2), (Path::new("b.rs"), 1)]);
+    }
+
+    #[test]
+    fn deleted_hit_is_dropped() {
+        let preview = Preview { hits: vec![hit("a.rs", 0, "foo", "bar"), hit("a.rs", 1, "foo", "baz")] };
+        let text = preview.text(|_| Some(vec![String::from("foo"), String::from("foo")]));
+        let edited = text.replace("    1 +bar\n", "");
+        let parsed: Vec<usize> = preview.parse(&edited).iter().map(|(hit, _)| *hit.row).collect();
+        assert_eq!(parsed, vec![1]);
+    }
+}
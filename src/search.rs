@@ -0,0 +1,89 @@
+use crate::{buffer::Buffer, utils::BufRange};
+use regex::{Regex, RegexBuilder};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+impl Direction {
+    pub fn reversed(self) -> Self {
+        match self {
+            Direction::Forward => Direction::Backward,
+            Direction::Backward => Direction::Forward,
+        }
+    }
+}
+
+/// The most recent search, repeated with `n` and `N`
+pub struct Search {
+    pub pattern: String,
+    pub regex: Regex,
+    pub direction: Direction,
+}
+
+impl Search {
+    /// Compiles a pattern, which ignores case unless it contains an uppercase character
+    pub fn new(pattern: &str, direction: Direction) -> Result<Self, regex::Error> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(!has_uppercase(pattern))
+            .build()?;
+        Ok(Self {
+            pattern: pattern.to_string(),
+            regex,
+            direction,
+        })
+    }
+
+    /// Searches for a whole word, matching case exactly
+    pub fn word(word: &str, direction: Direction) -> Self {
+        let pattern = format!(r"\b{}\b", regex::escape(word));
+        Self {
+            regex: Regex::new(&pattern).expect("Escaped word should be a valid regex"),
+            pattern,
+            direction,
+        }
+    }
+
+    /// Finds the next match from the cursor in the given direction, wrapping around the end of the buffer.
+    /// Also returns whether the search wrapped around.
+    pub fn find(&self, buf: &Buffer, direction: Direction) -> Option<(BufRange, bool)> {
+        match direction {
+            Direction::Forward => {
+                let from = usize::min(*buf.idx + 1, buf.text.len_chars()).into();
+                buf.find_next(&self.regex, from)
+                    .map(|m| (m, false))
+                    .or_else(|| buf.find_next(&self.regex, 0.into()).map(|m| (m, true)))
+            }
+            Direction::Backward => buf
+                .find_prev(&self.regex, buf.idx)
+                .map(|m| (m, false))
+                .or_else(|| {
+                    buf.find_prev(&self.regex, buf.text.len_chars().into())
+                        .map(|m| (m, true))
+                }),
+        }
+    }
+}
+
+/// Whether a pattern contains an uppercase character, ignoring escape sequences such as `\S`
+fn has_uppercase(pattern: &str) -> bool {
+    let mut escaped = false;
+    for c in pattern.chars() {
+        if !escaped && c.is_uppercase() {
+            return true;
+        }
+        escaped = !escaped && c == '\\';
+    }
+    false
+}
+
+/// Adds an entry to a history, moving it to the end if it was already present
+pub fn add_to_history(history: &mut Vec<String>, entry: &str) {
+    if entry.is_empty() {
+        return;
+    }
+    history.retain(|e| e != entry);
+    history.push(entry.to_string());
+}
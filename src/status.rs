@@ -0,0 +1,146 @@
+use crate::{
+    buffer::{Buffer, EditMode},
+    highlight::language,
+    quickfix,
+};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// A part of the status line
+#[derive(Serialize, Deserialize, Clone)]
+pub enum StatusItem {
+    /// The mode of the buffer, in the style of the mode
+    Mode,
+    /// The path of the file relative to the root of the project
+    Path,
+    /// Whether the buffer was edited since saving, and whether the file can't be written
+    Flags,
+    /// The language of the file, or its extension
+    Filetype,
+    Encoding,
+    /// The line breaks of the file, `lf` or `crlf`
+    LineEnding,
+    /// The line and the display column of the cursor, counting from 1. Where tabs or wide characters make the
+    /// character column differ, it is shown first as in `12:5-9`.
+    Position,
+    /// How far through the file the cursor is
+    Percentage,
+    /// The number of characters or lines that are selected in visual mode
+    Selection,
+    /// The git branch the project is on
+    Branch,
+    Text(String),
+}
+
+/// The parts of the status line, drawn from the left and the right edge of the window
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct StatusLine {
+    pub left: Vec<StatusItem>,
+    pub right: Vec<StatusItem>,
+}
+
+impl Default for StatusLine {
+    fn default() -> Self {
+        Self {
+            left: vec![StatusItem::Mode, StatusItem::Path, StatusItem::Flags, StatusItem::Branch],
+            right: vec![
+                StatusItem::Selection,
+                StatusItem::Filetype,
+                StatusItem::Encoding,
+                StatusItem::LineEnding,
+                StatusItem::Position,
+                StatusItem::Percentage,
+            ],
+        }
+    }
+}
+
+/// What the status line shows about the file of a buffer that doesn't change while editing it, which is looked up
+/// once when the buffer is shown
+pub struct FileInfo {
+    path: String,
+    readonly: bool,
+    branch: Option<String>,
+}
+
+impl FileInfo {
+    pub fn new(path: &Path, readonly: bool) -> Self {
+        let root = quickfix::project_root();
+        let absolute = fs::canonicalize(path).ok();
+        let relative = absolute.as_deref().and_then(|absolute| absolute.strip_prefix(&root).ok());
+        Self {
+            path: relative.unwrap_or(path).display().to_string(),
+            readonly: readonly || fs::metadata(path).is_ok_and(|metadata| metadata.permissions().readonly()),
+            branch: absolute.as_deref().and_then(Path::parent).and_then(branch),
+        }
+    }
+}
+
+/// Returns the branch checked out in the repository a directory lies in, or the start of the commit if none is
+fn branch(dir: &Path) -> Option<String> {
+    let git = dir.ancestors().map(|dir| dir.join(".git")).find(|git| git.exists())?;
+    let head = fs::read_to_string(git.join("HEAD")).ok()?;
+    let head = head.trim();
+    Some(match head.strip_prefix("ref: refs/heads/") {
+        Some(branch) => branch.to_string(),
+        None => head.chars().take(7).collect(),
+    })
+}
+
+pub fn mode_name(mode: EditMode) -> &'static str {
+    match mode {
+        EditMode::Normal => "NORMAL",
+        EditMode::Insert => "INSERT",
+        EditMode::Visual => "VISUAL",
+        EditMode::VisualBlock => "V-BLOCK",
+    }
+}
+
+/// Returns the text of an item, or `None` if it has nothing to show
+pub fn text(item: &StatusItem, buf: &Buffer, info: &FileInfo) -> Option<String> {
+    Some(match item {
+        StatusItem::Mode => mode_name(buf.mode).to_string(),
+        StatusItem::Path => info.path.clone(),
+        StatusItem::Flags => {
+            let flags: String = [(buf.edited, "[+]"), (info.readonly, "[RO]")]
+                .iter()
+                .filter(|(set, _)| *set)
+                .map(|(_, flag)| *flag)
+                .collect();
+            return Some(flags).filter(|flags| !flags.is_empty());
+        }
+        StatusItem::Filetype => match language::detect(&buf.path) {
+            Some(language) => language.name().to_string(),
+            None => buf.path.extension()?.to_string_lossy().to_string(),
+        },
+        StatusItem::Encoding => String::from("utf-8"),
+        StatusItem::LineEnding => {
+            let line = buf.text.line(0);
+            match line.len_chars().checked_sub(2).map(|i| line.char(i)) {
+                Some('\r') => String::from("crlf"),
+                _ => String::from("lf"),
+            }
+        }
+        StatusItem::Position => {
+            let (col, display_col) = (*buf.col() + 1, *buf.display_col(buf.idx) + 1);
+            match col == display_col {
+                true => format!("{}:{}", *buf.row() + 1, col),
+                false => format!("{}:{}-{}", *buf.row() + 1, col, display_col),
+            }
+        }
+        StatusItem::Percentage => format!("{}%", usize::min((*buf.row() + 1) * 100 / (*buf.last_row() + 1), 100)),
+        StatusItem::Selection => {
+            let range = buf.selection().filter(|_| buf.mode.is_visual())?;
+            let (start, end) = (buf.char_to_pos(range.start), buf.char_to_pos(range.end - 1.into()));
+            let rows = *end.y - *start.y + 1;
+            match buf.mode {
+                EditMode::VisualBlock => format!("{}x{}", rows, start.x.abs_diff(*end.x) + 1),
+                _ if rows > 1 => format!("{} lines", rows),
+                _ => format!("{} chars", *range.end - *range.start),
+            }
+        }
+        StatusItem::Branch => info.branch.clone()?,
+        StatusItem::Text(text) => text.clone(),
+    })
+}
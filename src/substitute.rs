@@ -0,0 +1,307 @@
+use crate::{
+    action::BufferAction,
+    buffer::Buffer,
+    utils::{BufCharIdx, BufRange, BufRow, Selection},
+};
+use regex::{Captures, Regex};
+use std::collections::HashSet;
+
+#[derive(Clone, Copy, Default)]
+pub struct Flags {
+    /// Replace every match in a line instead of only the first
+    pub global: bool,
+    /// Ask for confirmation before replacing each match
+    pub confirm: bool,
+    /// Overrides smart case when set
+    pub ignore_case: Option<bool>,
+}
+
+impl Flags {
+    pub fn parse(flags: &str) -> Result<Self, String> {
+        let mut parsed = Flags::default();
+        for flag in flags.trim().chars() {
+            match flag {
+                'g' => parsed.global = true,
+                'c' => parsed.confirm = true,
+                'i' => parsed.ignore_case = Some(true),
+                'I' => parsed.ignore_case = Some(false),
+                c => return Err(format!("Unknown substitute flag: {}", c)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// A match of a substitution together with the text that replaces it
+pub struct Match {
+    pub range: BufRange,
+    pub replacement: String,
+}
+
+/// How many matches a substitution replaced, and on how many lines
+#[derive(Clone, Copy, Default)]
+pub struct Summary {
+    pub replaced: usize,
+    pub lines: usize,
+}
+
+impl Summary {
+    /// Adds the replacements of another substitution, such as one on a later line of `:global`
+    pub fn add(&mut self, other: Summary) {
+        self.replaced += other.replaced;
+        self.lines += other.lines;
+    }
+
+    /// Returns the message that reports the replacements, or `None` if nothing was replaced
+    pub fn message(&self) -> Option<String> {
+        Some(match (self.replaced, self.lines) {
+            (0, _) => return None,
+            (1, _) => String::from("1 substitution on 1 line"),
+            (replaced, 1) => format!("{} substitutions on 1 line", replaced),
+            (replaced, lines) => format!("{} substitutions on {} lines", replaced, lines),
+        })
+    }
+}
+
+/// A substitution in progress. Matches are replaced one at a time so that each can be confirmed,
+/// and all replacements are undone as a single step.
+pub struct Substitution {
+    regex: Regex,
+    replacement: String,
+    global: bool,
+    /// Where to continue searching, or `None` when the end of the range has been reached
+    next: Option<BufCharIdx>,
+    last_row: BufRow,
+    /// The actions that undo the replacements made so far
+    inverses: Vec<BufferAction>,
+    replaced: usize,
+    rows: HashSet<usize>,
+}
+
+impl Substitution {
+    pub fn new(regex: Regex, replacement: String, global: bool, rows: (BufRow, BufRow), buf: &Buffer) -> Self {
+        Self {
+            regex,
+            replacement,
+            global,
+            next: Some(buf.row_to_char(rows.0)),
+            last_row: rows.1,
+            inverses: Vec::new(),
+            replaced: 0,
+            rows: HashSet::new(),
+        }
+    }
+
+    /// Finds the next match to replace
+    pub fn next_match(&self, buf: &Buffer) -> Option<Match> {
+        let next = self.next?;
+        let mut locations = self.regex.capture_locations();
+        for row in *buf.char_to_row(next)..=*self.last_row {
+            let line = buf.line_str(row.into());
+            let line_start = buf.row_to_char(row.into());
+            let from = line
+                .char_indices()
+                .nth(next.saturating_sub(*line_start))
+                .map_or(line.len(), |(byte, _)| byte);
+            let from = if *line_start < *next { from } else { 0 };
+            if let Some(found) = self.regex.captures_read_at(&mut locations, &line, from) {
+                let to_char = |byte: usize| line_start + line[..byte].chars().count().into();
+                let replacement = expand(&self.replacement, |i| locations.get(i).map(|(s, e)| &line[s..e]));
+                let range = BufRange::new(to_char(found.start()), to_char(found.end()));
+                return Some(Match { range, replacement });
+            }
+        }
+        None
+    }
+
+    /// Replaces a match returned by `next_match`
+    pub fn replace(&mut self, buf: &mut Buffer, m: Match) -> Result<(), &'static str> {
+        let row = *buf.char_to_row(m.range.start);
+        let delete = BufferAction::Delete(Selection::Bounds(m.range.start, m.range.end));
+        self.inverses.push(buf.record(delete)?);
+        let inserted = m.replacement.chars().count();
+        let new_rows = m.replacement.matches('\n').count();
+        self.inverses.push(buf.record(BufferAction::InsertAt(m.range.start, m.replacement))?);
+        self.replaced += 1;
+        self.rows.insert(row + new_rows);
+        self.last_row = self.last_row + new_rows.into();
+        self.advance(buf, m.range.start, m.range.start + inserted.into(), m.range.start == m.range.end);
+        Ok(())
+    }
+
+    /// Skips a match returned by `next_match` without replacing it
+    pub fn skip(&mut self, buf: &Buffer, m: Match) {
+        self.advance(buf, m.range.start, m.range.end, m.range.start == m.range.end);
+    }
+
+    /// Continues after the match or its replacement, which lies between `start` and `end`
+    fn advance(&mut self, buf: &Buffer, start: BufCharIdx, end: BufCharIdx, empty: bool) {
+        let next = if !self.global {
+            buf.row_to_char(buf.char_to_row(start) + 1.into())
+        } else if empty {
+            // Step over empty matches so they are not found again
+            end + 1.into()
+        } else {
+            end
+        };
+        self.next = Some(next).filter(|next| **next < buf.text.len_chars() && *buf.char_to_row(*next) <= *self.last_row);
+    }
+
+    /// Replaces all remaining matches
+    pub fn replace_all(&mut self, buf: &mut Buffer) -> Result<(), &'static str> {
+        while let Some(m) = self.next_match(buf) {
+            self.replace(buf, m)?;
+        }
+        Ok(())
+    }
+
+    /// Stores the replacements as one undo step, moves the cursor to the last replaced line,
+    /// and returns a summary of what was replaced
+    pub fn finish(self, buf: &mut Buffer) -> Summary {
+        if let Some(row) = self.rows.iter().max() {
+            let idx = buf.row_to_char((*row).into());
+            buf.apply(BufferAction::MoveTo(idx, 0.into())).unwrap_or(());
+        }
+        let mut inverses = self.inverses;
+        if !inverses.is_empty() {
+            inverses.reverse();
+            buf.push_undo(BufferAction::Batch(inverses));
+        }
+        Summary { replaced: self.replaced, lines: self.rows.len() }
+    }
+}
+
+/// Replaces the first match in a line, or every match if `global` is set
+pub fn replace_line(regex: &Regex, line: &str, replacement: &str, global: bool) -> String {
+    let limit = if global { 0 } else { 1 };
+    regex
+        .replacen(line, limit, |captures: &Captures| expand(replacement, |i| captures.get(i).map(|m| m.as_str())))
+        .into_owned()
+}
+
+enum Case {
+    Upper,
+    Lower,
+}
+
+impl Case {
+    fn apply(&self, c: char, out: &mut String) {
+        match self {
+            Case::Upper => out.extend(c.to_uppercase()),
+            Case::Lower => out.extend(c.to_lowercase()),
+        }
+    }
+}
+
+/// Expands a replacement for a match. `&` and `\0` insert the whole match and `\1` to `\9` a capture group.
+/// `\u` and `\l` change the case of the next character, `\U` and `\L` of everything up to `\E`.
+pub fn expand<'a>(replacement: &str, group: impl Fn(usize) -> Option<&'a str>) -> String {
+    let mut out = String::new();
+    let mut next_case: Option<Case> = None;
+    let mut case: Option<Case> = None;
+    let mut push = |text: &str, next_case: &mut Option<Case>, case: &Option<Case>| {
+        for c in text.chars() {
+            match (next_case.take(), case) {
+                (Some(next_case), _) => next_case.apply(c, &mut out),
+                (None, Some(case)) => case.apply(c, &mut out),
+                (None, None) => out.push(c),
+            }
+        }
+    };
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => push(group(0).unwrap_or(""), &mut next_case, &case),
+            '\\' => match chars.next() {
+                Some(d @ '0'..='9') => {
+                    let i = d.to_digit(10).unwrap_or(0) as usize;
+                    push(group(i).unwrap_or(""), &mut next_case, &case)
+                }
+                Some('u') => next_case = Some(Case::Upper),
+                Some('l') => next_case = Some(Case::Lower),
+                Some('U') => case = Some(Case::Upper),
+                Some('L') => case = Some(Case::Lower),
+                Some('E') | Some('e') => case = None,
+                Some('n') | Some('r') => push("\n", &mut next_case, &case),
+                Some('t') => push("\t", &mut next_case, &case),
+                Some(c) => push(c.encode_utf8(&mut [0; 4]), &mut next_case, &case),
+                None => push("\\", &mut next_case, &case),
+            },
+            c => push(c.encode_utf8(&mut [0; 4]), &mut next_case, &case),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ex::ExCommand;
+    use std::path::PathBuf;
+
+    fn buffer(text: &str) -> Buffer {
+        let mut buf = Buffer::new(PathBuf::from("test.txt"));
+        buf.text = text.into();
+        buf
+    }
+
+    /// Runs `:s` on every line of the buffer
+    fn substitute(buf: &mut Buffer, command: &str) -> Result<(), &'static str> {
+        let (pattern, replacement, flags) = match ExCommand::parse(command) {
+            Ok(ExCommand::Substitute { pattern, replacement, flags, .. }) => (pattern, replacement, flags),
+            _ => panic!("Not a substitution: {}", command),
+        };
+        let rows = (BufRow(0), buf.last_row());
+        let mut substitution = Substitution::new(Regex::new(&pattern).unwrap(), replacement, flags.global, rows, buf);
+        substitution.replace_all(buf)
+    }
+
+    #[test]
+    fn substitute_in_readonly_buffer() {
+        let mut buf = buffer("foo\nfoo\n");
+        buf.readonly = true;
+        assert_eq!(substitute(&mut buf, "s/foo/bar/"), Err("The buffer is read-only"));
+        assert_eq!(buf.text.to_string(), "foo\nfoo\n");
+        assert!(buf.undo.is_empty());
+    }
+
+    /// Expands a replacement for the match of a pattern in a text
+    fn expanded(pattern: &str, text: &str, replacement: &str) -> String {
+        let captures = Regex::new(pattern).unwrap().captures(text).unwrap();
+        expand(replacement, |i| captures.get(i).map(|m| m.as_str()))
+    }
+
+    #[test]
+    fn expand_groups() {
+        assert_eq!(expanded("b+", "abbc", "[&]"), "[bb]");
+        assert_eq!(expanded("(a)(b)", "ab", r"\2\1\0"), "baab");
+        // Groups that didn't take part in the match are empty
+        assert_eq!(expanded("(a)|(b)", "a", r"<\2>"), "<>");
+        assert_eq!(expanded("a", "a", r"\&\\"), "&\\");
+    }
+
+    #[test]
+    fn expand_case() {
+        assert_eq!(expanded("(foo) (bar)", "foo bar", r"\u\1 \U\2\E!"), "Foo BAR!");
+        assert_eq!(expanded("(FOO)", "FOO", r"\l\1 \L\1\e \1"), "fOO foo FOO");
+        assert_eq!(expanded("x", "x", r"a\tb\nc"), "a\tb\nc");
+    }
+
+    #[test]
+    fn parse_flags() {
+        let flags = Flags::parse("gc").unwrap();
+        assert!(flags.global && flags.confirm && flags.ignore_case.is_none());
+        assert_eq!(Flags::parse("iI").unwrap().ignore_case, Some(false));
+        assert!(!Flags::parse(" ").unwrap().global);
+        assert!(Flags::parse("x").is_err());
+    }
+
+    #[test]
+    fn summary_message() {
+        assert_eq!(Summary::default().message(), None);
+        let mut summary = Summary { replaced: 1, lines: 1 };
+        assert_eq!(summary.message(), Some(String::from("1 substitution on 1 line")));
+        summary.add(Summary { replaced: 2, lines: 1 });
+        assert_eq!(summary.message(), Some(String::from("3 substitutions on 2 lines")));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::highlight::language::Language;
+use ropey::Rope;
+use std::{collections::HashSet, ops::{Range, RangeInclusive}};
+use tree_sitter::{InputEdit, Node, Parser, Point, Query, QueryCursor, Tree};
+
+/// The syntax tree of a buffer, which is updated for every edit and parsed again when it is needed
+pub struct Syntax {
+    language: Language,
+    parser: Parser,
+    tree: Option<Tree>,
+    /// Whether the text was edited since it was parsed
+    stale: bool,
+    indents: Query,
+    folds: Query,
+    symbols: Query,
+}
+
+/// A definition in the text, such as a function or a table
+pub struct Symbol {
+    /// The name of the capture of the whole definition, such as `function`
+    pub kind: String,
+    pub name: String,
+    /// The byte the name starts at
+    pub byte: usize,
+}
+
+impl Syntax {
+    pub fn new(language: Language) -> Self {
+        let mut parser = Parser::new();
+        parser.set_language(language.grammar()).expect("Incompatible tree-sitter grammar");
+        Self {
+            language,
+            parser,
+            tree: None,
+            stale: true,
+            indents: Query::new(language.grammar(), language.indent_query()).expect("Invalid indent query"),
+            folds: Query::new(language.grammar(), language.fold_query()).expect("Invalid fold query"),
+            symbols: Query::new(language.grammar(), language.symbol_query()).expect("Invalid symbol query"),
+        }
+    }
+
+    /// Updates the tree for an edit of the text
+    pub fn edit(&mut self, edit: &InputEdit) {
+        if let Some(tree) = &mut self.tree {
+            tree.edit(edit);
+        }
+        self.stale = true;
+    }
+
+    /// Parses the text if it was edited since it was parsed last, reusing the unchanged parts of the old tree
+    pub fn update(&mut self, text: &Rope) {
+        if !self.stale {
+            return;
+        }
+        let mut read = |byte: usize, _: Point| -> &[u8] {
+            if byte >= text.len_bytes() {
+                return &[];
+            }
+            let (chunk, start, _, _) = text.chunk_at_byte(byte);
+            &chunk.as_bytes()[byte - start..]
+        };
+        self.tree = self.parser.parse_with(&mut read, self.tree.as_ref());
+        self.stale = false;
+    }
+
+    /// Returns the language of the text at a byte. That is the language of the whole buffer, as long as languages
+    /// embedded in others aren't parsed.
+    pub fn language_at(&self, _byte: usize) -> Language {
+        self.language
+    }
+
+    /// Returns the tree as it was parsed last, which is out of date if the text was edited since `update`
+    pub fn tree(&self) -> Option<&Tree> {
+        self.tree.as_ref()
+    }
+
+    /// Whether a byte lies inside a string or comment, after its first character and before its last
+    pub fn in_literal(&self, byte: usize) -> bool {
+        self.literal_at(byte).is_some()
+    }
+
+    /// Returns the byte range of the string or comment a byte lies inside of
+    pub fn literal_at(&self, byte: usize) -> Option<Range<usize>> {
+        let mut node = self.tree().and_then(|tree| tree.root_node().descendant_for_byte_range(byte, byte));
+        while let Some(n) = node {
+            if is_literal(&n) && n.start_byte() < byte && byte < n.end_byte() {
+                return Some(n.byte_range());
+            }
+            node = n.parent();
+        }
+        None
+    }
+
+    /// Returns the first and last row of every node captured by the fold query that spans multiple rows, ordered by
+    /// their first row. Of the nodes that start on the same row only the largest is kept.
+    pub fn folds(&self) -> Vec<(usize, usize)> {
+        let tree = match self.tree() {
+            Some(tree) => tree,
+            None => return Vec::new(),
+        };
+        let mut folds: Vec<(usize, usize)> = Vec::new();
+        let mut cursor = QueryCursor::new();
+        for (m, i) in cursor.captures(&self.folds, tree.root_node(), &[][..]) {
+            let node = m.captures[i].node;
+            let (start, end) = (node.start_position(), node.end_position());
+            // A node that ends with a line break ends on the row before
+            let last = if end.column == 0 { end.row.saturating_sub(1) } else { end.row };
+            if last > start.row {
+                folds.push((start.row, last));
+            }
+        }
+        folds.sort_by_key(|&(first, last)| (first, std::cmp::Reverse(last)));
+        folds.dedup_by_key(|(first, _)| *first);
+        folds
+    }
+
+    /// Returns the definitions captured by the symbol query, in the order they appear in the text
+    pub fn symbols(&self, text: &Rope) -> Vec<Symbol> {
+        let tree = match self.tree() {
+            Some(tree) => tree,
+            None => return Vec::new(),
+        };
+        let names = self.symbols.capture_names();
+        let mut symbols = Vec::new();
+        let mut cursor = QueryCursor::new();
+        for m in cursor.matches(&self.symbols, tree.root_node(), &[][..]) {
+            let (mut name, mut kind) = (None, None);
+            for capture in m.captures {
+                match names[capture.index as usize].as_str() {
+                    "name" => name = Some(capture.node),
+                    other => kind = Some(other),
+                }
+            }
+            if let (Some(name), Some(kind)) = (name, kind) {
+                let text = text.byte_slice(name.start_byte()..name.end_byte()).to_string();
+                symbols.push(Symbol {
+                    kind: kind.to_string(),
+                    // Only the first line of names that span several, such as types with long generics
+                    name: text.lines().next().unwrap_or_default().to_string(),
+                    byte: name.start_byte(),
+                });
+            }
+        }
+        symbols.sort_by_key(|symbol| symbol.byte);
+        symbols
+    }
+
+    /// Returns the indentation level of every row in `rows`. It is `None` for rows whose indentation is part of their
+    /// contents, such as rows inside a multi-line string, and for rows the tree can't tell because the code around them
+    /// is incomplete.
+    pub fn indent_levels(&self, text: &Rope, rows: RangeInclusive<usize>) -> Vec<Option<usize>> {
+        let tree = match self.tree() {
+            Some(tree) => tree,
+            None => return rows.map(|_| None).collect(),
+        };
+        let root = tree.root_node();
+        let (mut indents, mut outdents) = (HashSet::new(), HashSet::new());
+        let mut cursor = QueryCursor::new();
+        cursor.set_point_range(Point::new(*rows.start(), 0)..Point::new(*rows.end() + 1, 0));
+        let names = self.indents.capture_names();
+        for (m, i) in cursor.captures(&self.indents, root, &[][..]) {
+            let capture = m.captures[i];
+            match names[capture.index as usize].as_str() {
+                "indent" => indents.insert(capture.node.id()),
+                "outdent" => outdents.insert(capture.node.id()),
+                _ => false,
+            };
+        }
+        rows.map(|row| {
+            let line = text.line(row);
+            let first = line.chars().position(|c| !matches!(c, ' ' | '\t'));
+            let col = first.filter(|&col| !matches!(line.char(col), '\n' | '\r')).map_or(0, |col| line.char_to_byte(col));
+            let point = Point::new(row, col);
+            let node = root.descendant_for_point_range(point, point)?;
+            if node.start_position().row < row && is_literal(&node) {
+                return None;
+            }
+            let previous = (0..row).rev().map(|row| (row, text.line(row).to_string())).find(|(_, line)| !line.trim().is_empty());
+            if let Some((previous, line)) = previous {
+                let end = Point::new(previous, line.trim_end().len());
+                if root.descendant_for_point_range(end, point).is_none_or(|node| node.has_error()) {
+                    return None;
+                }
+            }
+            // Indenting nodes that start on the same row add a single level
+            let mut starts = HashSet::new();
+            let mut ancestor = Some(node);
+            while let Some(node) = ancestor {
+                if node.is_error() {
+                    return None;
+                }
+                if indents.contains(&node.id()) && node.start_position().row < row {
+                    starts.insert(node.start_position().row);
+                }
+                ancestor = node.parent();
+            }
+            let outdent = outdents.contains(&node.id()) && node.start_position() == point;
+            Some(starts.len().saturating_sub(outdent as usize))
+        })
+        .collect()
+    }
+}
+
+/// Whether the node is a string, character or comment, whose contents are kept as they are
+fn is_literal(node: &Node) -> bool {
+    node.kind().contains("string") || node.kind().contains("comment") || node.kind() == "char_literal"
+}
+
+/// Returns the row and the byte column of a byte in the text
+pub fn point(text: &Rope, byte: usize) -> Point {
+    let row = text.byte_to_line(byte);
+    Point::new(row, byte - text.line_to_byte(row))
+}
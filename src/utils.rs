@@ -1,14 +1,14 @@
-use crate::buffer::Buffer;
+use crate::{brackets, buffer::Buffer, fold, grapheme, wrap};
 use derive_more::{Add, Deref, From, Sub};
 use std::ops::Range;
 
-#[derive(Clone, Copy, Default, From, Deref, Add, Sub)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, From, Deref, Add, Sub)]
 pub struct BufCharIdx(pub usize);
 
 #[derive(Clone, Copy, Default, From, Deref, Add, Sub)]
 pub struct BufByteIdx(pub usize);
 
-#[derive(Clone, Copy, Default, From)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, From)]
 pub struct BufRange {
     pub start: BufCharIdx,
     pub end: BufCharIdx
@@ -39,21 +39,9 @@ impl From<Range<BufCharIdx>> for BufRange {
 #[derive(Clone, Copy, Default, From, Deref, Add, Sub)]
 pub struct BufCol(pub usize);
 
-impl BufCol {
-    pub fn as_termcol(self) -> TermCol {
-        TermCol(self.0 as u16)
-    }
-}
-
-#[derive(Clone, Copy, Default, From, Deref, Add, Sub)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, From, Deref, Add, Sub)]
 pub struct BufRow(pub usize);
 
-impl BufRow {
-    pub fn as_termrow(self) -> TermRow {
-        TermRow(self.0 as u16)
-    }
-}
-
 #[derive(Clone, Copy, Default)]
 pub struct BufPos {
     pub x: BufCol,
@@ -66,23 +54,28 @@ impl BufPos {
     }
 }
 
-#[derive(Clone, Copy, Default, From, Deref, Add, Sub)]
-pub struct TermCol(pub u16);
+/// A column on screen within a line of the buffer, where a tab takes up the columns up to the next tab stop
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, From, Deref, Add, Sub)]
+pub struct DisplayCol(pub usize);
 
-impl TermCol {
-    pub fn as_bufcol(self) -> BufCol {
-        BufCol(self.0 as usize)
+/// A position on screen within the buffer, in display columns
+#[derive(Clone, Copy, Default)]
+pub struct DisplayPos {
+    pub x: DisplayCol,
+    pub y: BufRow,
+}
+
+impl DisplayPos {
+    pub fn new(x: DisplayCol, y: BufRow) -> Self {
+        Self { x, y }
     }
 }
 
 #[derive(Clone, Copy, Default, From, Deref, Add, Sub)]
-pub struct TermRow(pub u16);
+pub struct TermCol(pub u16);
 
-impl TermRow {
-    pub fn as_bufrow(self) -> BufRow {
-        BufRow(self.0 as usize)
-    }
-}
+#[derive(Clone, Copy, Default, From, Deref, Add, Sub)]
+pub struct TermRow(pub u16);
 
 #[derive(Clone, Copy, Default)]
 pub struct TermPos {
@@ -97,7 +90,7 @@ impl TermPos {
 }
 
 #[allow(unused)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub enum Movement {
     Up(usize),
     Down(usize),
@@ -110,47 +103,79 @@ pub enum Movement {
     FirstChar,
     NextWord(usize),
     PrevWord(usize),
+    /// To the bracket matching the one under or before the cursor
+    MatchingBracket,
+    /// Up by rows of the screen, which differ from lines when they wrap
+    ScreenUp(usize),
+    /// Down by rows of the screen
+    ScreenDown(usize),
+    /// To the first character of the row of the screen
+    ScreenHome,
+    /// To the last character of the row of the screen
+    ScreenEnd,
+    /// To the start of the next fold
+    NextFold,
+    /// To the end of the previous fold
+    PrevFold,
 }
 
 impl Movement {
     pub fn is_horizontal(&self) -> bool {
-        match self {
-            Movement::Up(_) => false,
-            Movement::Down(_) => false,
-            Movement::Top => false,
-            Movement::Bottom => false,
-            _ => true,
-        }
+        !matches!(self, Movement::Up(_) | Movement::Down(_) | Movement::Top | Movement::Bottom)
     }
 
     pub fn dest(&self, buf: &Buffer) -> BufCharIdx {
         match &self {
+            // Closed folds are moved over as a single line
             Movement::Up(amount) => {
-                let y = buf.row().saturating_sub(*amount).into();
-                let x = usize::min(*buf.max_col(y), *buf.saved_col).into();
-                buf.row_to_char(y) + x
+                let y = fold::closed(buf).up(*buf.row(), *amount).into();
+                buf.row_to_char(y) + (*buf.display_to_col(y, buf.saved_col)).into()
             }
             Movement::Down(amount) => {
-                let y =
-                    usize::min(*buf.row() + amount, buf.text.len_lines().saturating_sub(1)).into();
-                let x = usize::min(*buf.max_col(y), *buf.saved_col).into();
-                buf.row_to_char(y) + x
+                let last = buf.text.len_lines().saturating_sub(1);
+                let y = fold::closed(buf).down(*buf.row(), *amount, last).into();
+                buf.row_to_char(y) + (*buf.display_to_col(y, buf.saved_col)).into()
+            }
+            Movement::Left(amount) => {
+                let bounds = grapheme::boundaries(&buf.line_str(buf.row()));
+                let i = bounds.partition_point(|&bound| bound < *buf.col());
+                buf.row_to_char(buf.row()) + bounds[i.saturating_sub(*amount)].into()
+            }
+            Movement::Right(amount) => {
+                let bounds = grapheme::boundaries(&buf.line_str(buf.row()));
+                let i = bounds.partition_point(|&bound| bound <= *buf.col());
+                let mut col = bounds[usize::min(i + amount.saturating_sub(1), bounds.len() - 1)];
+                // Without a line break after it, the cursor stops at the start of the last grapheme
+                if col > *buf.max_col(buf.row()) {
+                    col = bounds[bounds.len().saturating_sub(2)];
+                }
+                buf.row_to_char(buf.row()) + col.into()
             }
-            Movement::Left(amount) => usize::max(
-                buf.idx.saturating_sub(*amount),
-                *buf.row_to_char(buf.row()),
-            )
-            .into(),
-            Movement::Right(amount) => usize::min(
-                *buf.idx + amount,
-                *buf.row_to_char(buf.row()) + *buf.max_col(buf.row()),
-            )
-            .into(),
             Movement::Home => buf.row_to_char(buf.row()),
             Movement::End => buf.row_to_char(buf.row() + BufRow(1)) - BufCharIdx(1),
             Movement::FirstChar => {
-                unimplemented!()
+                let indent = buf.line_str(buf.row()).chars().take_while(|c| c.is_whitespace()).count();
+                buf.row_to_char(buf.row()) + usize::min(indent, *buf.max_col(buf.row())).into()
             }
+            Movement::ScreenUp(amount) => match buf.wrap_width {
+                Some(width) => wrap::vertical_dest(buf, width, true, *amount),
+                None => Movement::Up(*amount).dest(buf),
+            },
+            Movement::ScreenDown(amount) => match buf.wrap_width {
+                Some(width) => wrap::vertical_dest(buf, width, false, *amount),
+                None => Movement::Down(*amount).dest(buf),
+            },
+            Movement::ScreenHome => match buf.wrap_width {
+                Some(width) => wrap::row_end(buf, width, false),
+                None => Movement::Home.dest(buf),
+            },
+            Movement::ScreenEnd => match buf.wrap_width {
+                Some(width) => wrap::row_end(buf, width, true),
+                None => Movement::End.dest(buf),
+            },
+            Movement::NextFold => fold::next(buf, false),
+            Movement::PrevFold => fold::next(buf, true),
+            Movement::MatchingBracket => brackets::matching_pair(buf).map_or(buf.idx, |(_, other)| other),
             Movement::Top => BufCharIdx(0),
             Movement::Bottom => buf.text.len_chars().into(),
             Movement::NextWord(_amount) => {
@@ -163,10 +188,23 @@ impl Movement {
     }
 }
 
+/// Where the cursors are placed when splitting a visual block into a cursor per line
+#[derive(Clone, Copy, Debug)]
+pub enum BlockSplit {
+    /// At the left edge of the block
+    Insert,
+    /// After the right edge of the block
+    Append,
+    /// Selecting the part of the line inside the block
+    Select,
+}
+
 #[allow(unused)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub enum Selection {
     Bounds(BufCharIdx, BufCharIdx),
+    /// The text selected by the cursor
+    Selected,
     Lines(usize),
     UpTo(Movement),
     Between {
@@ -187,6 +225,7 @@ impl Selection {
     pub fn bounds(&self, buf: &Buffer) -> BufRange {
         match self {
             Selection::Bounds(start, end) => *start..*end,
+            Selection::Selected => return buf.selection().unwrap_or(BufRange::new(buf.idx, buf.idx)),
             Selection::Lines(amount) => {
                 let start = buf.row_to_char(buf.row());
                 let dest = usize::min(*buf.row() + amount, buf.text.len_lines()).into();
@@ -201,7 +240,23 @@ impl Selection {
                 inclusive: _,
             } => BufCharIdx(0)..BufCharIdx(0),
             Selection::Word { inclusive: _ } => BufCharIdx(0)..BufCharIdx(0), // TODO: implement
-            Selection::Paragraph { inclusive: _ } => BufCharIdx(0)..BufCharIdx(0), // TODO: implement
+            Selection::Paragraph { inclusive } => {
+                // The lines around the cursor that are all blank or all non-blank, and the lines after them up to
+                // the next such group if `inclusive` is set
+                let blank = |row: usize| buf.line_str(row.into()).trim().is_empty();
+                let (last, row) = (*buf.last_row(), *buf.row());
+                let (mut start, mut end) = (row, row);
+                while start > 0 && blank(start - 1) == blank(row) {
+                    start -= 1;
+                }
+                while end < last && blank(end + 1) == blank(row) {
+                    end += 1;
+                }
+                while *inclusive && end < last && blank(end + 1) != blank(row) {
+                    end += 1;
+                }
+                buf.row_to_char(start.into())..buf.row_to_char((end + 1).into())
+            }
         }.into()
     }
 }
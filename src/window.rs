@@ -1,162 +1,985 @@
 use crate::{
-    action::Action,
-    buffer::{Buffer, EditMode},
+    action::{Action, BufferAction, EditorAction, RenderAction},
+    anchor::{Anchor, AnchorId, Gravity},
+    brackets,
+    buffer::{Buffer, Cursor, EditMode},
     config::Config,
-    highlight::{Highlighter, language},
+    ex::{ExCommand, LineRange},
+    fold, grapheme,
+    highlight::{Highlighter, Layer, language},
     input::InputHandler,
+    jumplist::JumpList,
+    message::{Error, Message, Severity},
+    prompt::{Prompt, PromptKind, PromptStatus},
     rect::Rect,
-    render::Renderer,
-    utils::{BufRow, TermCol, TermRow, BufRange},
+    render::{self, Renderer},
+    search::{add_to_history, Direction, Search},
+    status::{self, FileInfo},
+    substitute::{Flags, Match, Substitution, Summary},
+    utils::{BufCharIdx, DisplayPos, BufRow, TermCol, TermRow, BufRange, Selection},
+    wrap::{self, Layout},
 };
 use crossterm::{
     cursor::{
         CursorShape,
     },
-    event::KeyEvent,
+    event::{KeyCode, KeyEvent, KeyModifiers},
+    style::ContentStyle,
     terminal::{self, ClearType},
     Result,
 };
+use log::{debug, error, info, trace, warn};
+use regex::Regex;
 use std::{
+    io,
     path::PathBuf,
+    time::Instant,
 };
 use tree_sitter_highlight::HighlightEvent;
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum WindowKind {
+    /// A window for editing files
+    File,
+    /// The list of locations that `:cn` and `:cp` jump between
+    Quickfix,
+    /// The replacements of a project wide find and replace, which `:w` applies
+    Preview,
+}
+
 pub struct Window {
     /// The buffer displayed by the window
     pub buf: Buffer,
+    pub kind: WindowKind,
+    /// Whether the window receives the keys that are pressed
+    pub focused: bool,
+    /// An action for the editor to apply after the current key has been handled
+    pub editor_action: Option<EditorAction>,
     /// The renderer used to draw stuff onto the terminal
     renderer: Renderer,
-    /// The space the window gets to render
+    /// The space the window gets to render the buffer in, the status line is drawn on the row below it
     pub rect: Rect,
+    /// The row of the terminal used for the command line
+    command_row: TermRow,
+    input: InputHandler,
     /// Configuration for this window
     config: Config,
+    /// What the status line shows about the file of the buffer
+    file_info: FileInfo,
     hl: Highlighter,
+    /// The input currently being typed on the command line
+    pub prompt: Option<Prompt>,
+    /// The message shown on the command line until the next key press, or until it times out
+    pub message: Option<Message>,
+    /// Messages reported since the editor last collected them, which it adds to the history of `:messages`
+    pub pending_messages: Vec<Message>,
+    /// The last search, repeated by `n` and `N`
+    search: Option<Search>,
+    /// Whether the matches of the last search are highlighted
+    hlsearch: bool,
+    /// The search currently being typed, with the cursor and scroll position from before typing it
+    incsearch: Option<(Option<Search>, Cursor, DisplayPos)>,
+    search_history: Vec<String>,
+    command_history: Vec<String>,
+    /// A substitution waiting for confirmation of whether the match should be replaced
+    confirming: Option<(Substitution, Match)>,
+    /// The positions jumped away from, across all buffers shown in the window
+    pub jumps: JumpList,
+    /// Anchors in the buffer at positions jumped away from, which the editor adds to `jumps`
+    pub pending_jumps: Vec<AnchorId>,
 }
 
 impl Window {
+    /// Creates a window for a file, which is empty if the file doesn't exist or can't be read
     pub fn new(path: PathBuf, config: Config) -> Self {
-        let hl = Highlighter::new(language::detect(&path), config.hl.clone());
-        let buf = Buffer::new(path);
+        match Buffer::open(path.clone()) {
+            Ok(buf) => Self::with_buffer(buf, WindowKind::File, config),
+            Err(error) => {
+                let not_found = error.kind() == io::ErrorKind::NotFound;
+                let mut window = Self::with_buffer(Buffer::new(path.clone()), WindowKind::File, config);
+                match not_found {
+                    true => window.report(Message::info(format!("\"{}\" [New]", path.display()))),
+                    false => window.report(Error::Io { path, error }),
+                }
+                window
+            }
+        }
+    }
+
+    pub fn with_buffer(mut buf: Buffer, kind: WindowKind, config: Config) -> Self {
+        buf.filetype = config.filetype(&buf.path);
+        let mut hl = Highlighter::new(config.hl.clone());
+        let hl_error = hl.set_language(language::detect(&buf.path)).err();
+        let file_info = FileInfo::new(&buf.path, buf.readonly);
         let (width, height) = terminal::size().unwrap();
         let line_nrs_width = buf.text.len_lines().to_string().len() as u16 + 1;
 
-        Window {
+        let mut window = Window {
             buf,
+            kind,
+            focused: false,
+            editor_action: None,
             renderer: Renderer::new(),
             rect: Rect::new(
                 width - line_nrs_width,
-                height,
+                height - 2,
                 line_nrs_width,
                 0,
             ),
+            command_row: TermRow(height - 1),
+            input: InputHandler::default(),
             config,
+            file_info,
             hl,
+            prompt: None,
+            message: None,
+            pending_messages: Vec::new(),
+            search: None,
+            hlsearch: false,
+            incsearch: None,
+            search_history: Vec::new(),
+            command_history: Vec::new(),
+            confirming: None,
+            jumps: JumpList::default(),
+            pending_jumps: Vec::new(),
+        };
+        if let Some(e) = hl_error {
+            window.report(e);
+        }
+        window
+    }
+
+    /// Moves the window to the rows starting at `y`, the command line is drawn on `command_row`. The last row of the
+    /// window is taken up by the status line.
+    pub fn resize(&mut self, width: u16, height: u16, y: u16, command_row: u16) {
+        self.rect.resize(TermCol(width) - self.rect.offset.x, TermRow(height.saturating_sub(1)));
+        self.rect.offset.y = TermRow(y);
+        self.command_row = TermRow(command_row);
+    }
+
+    /// Replaces the buffer shown in the window, and returns the old one
+    pub fn set_buffer(&mut self, mut buf: Buffer) -> Buffer {
+        buf.filetype = self.config.filetype(&buf.path);
+        if let Err(e) = self.hl.set_language(language::detect(&buf.path)) {
+            self.report(e);
         }
+        self.file_info = FileInfo::new(&buf.path, buf.readonly);
+        self.rect.scroll = DisplayPos::default();
+        self.incsearch = None;
+        self.confirming = None;
+        std::mem::replace(&mut self.buf, buf)
     }
 
-    pub fn update_size(&mut self, width: u16, height: u16) {
-        self.rect.resize(
-            TermCol(width) - self.rect.offset.x,
-            TermRow(height),
-        );
+    pub fn flush(&mut self) -> Result<()> {
+        self.renderer.flush()
     }
 
     fn draw_line_nrs(&mut self) -> Result<()> {
-        self.rect.offset.x = TermCol(self.buf.text.len_lines().to_string().len() as u16 + 1);
+        // The text gets narrower as the line numbers get wider
+        let width = TermCol(self.buf.text.len_lines().to_string().len() as u16 + 1);
+        self.rect.width = TermCol((*self.rect.width + *self.rect.offset.x).saturating_sub(*width));
+        self.rect.offset.x = width;
         self.renderer.save_cursor()?;
-        for line_nr in 0..*self.rect.height {
-            self.renderer.move_to(0, line_nr)?;
-            let nr = (line_nr as i64 - (*self.rect.terminal_y(self.buf.row())) as i64).abs() as usize;
-            let (style, nr) = if nr == 0 {
-                (self.config.line_nr_active, *self.buf.row() + 1)
-            } else {
-                (self.config.line_nr_column, nr)
+        // Only the first row of a wrapped line is numbered, the rows after the end of the buffer are numbered as if
+        // there were more lines. Numbers are relative to the line of the cursor, counting a closed fold as one line.
+        let layout = self.layout();
+        let lines = layout.lines();
+        let mut rows: Vec<_> = lines
+            .iter()
+            .enumerate()
+            .flat_map(|(i, line)| line.segments.iter().enumerate().map(move |(j, _)| (i, *line.first, j == 0)))
+            .collect();
+        let after = (lines.len()..).zip(*layout.end()..).map(|(i, row)| (i, row, true));
+        rows.extend(after.take((*self.rect.height as usize).saturating_sub(rows.len())));
+        let cursor = *self.buf.row();
+        let cursor_line = lines.iter().position(|line| *line.first <= cursor && cursor <= *line.last);
+        for (y, (i, row, first)) in rows.into_iter().enumerate() {
+            self.renderer.move_to(0, *self.rect.offset.y + y as u16)?;
+            let (style, nr) = match cursor_line.map_or(row.abs_diff(cursor), |line| i.abs_diff(line)) {
+                0 => (self.config.line_nr_active, row + 1),
+                nr => (self.config.line_nr_column, nr),
             };
             self.renderer.set_style(&style)?;
-            self.renderer.print(format!("{: >width$} ", nr, width = *self.rect.offset.x as usize - 1))?;
+            let width = *self.rect.offset.x as usize - 1;
+            match first {
+                true => self.renderer.print(format!("{: >width$} ", nr, width = width))?,
+                false => self.renderer.print(format!("{: >width$} ", "", width = width))?,
+            }
         }
         self.renderer.restore_cursor()?;
         Ok(())
     }
 
+    /// Returns where the visible lines are drawn
+    fn layout(&self) -> Layout {
+        Layout::new(&self.buf, &self.rect, self.buf.filetype.wrap)
+    }
+
+    /// Scrolls to make sure the cursor is visible, and returns the amount that was scrolled vertically
+    fn scroll_to_cursor(&mut self) -> isize {
+        let width = *self.rect.width as usize;
+        let wrap = self.buf.filetype.wrap;
+        self.buf.wrap_width = Some(width).filter(|_| wrap);
+        let closed = fold::closed(&self.buf);
+        if !wrap && closed.is_empty() {
+            return self.rect.scroll_to_cursor(self.buf.cursor(), self.config.margins);
+        }
+        match wrap {
+            true => self.rect.scroll.x = 0.into(),
+            false => self.rect.scroll_to_col(self.buf.cursor().x, self.config.margins),
+        }
+        let buf = &self.buf;
+        let row = closed.first(*buf.row());
+        let sub = match wrap && row == *buf.row() {
+            true => wrap::cursor_row(buf, width),
+            false => 0,
+        };
+        let rows = |row: usize| match (closed.containing(row), wrap) {
+            (Some((first, _)), _) => (first == row) as usize,
+            (None, true) => wrap::segments(buf, row.into(), width).len(),
+            (None, false) => 1,
+        };
+        self.rect.scroll_to_row(row.into(), sub, self.config.margins, rows)
+    }
+
     pub fn draw_all(&mut self) -> Result<()> {
-        self.update_cursor()?;
+        self.scroll_to_cursor();
         self.draw(self.rect.top())?;
-        Ok(())
+        match self.focused {
+            true => self.update_cursor(),
+            false => {
+                self.draw_line_nrs()?;
+                self.draw_status_line()
+            }
+        }
     }
 
     /// Draws the buffer in the given view starting from the line at index `begin`.
     pub fn draw(&mut self, first_line: BufRow) -> Result<()> {
-        let last_line: BufRow = (self.rect.bottom() - 1.into()).min(self.buf.text.len_lines()).into();
-    
+        let layout = self.layout();
+        let last_line = layout.end();
+
         self.renderer.save_cursor()?;
-        self.renderer.move_to(self.rect.terminal_x(0.into()), self.rect.terminal_y(first_line))?;
-        self.renderer.clear(ClearType::UntilNewLine)?;
-    
+        // The rows are cleared first, so that nothing is left behind of lines that got shorter
+        let first_y = layout.line(first_line).and_then(|line| line.segments.first()).map_or(layout.height(), |(y, _)| *y);
+        for y in first_y..*self.rect.height as usize {
+            self.renderer.move_to(self.rect.offset.x, *self.rect.offset.y + y as u16)?;
+            self.renderer.clear(ClearType::UntilNewLine)?;
+        }
+
         let rendered_bytes = self.buf.row_to_byte(first_line)..self.buf.row_to_byte(last_line);
-        if !self.hl.has_hl() {
-            self.hl.update_hl(&self.buf);
+        if !self.hl.has_hl(&self.buf) {
+            let start = Instant::now();
+            if let Err(e) = self.hl.update_hl(&self.buf) {
+                self.report(e);
+            }
+            debug!("Highlighted {} in {:?}", self.buf.path.display(), start.elapsed());
         }
+        let visible = BufRange::new(self.buf.row_to_char(first_line), self.buf.row_to_char(last_line));
+        let confirming = self.confirming.as_ref().map_or_else(Vec::new, |(_, m)| vec![m.range]);
+        self.buf.update_syntax();
+        let brackets = brackets::matching_pair(&self.buf).map_or_else(Vec::new, |(idx, other)| {
+            vec![BufRange::new(idx, idx + 1.into()), BufRange::new(other, other + 1.into())]
+        });
+        let layers = [
+            Layer::new(brackets, self.config.matching_bracket),
+            self.search_layer(visible),
+            Layer::new(confirming, self.config.selection),
+            self.selection_layer(),
+        ];
+        let mut styles = Vec::new();
         for event in self.hl.get_hl() {
             match event {
                 HighlightEvent::Source { start, end } => {
-                    if *start > *rendered_bytes.end || *end <= *rendered_bytes.start {
+                    if *start >= *rendered_bytes.end || *end <= *rendered_bytes.start {
                         continue;
                     }
                     let first = self.buf.byte_to_char(usize::max(*start, *rendered_bytes.start).into());
                     let last = self.buf.byte_to_char(usize::min(*end, *rendered_bytes.end).into());
-                    self.renderer.print_range(&self.rect, &self.buf, BufRange::new(first, last))?;
+                    self.renderer.print_layered(&layout, &self.buf, BufRange::new(first, last), styles.last(), &layers)?;
+                }
+                HighlightEvent::HighlightStart(s) => styles.push(self.hl.get_style(s)),
+                HighlightEvent::HighlightEnd => {
+                    styles.pop();
                 }
-                HighlightEvent::HighlightStart(s) => self.renderer.set_style(self.hl.get_style(&s))?,
-                HighlightEvent::HighlightEnd => self.renderer.reset_style()?,
             }
         }
-        self.renderer.move_to(10, self.rect.terminal_y(self.rect.bottom()))?;
-        self.renderer.print(format!("{}:{}", *self.buf.row(), *self.buf.col()))?;
-    
+        self.draw_continuations(&layout, first_line, last_line)?;
+        self.draw_folds(&layout, first_line)?;
+        self.renderer.reset_style()?;
         self.renderer.restore_cursor()?;
         Ok(())
     }
 
+    /// Marks the rows that continue beyond the left or right edge of the window, or the rows that continue a
+    /// wrapped line with `showbreak`
+    fn draw_continuations(&mut self, layout: &Layout, first: BufRow, last: BufRow) -> Result<()> {
+        let filetype = &self.buf.filetype;
+        let showbreak = grapheme::text_width(&filetype.showbreak, 0, filetype.tabstop);
+        self.renderer.set_style(&self.config.line_nr_column)?;
+        for line in layout.lines().iter().filter(|line| line.last >= first && line.first < last && !line.is_folded()) {
+            let width = grapheme::text_width(&self.buf.line_str(line.first), 0, filetype.tabstop);
+            for (i, (y, segment)) in line.segments.iter().enumerate() {
+                if filetype.wrap {
+                    if i > 0 && showbreak > 0 && segment.x >= showbreak {
+                        let pos = layout.terminal_pos(*y, segment, segment.left);
+                        self.renderer.move_to(*pos.x - showbreak as u16, pos.y)?;
+                        self.renderer.print(&filetype.showbreak)?;
+                    }
+                    continue;
+                }
+                if segment.left > 0 && width > 0 {
+                    let pos = layout.terminal_pos(*y, segment, segment.left);
+                    self.renderer.move_to(pos.x, pos.y)?;
+                    self.renderer.print('<')?;
+                }
+                if width > segment.right {
+                    let pos = layout.terminal_pos(*y, segment, segment.right - 1);
+                    self.renderer.move_to(pos.x, pos.y)?;
+                    self.renderer.print('>')?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Draws the closed folds from the line at `first` on, as a line that spans the width of the window
+    fn draw_folds(&mut self, layout: &Layout, first: BufRow) -> Result<()> {
+        let width = *self.rect.width as usize;
+        self.renderer.set_style(&self.config.fold)?;
+        for line in layout.lines().iter().filter(|line| line.last >= first && line.is_folded()) {
+            let (y, segment) = &line.segments[0];
+            let pos = layout.terminal_pos(*y, segment, segment.left);
+            let text = render::clip(&fold::summary(&self.buf, line.first, line.last), 0, 0, width, self.buf.filetype.tabstop);
+            let fill = width.saturating_sub(grapheme::text_width(&text, 0, self.buf.filetype.tabstop));
+            self.renderer.move_to(pos.x, pos.y)?;
+            self.renderer.print(format!("{}{}", text, " ".repeat(fill)))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the matches of the search being typed, or of the last search if those are highlighted
+    fn search_layer(&self, visible: BufRange) -> Layer {
+        let search = match &self.incsearch {
+            Some((search, _, _)) => search.as_ref(),
+            None => self.search.as_ref().filter(|_| self.hlsearch),
+        };
+        let ranges = search.map_or_else(Vec::new, |search| self.buf.find_all(&search.regex, visible));
+        Layer::new(ranges, self.config.search)
+    }
+
+    /// Returns the selections of all cursors, and the additional cursors themselves
+    fn selection_layer(&self) -> Layer {
+        let mut ranges = Vec::new();
+        for (i, cursor) in self.buf.all_cursors().enumerate() {
+            match self.buf.cursor_selection(&cursor) {
+                Some(_) if self.buf.mode == EditMode::VisualBlock => ranges.extend(self.block_ranges(&cursor)),
+                Some(range) => ranges.push(range),
+                // The primary cursor is drawn by the terminal
+                None if i == 0 => (),
+                None => ranges.push(BufRange::new(cursor.idx, usize::min(*cursor.idx + 1, self.buf.text.len_chars()).into())),
+            }
+        }
+        Layer::new(ranges, self.config.selection)
+    }
+
+    /// Returns the part of every line that lies within the visual block of a cursor
+    fn block_ranges(&self, cursor: &Cursor) -> Vec<BufRange> {
+        let anchor = self.buf.char_to_pos(cursor.anchor.unwrap_or(cursor.idx));
+        let pos = self.buf.char_to_pos(cursor.idx);
+        let left = usize::min(*anchor.x, *pos.x);
+        let right = usize::max(*anchor.x, *pos.x) + 1;
+        (usize::min(*anchor.y, *pos.y)..=usize::max(*anchor.y, *pos.y))
+            .map(|row| {
+                let start = self.buf.row_to_char(row.into());
+                let len = self.buf.line_str(row.into()).chars().count();
+                BufRange::new(start + usize::min(left, len).into(), start + usize::min(right, len).into())
+            })
+            .collect()
+    }
+
+    /// Draws the prompt or message on the last row of the window
+    fn draw_command_line(&mut self) -> Result<()> {
+        self.renderer.move_to(0, self.command_row)?;
+        self.renderer.clear(ClearType::CurrentLine)?;
+        if let Some(prompt) = &self.prompt {
+            self.renderer.print(prompt.line())
+        } else if let Some(message) = &self.message {
+            let style = match message.severity {
+                Severity::Info => self.config.message_info,
+                Severity::Warning => self.config.message_warning,
+                Severity::Error => self.config.message_error,
+            };
+            // A message with multiple lines is drawn over the rows above the command line
+            let lines: Vec<&str> = message.text.lines().collect();
+            let first = lines.len().saturating_sub(*self.command_row as usize + 1);
+            for (i, line) in lines[first..].iter().enumerate() {
+                self.renderer.move_to(0, *self.command_row + 1 + (first + i) as u16 - lines.len() as u16)?;
+                self.renderer.clear(ClearType::CurrentLine)?;
+                self.renderer.set_style(&style)?;
+                self.renderer.print(line)?;
+                self.renderer.reset_style()?;
+            }
+            Ok(())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Shows a message and adds it to the history of `:messages`
+    pub fn report(&mut self, message: impl Into<Message>) {
+        let message = message.into();
+        match message.severity {
+            Severity::Info => info!("{}", message.text),
+            Severity::Warning => warn!("{}", message.text),
+            Severity::Error => error!("{}", message.text),
+        }
+        self.pending_messages.push(message.clone());
+        self.message = Some(message);
+    }
+
+    /// Clears the message once it has been shown for longer than the timeout of the config. A question whether to
+    /// replace a match stays until it is answered.
+    pub fn expire_message(&mut self) -> Result<()> {
+        let timeout = self.config.message_timeout;
+        if self.confirming.is_some() || !self.message.as_ref().is_some_and(|message| message.is_expired(timeout)) {
+            return Ok(());
+        }
+        self.message = None;
+        self.renderer.save_cursor()?;
+        self.draw_command_line()?;
+        self.renderer.restore_cursor()?;
+        self.renderer.flush()
+    }
+
+    /// Draws the items of the status line on the row below the buffer, the ones on the right against the right edge
+    fn draw_status_line(&mut self) -> Result<()> {
+        let width = (*self.rect.offset.x + *self.rect.width) as usize;
+        let tabstop = self.buf.filetype.tabstop;
+        let base = match self.focused {
+            true => self.config.status_line,
+            false => self.config.status_line_inactive,
+        };
+        let mode = match self.buf.mode {
+            EditMode::Normal => self.config.status_normal,
+            EditMode::Insert => self.config.status_insert,
+            EditMode::Visual | EditMode::VisualBlock => self.config.status_visual,
+        };
+        let items = |items: &[status::StatusItem]| -> Vec<(String, ContentStyle)> {
+            items
+                .iter()
+                .filter_map(|item| {
+                    let text = format!(" {} ", status::text(item, &self.buf, &self.file_info)?);
+                    Some((text, if let status::StatusItem::Mode = item { mode } else { base }))
+                })
+                .collect()
+        };
+        let (left, right) = (items(&self.config.status.left), items(&self.config.status.right));
+        let right_width: usize = right.iter().map(|(text, _)| grapheme::text_width(text, 0, tabstop)).sum();
+        // The items on the left are cut off where the ones on the right start
+        let available = width.saturating_sub(right_width);
+        self.renderer.save_cursor()?;
+        self.renderer.move_to(0, *self.rect.offset.y + *self.rect.height)?;
+        let mut col = 0;
+        for (text, style) in left {
+            let text = render::clip(&text, col, col, available, tabstop);
+            self.renderer.set_style(&style)?;
+            self.renderer.print(&text)?;
+            col += grapheme::text_width(&text, col, tabstop);
+        }
+        self.renderer.set_style(&base)?;
+        self.renderer.print(" ".repeat(width.saturating_sub(col + right_width)))?;
+        col = usize::max(col, available);
+        for (text, style) in right {
+            let text = render::clip(&text, col, col, width, tabstop);
+            self.renderer.set_style(&style)?;
+            self.renderer.print(&text)?;
+            col += grapheme::text_width(&text, col, tabstop);
+        }
+        self.renderer.reset_style()?;
+        self.renderer.restore_cursor()
+    }
+
     pub fn update_cursor(&mut self) -> Result<()> {
         match self.buf.mode {
-            EditMode::Normal => self.renderer.set_cursor_shape(CursorShape::Block)?,
+            _ if self.prompt.is_some() => self.renderer.set_cursor_shape(CursorShape::Line)?,
+            EditMode::Normal | EditMode::Visual | EditMode::VisualBlock => self.renderer.set_cursor_shape(CursorShape::Block)?,
             EditMode::Insert => self.renderer.set_cursor_shape(CursorShape::Line)?,
         }
-        let cursor = self.buf.cursor();
-        let dy = self.rect.scroll_to_cursor(cursor);
-        if dy < 0 {
-            self.renderer.scroll_down(dy.abs() as u16)?;
-        } else if dy > 0 {
-            self.renderer.scroll_up(dy.abs() as u16)?;
+        // Other windows may share the terminal, so it is redrawn instead of scrolled
+        if self.scroll_to_cursor() != 0 {
+            self.draw(self.rect.top())?;
         }
-        let pos = self.rect.terminal_pos(cursor);
-        self.renderer.move_to(pos.x, pos.y)?;
-        self.draw_line_nrs()
+        match &self.prompt {
+            Some(prompt) => {
+                let x = prompt.line().chars().count() as u16;
+                self.renderer.move_to(x, self.command_row)?;
+            }
+            None => {
+                let cursor = self.buf.cursor();
+                if let Some(pos) = self.layout().position(cursor.y, cursor.x) {
+                    self.renderer.move_to(pos.x, pos.y)?;
+                }
+            }
+        }
+        self.draw_line_nrs()?;
+        self.draw_status_line()?;
+        // The command line is drawn last, as messages with multiple lines cover the rows above it
+        self.renderer.save_cursor()?;
+        self.draw_command_line()?;
+        self.renderer.restore_cursor()
     }
 
     pub fn handle_keyevent(&mut self, key_event: KeyEvent) -> Result<()> {
-        match self.buf.mode {
-            EditMode::Normal => {
-                match InputHandler::parse_normal(key_event) {
-                    Some(command) => {
-                        self.buf.apply(command.buffer_action).unwrap_or(());
-                        command.render_action.apply(self)?;
+        self.message = None;
+        self.feed_key(key_event)?.apply(self)?;
+        self.renderer.flush()
+    }
+
+    /// Handles a key press without drawing, and returns what should be redrawn
+    fn feed_key(&mut self, key_event: KeyEvent) -> Result<RenderAction> {
+        if self.confirming.is_some() {
+            self.handle_confirm_key(key_event);
+            return Ok(RenderAction::DrawAll);
+        }
+        if let Some(prompt) = &mut self.prompt {
+            let history = match prompt.kind {
+                PromptKind::Search(_) => &self.search_history[..],
+                PromptKind::Command => &self.command_history[..],
+                PromptKind::SplitSelection => &[],
+            };
+            match prompt.handle_key(key_event, history) {
+                PromptStatus::Editing => self.update_incsearch(),
+                PromptStatus::Cancelled => {
+                    self.prompt = None;
+                    self.end_incsearch();
+                }
+                PromptStatus::Submitted => {
+                    self.end_incsearch();
+                    if let Some(prompt) = self.prompt.take() {
+                        self.submit_prompt(prompt);
                     }
-                    None => (),
                 }
             }
-            EditMode::Insert => {
-                match InputHandler::parse_insert(key_event) {
-                    Some(command) => {
-                        self.buf.apply(command.buffer_action).unwrap_or(());
-                        command.render_action.apply(self)?;
+            return Ok(RenderAction::DrawAll);
+        }
+        if self.kind == WindowKind::Quickfix && self.buf.mode == EditMode::Normal && key_event.code == KeyCode::Enter {
+            self.editor_action = Some(EditorAction::QuickfixGoto(Some(*self.buf.row())));
+            return Ok(RenderAction::Nothing);
+        }
+        let command = match self.buf.mode {
+            EditMode::Normal => self.input.parse_normal(key_event),
+            EditMode::Insert => InputHandler::parse_insert(key_event),
+            mode => self.input.parse_visual(key_event, mode),
+        };
+        Ok(match command {
+            Some(command) => {
+                trace!("{:?}", command);
+                command.window_action.apply(self)?;
+                let (origin, jump) = (self.buf.idx, command.buffer_action.is_jump());
+                match self.buf.apply(command.buffer_action) {
+                    Ok(()) if jump => self.add_pending_jump(origin),
+                    Ok(()) => (),
+                    Err(e) => self.report(Error::Action(e)),
+                }
+                command.render_action
+            }
+            None => RenderAction::Nothing,
+        })
+    }
+
+    pub fn open_prompt(&mut self, kind: PromptKind) {
+        let mut prompt = Prompt::new(kind);
+        match kind {
+            PromptKind::Search(_) => self.incsearch = Some((None, self.buf.primary(), self.rect.scroll)),
+            // Commands typed in visual mode apply to the lines of the selection
+            PromptKind::Command if self.buf.mode.is_visual() => {
+                self.buf.apply(BufferAction::SetMode(EditMode::Normal)).unwrap_or(());
+                prompt.input = String::from("'<,'>");
+            }
+            _ => (),
+        }
+        self.prompt = Some(prompt);
+    }
+
+    /// Opens the command line with some input already typed
+    pub fn open_command_line(&mut self, input: String) {
+        self.open_prompt(PromptKind::Command);
+        if let Some(prompt) = &mut self.prompt {
+            prompt.input.push_str(&input);
+        }
+    }
+
+    /// Returns the patterns that were searched for, the latest last
+    pub fn search_history(&self) -> &[String] {
+        &self.search_history
+    }
+
+    /// Moves the cursor to the first match of the search being typed, or back to where it was
+    fn update_incsearch(&mut self) {
+        let (prompt, (search, origin, scroll)) = match (&self.prompt, &mut self.incsearch) {
+            (Some(prompt), Some(incsearch)) => (prompt, incsearch),
+            _ => return,
+        };
+        let direction = match prompt.kind {
+            PromptKind::Search(direction) => direction,
+            _ => return,
+        };
+        *search = Search::new(&prompt.input, direction).ok().filter(|_| !prompt.input.is_empty());
+        self.buf.apply(BufferAction::MoveTo(origin.idx, origin.saved_col)).unwrap_or(());
+        self.rect.scroll = *scroll;
+        let buf = &self.buf;
+        if let Some((found, _)) = search.as_ref().and_then(|search| search.find(buf, direction)) {
+            let col = self.buf.display_col(found.start);
+            self.buf.apply(BufferAction::MoveTo(found.start, col)).unwrap_or(());
+        }
+    }
+
+    /// Moves the cursor back to where it was before typing a search
+    fn end_incsearch(&mut self) {
+        if let Some((_, origin, scroll)) = self.incsearch.take() {
+            self.buf.apply(BufferAction::MoveTo(origin.idx, origin.saved_col)).unwrap_or(());
+            self.rect.scroll = scroll;
+        }
+    }
+
+    fn submit_prompt(&mut self, prompt: Prompt) {
+        let result = match prompt.kind {
+            PromptKind::SplitSelection => match Regex::new(&prompt.input) {
+                Ok(regex) => self.buf.apply(BufferAction::SplitSelections(regex)),
+                Err(_) => Err("Invalid regex"),
+            },
+            PromptKind::Search(direction) => {
+                add_to_history(&mut self.search_history, &prompt.input);
+                match (prompt.input.is_empty(), self.search.as_mut()) {
+                    // An empty pattern repeats the last search
+                    (true, Some(search)) => {
+                        search.direction = direction;
+                        self.search_next(false);
+                        Ok(())
                     }
-                    None => (),
+                    (true, None) => Err("No previous search pattern"),
+                    (false, _) => match Search::new(&prompt.input, direction) {
+                        Ok(search) => {
+                            self.start_search(search);
+                            Ok(())
+                        }
+                        Err(_) => Err("Invalid regex"),
+                    },
                 }
             }
+            PromptKind::Command => {
+                add_to_history(&mut self.command_history, &prompt.input);
+                // Everything a command changes is undone at once
+                let undo_len = self.buf.undo.len();
+                if let Err(e) = ExCommand::parse(&prompt.input).and_then(|command| self.execute(command)) {
+                    self.report(Error::Command(e));
+                }
+                self.buf.join_undo(undo_len);
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            self.report(Error::Action(e));
+        }
+    }
+
+    pub fn execute(&mut self, command: ExCommand) -> std::result::Result<(), String> {
+        match command {
+            ExCommand::NoHighlight => self.hlsearch = false,
+            ExCommand::Substitute { range, pattern, replacement, flags } => {
+                match self.substitute(range, &pattern, replacement, flags)? {
+                    Some(summary) => self.report_substitution(summary),
+                    None => {
+                        let pattern = self.search.as_ref().map_or(pattern, |search| search.pattern.clone());
+                        return Err(format!("Pattern not found: {}", pattern));
+                    }
+                }
+            }
+            ExCommand::Delete { range } => {
+                let (start, end) = range.rows(&self.buf)?;
+                let lines = self.line_bounds(start, end);
+                self.buf.apply(BufferAction::Delete(Selection::Bounds(lines.start, lines.end)))?;
+                let row = usize::min(*start, *self.buf.last_row()).into();
+                self.buf.apply(BufferAction::MoveTo(self.buf.row_to_char(row), 0.into()))?;
+            }
+            ExCommand::Move { range, dest } => {
+                let (start, end) = range.rows(&self.buf)?;
+                let dest = dest.line(&self.buf)?;
+                if *start < dest && dest <= *end {
+                    return Err(String::from("Cannot move a range of lines into itself"));
+                }
+                let lines = self.line_bounds(start, end);
+                let mut text = self.buf.slice(lines).to_string();
+                if !text.ends_with('\n') {
+                    text = format!("{}\n", text.strip_prefix('\n').unwrap_or(&text));
+                }
+                // Lines moved up stay below the destination, so it is found before deleting them
+                let dest = if dest > *end { dest - (*end - *start + 1) } else { dest };
+                self.buf.apply(BufferAction::Delete(Selection::Bounds(lines.start, lines.end)))?;
+                let at_end = dest > *self.buf.last_row();
+                let idx = if at_end { self.buf.text.len_chars().into() } else { self.buf.row_to_char(dest.into()) };
+                let len = self.buf.text.len_chars();
+                // The last line has no line break to insert the lines after
+                if at_end && len > 0 && self.buf.text.char(len - 1) != '\n' {
+                    text = format!("\n{}", text.strip_suffix('\n').unwrap_or(&text));
+                }
+                self.buf.apply(BufferAction::InsertAt(idx, text))?;
+                let row = usize::min(dest + *end - *start, *self.buf.last_row()).into();
+                self.buf.apply(BufferAction::MoveTo(self.buf.row_to_char(row), 0.into()))?;
+            }
+            ExCommand::Normal { range, keys } => {
+                let (start, end) = range.rows(&self.buf)?;
+                // The lines marked by an enclosing `:global` are put aside while the lines of the range are marked
+                let outer = std::mem::take(&mut self.buf.line_marks);
+                self.buf.mark_lines((*start..=*end).map(BufRow::from));
+                let result = self.type_keys_on_marked_lines(&keys);
+                self.buf.mark_lines(std::iter::empty());
+                self.buf.line_marks = outer;
+                result?;
+            }
+            ExCommand::Grep { pattern } => {
+                let regex = self.use_pattern(&pattern)?.regex.clone();
+                self.message = Some(Message::info(format!("Searching for {}...", pattern)));
+                self.editor_action = Some(EditorAction::Grep(regex, pattern));
+            }
+            ExCommand::ProjectReplace { pattern, replacement, flags } => {
+                let regex = self.substitute_regex(&pattern, flags)?;
+                self.editor_action = Some(EditorAction::PreviewReplace { regex, replacement, global: flags.global });
+            }
+            ExCommand::Write => match self.kind {
+                WindowKind::File if self.buf.readonly => return Err(String::from("The buffer is read-only")),
+                WindowKind::File => {
+                    self.buf.write().map_err(|e| format!("Could not write {}: {}", self.buf.path.display(), e))?;
+                    self.report(Message::info(format!("\"{}\" {}L written", self.buf.path.display(), *self.buf.last_row() + 1)));
+                }
+                WindowKind::Preview => self.editor_action = Some(EditorAction::ApplyPreview(self.buf.text.to_string())),
+                WindowKind::Quickfix => return Err(String::from("The quickfix list can't be written")),
+            },
+            ExCommand::WriteAll => self.editor_action = Some(EditorAction::WriteAll),
+            ExCommand::Close => self.editor_action = Some(EditorAction::CloseWindow),
+            ExCommand::Marks => self.editor_action = Some(EditorAction::ListMarks),
+            ExCommand::Messages => self.editor_action = Some(EditorAction::ListMessages),
+            ExCommand::Log => self.editor_action = Some(EditorAction::OpenLog),
+            ExCommand::Pick(kind) => self.editor_action = Some(EditorAction::OpenPicker(kind)),
+            ExCommand::QuickfixOpen => self.editor_action = Some(EditorAction::OpenQuickfix),
+            ExCommand::QuickfixClose => self.editor_action = Some(EditorAction::CloseQuickfix),
+            ExCommand::QuickfixNext { reverse } => self.editor_action = Some(EditorAction::QuickfixNext { reverse }),
+            ExCommand::QuickfixGoto(nr) => {
+                self.editor_action = Some(EditorAction::QuickfixGoto(nr.map(|nr| nr.saturating_sub(1))))
+            }
+            ExCommand::Global { range, pattern, invert, command } => {
+                let (start, end) = range.rows(&self.buf)?;
+                let regex = self.use_pattern(&pattern)?.regex.clone();
+                let buf = &self.buf;
+                let rows: Vec<BufRow> = (*start..=*end)
+                    .map(BufRow::from)
+                    .filter(|&row| regex.is_match(&buf.line_str(row)) != invert)
+                    .collect();
+                if rows.is_empty() {
+                    return Err(format!("Pattern not found: {}", pattern));
+                }
+                if let ExCommand::Substitute { flags, .. } = &*command {
+                    if flags.confirm {
+                        return Err(String::from("Cannot confirm substitutions inside :global"));
+                    }
+                }
+                self.buf.mark_lines(rows.into_iter());
+                // The substitutions on all lines are reported at once
+                let mut substituted = Summary::default();
+                while let Some(idx) = self.buf.next_line_mark() {
+                    self.buf.apply(BufferAction::MoveTo(idx, 0.into()))?;
+                    match (*command).clone() {
+                        // Lines without a match are skipped silently
+                        ExCommand::Substitute { range, pattern, replacement, flags } => {
+                            if let Some(summary) = self.substitute(range, &pattern, replacement, flags)? {
+                                substituted.add(summary);
+                            }
+                        }
+                        command => self.execute(command)?,
+                    }
+                }
+                self.report_substitution(substituted);
+            }
         }
-        self.renderer.flush()?;
         Ok(())
     }
+
+    /// Replaces the matches of a pattern in the lines of a range, or starts asking to confirm each one. Returns what
+    /// was replaced, which is nothing yet when confirming, or `None` if the pattern wasn't found.
+    fn substitute(
+        &mut self,
+        range: LineRange,
+        pattern: &str,
+        replacement: String,
+        flags: Flags,
+    ) -> std::result::Result<Option<Summary>, String> {
+        let rows = range.rows(&self.buf)?;
+        let regex = self.substitute_regex(pattern, flags)?;
+        let mut substitution = Substitution::new(regex, replacement, flags.global, rows, &self.buf);
+        match substitution.next_match(&self.buf) {
+            None => Ok(None),
+            Some(m) if flags.confirm => {
+                self.confirm_substitution(substitution, m);
+                Ok(Some(Summary::default()))
+            }
+            Some(_) => {
+                substitution.replace_all(&mut self.buf)?;
+                Ok(Some(substitution.finish(&mut self.buf)))
+            }
+        }
+    }
+
+    /// Reports how many matches were replaced, unless none were
+    fn report_substitution(&mut self, summary: Summary) {
+        if let Some(message) = summary.message() {
+            self.report(Message::info(message));
+        }
+    }
+
+    /// Returns the regex of a substitution, which is the last search if `pattern` is empty
+    fn substitute_regex(&mut self, pattern: &str, flags: Flags) -> std::result::Result<Regex, String> {
+        let search = self.use_pattern(pattern)?;
+        match flags.ignore_case {
+            Some(true) => Regex::new(&format!("(?i){}", search.pattern)),
+            Some(false) => Regex::new(&format!("(?-i){}", search.pattern)),
+            None => Ok(search.regex.clone()),
+        }
+        .map_err(|_| String::from("Invalid regex"))
+    }
+
+    /// Returns the last search if `pattern` is empty, otherwise makes `pattern` the last search
+    fn use_pattern(&mut self, pattern: &str) -> std::result::Result<&Search, String> {
+        if !pattern.is_empty() {
+            let search = Search::new(pattern, Direction::Forward).map_err(|_| String::from("Invalid regex"))?;
+            add_to_history(&mut self.search_history, pattern);
+            self.search = Some(search);
+        }
+        self.hlsearch = true;
+        self.search.as_ref().ok_or_else(|| String::from("No previous regular expression"))
+    }
+
+    /// Returns the range of the lines from `start` to `end`. When the last line has no line break, the
+    /// line break before the lines is included instead, so that removing them leaves no empty line behind.
+    fn line_bounds(&self, start: BufRow, end: BufRow) -> BufRange {
+        let first = self.buf.row_to_char(start);
+        let last = self.buf.row_to_char(end + 1.into());
+        if *first > 0 && *last > *first && self.buf.text.char(*last - 1) != '\n' {
+            BufRange::new(first - 1.into(), last)
+        } else {
+            BufRange::new(first, last)
+        }
+    }
+
+    /// Types keys at the start of every line marked with `Buffer::mark_lines`
+    fn type_keys_on_marked_lines(&mut self, keys: &str) -> std::result::Result<(), String> {
+        while let Some(idx) = self.buf.next_line_mark() {
+            self.buf.apply(BufferAction::MoveTo(idx, 0.into()))?;
+            self.type_keys(keys).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Types keys in normal mode as if they were pressed, and returns to normal mode afterwards
+    fn type_keys(&mut self, keys: &str) -> Result<()> {
+        for c in keys.chars() {
+            self.feed_key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE))?;
+        }
+        if self.prompt.take().is_some() {
+            self.end_incsearch();
+        }
+        self.buf.apply(BufferAction::SetMode(EditMode::Normal)).unwrap_or(());
+        Ok(())
+    }
+
+    /// Shows a match of a substitution and asks whether it should be replaced
+    fn confirm_substitution(&mut self, substitution: Substitution, m: Match) {
+        let col = self.buf.display_col(m.range.start);
+        self.buf.apply(BufferAction::MoveTo(m.range.start, col)).unwrap_or(());
+        self.message = Some(Message::info(format!("replace with {} (y/n/a/q/l)?", m.replacement)));
+        self.confirming = Some((substitution, m));
+    }
+
+    /// Handles the answer to the question whether a match should be replaced
+    fn handle_confirm_key(&mut self, key_event: KeyEvent) {
+        let (mut substitution, m) = match self.confirming.take() {
+            Some(confirming) => confirming,
+            None => return,
+        };
+        let result = match key_event.code {
+            KeyCode::Char('y') => substitution.replace(&mut self.buf, m),
+            KeyCode::Char('n') => {
+                substitution.skip(&self.buf, m);
+                Ok(())
+            }
+            KeyCode::Char('a') => substitution
+                .replace(&mut self.buf, m)
+                .and_then(|_| substitution.replace_all(&mut self.buf)),
+            KeyCode::Char('l') => {
+                let result = substitution.replace(&mut self.buf, m);
+                let summary = substitution.finish(&mut self.buf);
+                self.report_substitution(summary);
+                return result.unwrap_or_else(|e| self.report(Error::Action(e)));
+            }
+            KeyCode::Char('q') | KeyCode::Esc => {
+                let summary = substitution.finish(&mut self.buf);
+                self.report_substitution(summary);
+                return;
+            }
+            _ => {
+                self.confirm_substitution(substitution, m);
+                return;
+            }
+        };
+        if let Err(e) = result {
+            self.report(Error::Action(e));
+        }
+        match substitution.next_match(&self.buf) {
+            Some(m) => self.confirm_substitution(substitution, m),
+            None => {
+                let summary = substitution.finish(&mut self.buf);
+                self.report_substitution(summary);
+            }
+        }
+    }
+
+    /// Remembers a position jumped away from, for the editor to add to the jump list
+    fn add_pending_jump(&mut self, origin: BufCharIdx) {
+        if self.kind == WindowKind::File {
+            let anchor = self.buf.anchors.add(Anchor::point(origin, Gravity::Right));
+            self.pending_jumps.push(anchor);
+        }
+    }
+
+    /// Makes `search` the last search and jumps to its first match
+    pub fn start_search(&mut self, search: Search) {
+        add_to_history(&mut self.search_history, &search.pattern);
+        self.search = Some(search);
+        self.search_next(false);
+    }
+
+    /// Jumps to the next match of the last search
+    pub fn search_next(&mut self, reverse: bool) {
+        let search = match &self.search {
+            Some(search) => search,
+            None => return self.report(Error::Action("No previous search pattern")),
+        };
+        self.hlsearch = true;
+        let direction = if reverse { search.direction.reversed() } else { search.direction };
+        match search.find(&self.buf, direction) {
+            Some((found, wrapped)) => {
+                // The pattern is echoed without adding it to the history of `:messages`
+                match (wrapped, direction) {
+                    (false, Direction::Forward) => self.message = Some(Message::info(format!("/{}", search.pattern))),
+                    (false, Direction::Backward) => self.message = Some(Message::info(format!("?{}", search.pattern))),
+                    (true, Direction::Forward) => self.report(Message::warning("search hit BOTTOM, continuing at TOP")),
+                    (true, Direction::Backward) => self.report(Message::warning("search hit TOP, continuing at BOTTOM")),
+                }
+                let col = self.buf.display_col(found.start);
+                self.add_pending_jump(self.buf.idx);
+                self.buf.apply(BufferAction::MoveTo(found.start, col)).unwrap_or(());
+            }
+            None => self.report(Error::Command(format!("Pattern not found: {}", search.pattern))),
+        }
+    }
 }
@@ -0,0 +1,226 @@
+use crate::{
+    buffer::Buffer,
+    fold, grapheme,
+    indent::indentation,
+    rect::Rect,
+    utils::{BufCharIdx, BufRow, DisplayCol, TermPos},
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A part of a line that is shown on a single row of the screen
+#[derive(Clone, Copy)]
+pub struct Segment {
+    /// The first display column of the line that is shown on the row
+    pub left: usize,
+    /// The display column after the last one that fits on the row
+    pub right: usize,
+    /// The column of the rect that `left` is drawn at, which is after the indentation and `showbreak` of rows that
+    /// continue a line
+    pub x: usize,
+}
+
+/// Splits a row of the buffer into the segments that fit within `width` columns. Continued rows keep the
+/// indentation of the line if `breakindent` is set, and lines break after whitespace if `linebreak` is set.
+pub fn segments(buf: &Buffer, row: BufRow, width: usize) -> Vec<Segment> {
+    let filetype = &buf.filetype;
+    let line = buf.line_str(row);
+    let mut cells = Vec::new();
+    let mut col = 0;
+    for g in line.graphemes(true) {
+        let w = grapheme::width(g, col, filetype.tabstop);
+        cells.push((col, w, g.chars().all(char::is_whitespace)));
+        col += w;
+    }
+    // The prefix of continued rows is left out when it takes up more than half of the row
+    let showbreak = grapheme::text_width(&filetype.showbreak, 0, filetype.tabstop);
+    let indent = match filetype.breakindent {
+        true => grapheme::text_width(indentation(&line), 0, filetype.tabstop),
+        false => 0,
+    };
+    let prefix = [showbreak + indent, showbreak, 0].iter().copied().find(|prefix| *prefix <= width / 2).unwrap_or(0);
+    let available = |x: usize| width.saturating_sub(x).max(1);
+
+    let mut segments = Vec::new();
+    let (mut start, mut left, mut x) = (0, 0, 0);
+    let mut last_break = None;
+    let mut i = 0;
+    while i < cells.len() {
+        let (col, w, whitespace) = cells[i];
+        if col + w - left > available(x) && i > start {
+            let at = match last_break {
+                Some(at) if filetype.linebreak && at > start => at,
+                _ => i,
+            };
+            let right = cells[at].0;
+            segments.push(Segment { left, right, x });
+            start = at;
+            i = at;
+            left = right;
+            x = prefix;
+            last_break = None;
+            continue;
+        }
+        if whitespace {
+            last_break = Some(i + 1);
+        }
+        i += 1;
+    }
+    segments.push(Segment { left, right: left + available(x), x });
+    segments
+}
+
+/// Returns the index of the segment that shows a display column
+fn segment_at(segments: &[Segment], col: usize) -> usize {
+    segments.iter().rposition(|segment| segment.left <= col).unwrap_or(0)
+}
+
+/// Returns the row of the screen the cursor is on within its line
+pub fn cursor_row(buf: &Buffer, width: usize) -> usize {
+    segment_at(&segments(buf, buf.row(), width), *buf.display_col(buf.idx))
+}
+
+/// Returns the character `count` rows of the screen above or below the cursor, at the same column of the screen. A
+/// closed fold takes up a single row.
+pub fn vertical_dest(buf: &Buffer, width: usize, up: bool, count: usize) -> BufCharIdx {
+    let closed = fold::closed(buf);
+    let rows = |row: usize| {
+        let mut segs = segments(buf, row.into(), width);
+        if closed.containing(row).is_some() {
+            segs.truncate(1);
+        }
+        segs
+    };
+    let mut row = closed.first(*buf.row());
+    let mut segs = rows(row);
+    let col = *buf.display_col(buf.idx);
+    let mut i = segment_at(&segs, col);
+    let x = segs[i].x + col - segs[i].left;
+    for _ in 0..count {
+        if up && i > 0 {
+            i -= 1;
+        } else if up && row > 0 {
+            row = closed.first(row - 1);
+            segs = rows(row);
+            i = segs.len() - 1;
+        } else if !up && i + 1 < segs.len() {
+            i += 1;
+        } else if !up && closed.last(row) + 1 < buf.text.len_lines() {
+            row = closed.last(row) + 1;
+            segs = rows(row);
+            i = 0;
+        } else {
+            break;
+        }
+    }
+    let segment = segs[i];
+    let mut target = segment.left + x.saturating_sub(segment.x);
+    if i + 1 < segs.len() {
+        target = target.min(segment.right - 1);
+    }
+    buf.row_to_char(row.into()) + (*buf.display_to_col(row.into(), target.into())).into()
+}
+
+/// Returns the first character of the row of the screen the cursor is on, or the last one if `end` is set
+pub fn row_end(buf: &Buffer, width: usize, end: bool) -> BufCharIdx {
+    let segs = segments(buf, buf.row(), width);
+    let i = segment_at(&segs, *buf.display_col(buf.idx));
+    let target = match end {
+        true if i + 1 == segs.len() => return buf.row_to_char(buf.row() + 1.into()) - 1.into(),
+        true => segs[i].right - 1,
+        false => segs[i].left,
+    };
+    buf.row_to_char(buf.row()) + (*buf.display_to_col(buf.row(), target.into())).into()
+}
+
+/// A line of the buffer as it is drawn, or the rows of a closed fold, which are drawn as a single line
+pub struct Line {
+    pub first: BufRow,
+    pub last: BufRow,
+    /// The rows of the rect the line is drawn on, with the part of the line drawn on each
+    pub segments: Vec<(usize, Segment)>,
+}
+
+impl Line {
+    pub fn is_folded(&self) -> bool {
+        self.last > self.first
+    }
+}
+
+/// The lines of the buffer that are visible in a rect, and the rows of the rect their segments are drawn on
+pub struct Layout {
+    first: BufRow,
+    offset: TermPos,
+    lines: Vec<Line>,
+}
+
+impl Layout {
+    /// Lays out the lines from the top of the rect. Without wrapping, every line is a single segment that shows the
+    /// columns the rect is scrolled to, a closed fold is always a single segment that starts at the left edge.
+    pub fn new(buf: &Buffer, rect: &Rect, wrap: bool) -> Self {
+        let (width, height) = (*rect.width as usize, *rect.height as usize);
+        let closed = fold::closed(buf);
+        let mut lines = Vec::new();
+        let mut y = 0;
+        let mut row = *rect.top();
+        while y < height && row < buf.text.len_lines() {
+            let (first, last) = closed.containing(row).unwrap_or((row, row));
+            let segs = match (wrap, last > first) {
+                (_, true) => vec![Segment { left: 0, right: width, x: 0 }],
+                (true, false) => segments(buf, row.into(), width),
+                (false, false) => vec![Segment { left: *rect.left(), right: *rect.right(), x: 0 }],
+            };
+            let segments: Vec<_> = (y..height).zip(segs).collect();
+            y += segments.len();
+            lines.push(Line { first: first.into(), last: last.into(), segments });
+            row = last + 1;
+        }
+        Self { first: rect.top(), offset: rect.offset, lines }
+    }
+
+    /// Returns the row after the last one that is visible
+    pub fn end(&self) -> BufRow {
+        self.lines.last().map_or(self.first, |line| line.last + 1.into())
+    }
+
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    /// Returns the visible line a row is drawn as part of
+    pub fn line(&self, row: BufRow) -> Option<&Line> {
+        let i = self.lines.partition_point(|line| *line.last < *row);
+        self.lines.get(i).filter(|line| *line.first <= *row)
+    }
+
+    /// Returns the visible segments of a row, with the row of the rect they are drawn on. The rows of closed folds
+    /// have none, as their text isn't drawn.
+    pub fn segments(&self, row: BufRow) -> &[(usize, Segment)] {
+        self.line(row).filter(|line| !line.is_folded()).map_or(&[], |line| &line.segments[..])
+    }
+
+    /// Returns the number of rows of the rect that are taken up by the buffer
+    pub fn height(&self) -> usize {
+        self.lines.iter().map(|line| line.segments.len()).sum()
+    }
+
+    /// Returns the position on the terminal of a column of a segment drawn on row `y` of the rect
+    pub fn terminal_pos(&self, y: usize, segment: &Segment, col: usize) -> TermPos {
+        let x = segment.x + col.saturating_sub(segment.left);
+        TermPos::new(*self.offset.x + x as u16, *self.offset.y + y as u16)
+    }
+
+    /// Returns the position on the terminal of a display column of a row, if the row is visible. The rows of a closed
+    /// fold are at the start of the line drawn for it.
+    pub fn position(&self, row: BufRow, col: DisplayCol) -> Option<TermPos> {
+        let line = self.line(row)?;
+        if line.is_folded() {
+            let (y, segment) = line.segments.first()?;
+            return Some(self.terminal_pos(*y, segment, segment.left));
+        }
+        let segs = &line.segments;
+        let (y, segment) = segs.iter().rev().find(|(_, segment)| segment.left <= *col).or_else(|| segs.first())?;
+        // The line break after a full row is drawn on the last column
+        let col = usize::min(*col, segment.right - 1);
+        Some(self.terminal_pos(*y, segment, col))
+    }
+}